@@ -0,0 +1,199 @@
+//! Ardour generic MIDI binding maps.
+//!
+//! Writes a `.map` file for Ardour's Generic MIDI control surface, binding each assigned pad's
+//! note and knob's CC, on the program's channel, to a transport function, a menu action or a
+//! track's control. Copied to Ardour's `midi_maps` directory it shows up in the surface's
+//! settings.
+//!
+//! Targets for pads are transport functions (`transport-roll`...), menu actions written
+//! `Group/action` (`Transport/Record`), or `mute:N`, `solo:N` and `rec:N` for track N. Knobs take
+//! `gain:N`, `pan:N`, `trim:N` or `plugin:N:P:Q`, parameter Q of plugin P on track N. Either also
+//! takes a binding URI as Ardour writes them, starting with `/`.
+
+use crate::mapping::{escape, Assignments, Choice, Control, Exporter};
+use crate::program_file::Names;
+use crate::Program;
+
+pub static EXPORTER: Exporter = Exporter {
+    id: "ardour",
+    name: "Ardour Binding Map",
+    file_type: "Ardour MIDI binding map",
+    extension: "map",
+    pad_choices,
+    knob_choices,
+    export,
+};
+
+/// Tracks the dialog offers targets for
+const TRACKS: usize = 4;
+
+/// Functions Ardour binds directly, with labels
+static FUNCTIONS: &[(&str, &str)] = &[
+    ("transport-roll", "Play"),
+    ("transport-stop", "Stop"),
+    ("transport-zero", "Go to zero"),
+    ("transport-start", "Go to start"),
+    ("transport-end", "Go to end"),
+    ("loop-toggle", "Toggle loop"),
+    ("rec-enable", "Record enable"),
+    ("rec-disable", "Record disable"),
+];
+
+/// Menu actions offered in the dialog
+static ACTIONS: &[(&str, &str)] = &[
+    ("Transport/ToggleRoll", "Play or stop"),
+    ("Transport/Record", "Record"),
+    ("Transport/Rewind", "Rewind"),
+    ("Transport/Forward", "Fast forward"),
+    ("Transport/ToggleClick", "Toggle metronome"),
+    ("Common/add-location-from-playhead", "Add marker"),
+    ("Editor/undo", "Undo"),
+    ("Editor/redo", "Redo"),
+    ("Common/Save", "Save session"),
+];
+
+/// Track controls for pads and knobs, with the URI they're bound to
+static PAD_TRACK_CONTROLS: &[(&str, &str, &str)] = &[
+    ("mute", "mute", "/route/mute"),
+    ("solo", "solo", "/route/solo"),
+    ("rec", "record arm", "/route/recenable"),
+];
+
+static KNOB_TRACK_CONTROLS: &[(&str, &str, &str)] = &[
+    ("gain", "gain", "/route/gain"),
+    ("pan", "pan", "/route/pandirection"),
+    ("trim", "trim", "/route/trim"),
+];
+
+fn pad_choices() -> Vec<Choice> {
+    let mut choices = FUNCTIONS.iter().chain(ACTIONS.iter())
+        .map(|(id, label)| Choice { id: id.to_string(), label: label.to_string() })
+        .collect::<Vec<_>>();
+    choices.extend(track_choices(PAD_TRACK_CONTROLS));
+    choices
+}
+
+fn knob_choices() -> Vec<Choice> {
+    let mut choices = track_choices(KNOB_TRACK_CONTROLS);
+    choices.extend((1..=TRACKS).map(|track| Choice {
+        id: format!("plugin:{}:1:1", track),
+        label: format!("Track {} first plugin parameter", track),
+    }));
+    choices
+}
+
+fn track_choices(controls: &[(&str, &str, &str)]) -> Vec<Choice> {
+    (1..=TRACKS).flat_map(|track| controls.iter().map(move |(id, label, _)| Choice {
+        id: format!("{}:{}", id, track),
+        label: format!("Track {} {}", track, label),
+    })).collect()
+}
+
+/// Attribute and value binding `target`
+fn resolve(control: Control, target: &str) -> Result<(&'static str, String), String> {
+    if target.starts_with('/') {
+        return Ok(("uri", target.to_string()));
+    }
+    let parts = target.split(':').collect::<Vec<_>>();
+    let numbers = parts[1..].iter().map(|n| n.parse::<u32>()).collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid Ardour target \"{}\"", target))?;
+    let track_controls = match control {
+        Control::Pad => PAD_TRACK_CONTROLS,
+        Control::Knob => KNOB_TRACK_CONTROLS,
+    };
+    match (control, parts[0], numbers.as_slice()) {
+        (Control::Pad, function, []) if FUNCTIONS.iter().any(|(id, _)| *id == function) => {
+            Ok(("function", function.to_string()))
+        },
+        (Control::Pad, action, []) if action.contains('/') => Ok(("action", action.to_string())),
+        (Control::Knob, "plugin", [track, plugin, parameter]) => {
+            Ok(("uri", format!("/route/plugin/parameter B{} {} {}", track, plugin, parameter)))
+        },
+        (_, id, [track]) => match track_controls.iter().find(|(c, _, _)| *c == id) {
+            Some((_, _, uri)) => Ok(("uri", format!("{} B{}", uri, track))),
+            None => Err(unknown(control, target)),
+        },
+        _ => Err(unknown(control, target)),
+    }
+}
+
+fn unknown(control: Control, target: &str) -> String {
+    let (kind, expected) = match control {
+        Control::Pad => ("pad", "a function, Group/action, mute:N, solo:N, rec:N or a URI"),
+        Control::Knob => ("knob", "gain:N, pan:N, trim:N, plugin:N:P:Q or a URI"),
+    };
+    format!("unknown Ardour target \"{}\" for a {}, expected {}", target, kind, expected)
+}
+
+/// The binding map for program `id`, named after the program
+pub fn export(id: u8, program: &Program, names: &Names, assignments: &Assignments) -> Result<String, String> {
+    let channel = (program.channel & 0x0F) + 1;
+    let mut bindings = String::new();
+    for (control, i, target) in assignments.assigned() {
+        let (attribute, value) = resolve(control, target)?;
+        let message = match control {
+            Control::Pad => format!("note=\"{}\"", program.pads[i].note),
+            Control::Knob => format!("ctl=\"{}\"", program.knobs[i].control_change),
+        };
+        bindings.push_str(&format!("  <!-- {} -->\n  <Binding channel=\"{}\" {} {}=\"{}\"/>\n",
+            escape(&control.title(names, i)).replace("--", "- -"), channel, message, attribute, escape(&value)));
+    }
+    let name = if names.program.is_empty() { format!("LPD8 PROG {}", id) } else { format!("LPD8 {}", names.program) };
+    Ok(format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<ArdourMIDIBindings version="1.0.0" name="{}">
+  <DeviceInfo bank-size="0"/>
+{}</ArdourMIDIBindings>
+"#, escape(&name), bindings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_structure() {
+        let mut program = Program { channel: 2, ..Program::default() };
+        program.pads[0].note = 36;
+        program.pads[1].note = 37;
+        program.pads[2].note = 38;
+        program.knobs[0].control_change = 20;
+        program.knobs[1].control_change = 21;
+        let mut names = Names { program: "Mix & Rec".to_string(), ..Names::default() };
+        names.pads[0] = "Play --".to_string();
+        let assignments = Assignments::parse(&[
+            "PAD1=transport-roll", "PAD2=mute:2", "PAD3=Transport/Record", "K1=plugin:1:2:3", "K2=/route/gain B4",
+        ]).unwrap();
+
+        let xml = export(1, &program, &names, &assignments).unwrap();
+        let document = roxmltree::Document::parse(&xml).expect("well formed XML");
+        let root = document.root_element();
+        assert_eq!(root.tag_name().name(), "ArdourMIDIBindings");
+        assert_eq!(root.attribute("name"), Some("LPD8 Mix & Rec"));
+
+        let elements = root.children().filter(|n| n.is_element()).collect::<Vec<_>>();
+        assert_eq!(elements[0].tag_name().name(), "DeviceInfo");
+        let bindings = &elements[1..];
+        assert_eq!(bindings.len(), 5);
+        for binding in bindings {
+            assert_eq!(binding.tag_name().name(), "Binding");
+            assert_eq!(binding.attribute("channel"), Some("3"));
+        }
+        assert_eq!(bindings[0].attribute("note"), Some("36"));
+        assert_eq!(bindings[0].attribute("function"), Some("transport-roll"));
+        assert_eq!(bindings[1].attribute("note"), Some("37"));
+        assert_eq!(bindings[1].attribute("uri"), Some("/route/mute B2"));
+        assert_eq!(bindings[2].attribute("action"), Some("Transport/Record"));
+        assert_eq!(bindings[3].attribute("ctl"), Some("20"));
+        assert_eq!(bindings[3].attribute("uri"), Some("/route/plugin/parameter B1 2 3"));
+        assert_eq!(bindings[4].attribute("ctl"), Some("21"));
+        assert_eq!(bindings[4].attribute("uri"), Some("/route/gain B4"));
+    }
+
+    #[test]
+    fn unknown_target() {
+        for arg in ["K1=transport-roll", "K1=mute:1", "K1=plugin:1", "K1=gain:x", "PAD1=gain:1", "PAD1=play"].iter() {
+            let assignments = Assignments::parse(&[*arg]).unwrap();
+            assert!(export(1, &Program::default(), &Names::default(), &assignments).is_err(), "{}", arg);
+        }
+    }
+}
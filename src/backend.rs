@@ -0,0 +1,286 @@
+//! MIDI backends picked at run time.
+//!
+//! midir chooses its backend when it's built, so it's kept for the platform's own MIDI system
+//! and JACK, when compiled in with the default `jack` feature, is reached through the jack crate.
+//! Builds without the feature report JACK unavailable and a saved choice of it falls back to ALSA.
+//! Ports are found and connected by name, the rest of the program doesn't see which backend
+//! is in use. With JACK, ports are shown without the client number a2jmidid puts in their
+//! names, and the emulator's virtual ports need ALSA.
+
+use std::fmt;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Backend {
+    /// midir's platform backend, ALSA on Linux
+    Alsa,
+    Jack,
+}
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+static ALL: &[Backend] = &[Backend::Alsa, Backend::Jack];
+
+impl Backend {
+    /// Backends this build supports
+    pub fn available() -> Vec<Backend> {
+        ALL.iter().cloned().filter(|b| b.check().is_ok()).collect()
+    }
+
+    /// Any backend by id, whether this build supports it or not
+    pub fn from_name(name: &str) -> Option<Backend> {
+        ALL.iter().cloned().find(|b| b.id() == name)
+    }
+
+    /// Why the backend can't be used in this build
+    pub fn check(self) -> Result<(), String> {
+        match self {
+            Backend::Jack if !cfg!(feature = "jack") => {
+                Err("JACK isn't available, this build doesn't have the `jack` feature".to_string())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Backend::Alsa => "alsa",
+            Backend::Jack => "jack",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Backend::Alsa if cfg!(target_os = "linux") => "ALSA",
+            Backend::Alsa => "System MIDI",
+            Backend::Jack => "JACK",
+        }
+    }
+
+    pub fn current() -> Backend {
+        match CURRENT.load(Ordering::Relaxed) {
+            1 => Backend::Jack,
+            _ => Backend::Alsa,
+        }
+    }
+
+    /// Switches the backend new connections and port lists use.
+    pub fn set_current(backend: Backend) {
+        CURRENT.store(backend as usize, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+/// Open input, closed when dropped
+pub enum InputConnection {
+    Alsa(MidiInputConnection<()>),
+    #[cfg(feature = "jack")]
+    Jack(Box<dyn std::any::Any + Send>),
+}
+
+/// Open output, closed when dropped
+pub enum OutputConnection {
+    Alsa(MidiOutputConnection),
+    #[cfg(feature = "jack")]
+    Jack(jack_backend::Output),
+}
+
+impl OutputConnection {
+    pub fn send(&mut self, message: &[u8]) -> Result<(), String> {
+        match self {
+            OutputConnection::Alsa(connection) => connection.send(message).map_err(|e| e.to_string()),
+            #[cfg(feature = "jack")]
+            OutputConnection::Jack(output) => output.send(message),
+        }
+    }
+}
+
+/// Port name without the numbers the backend adds, which change between plug-ins
+pub fn display_name(port_name: &str) -> String {
+    match port_name.rfind(' ') {
+        // ALSA's client:port
+        Some(i) if port_name[i + 1..].contains(':') && port_name[i + 1..].chars().all(|c| c.is_ascii_digit() || c == ':') =>
+            port_name[..i].to_string(),
+        // a2jmidid's [client] in JACK port names
+        _ => match (port_name.find(" ["), port_name.find("] ")) {
+            (Some(start), Some(end)) if start < end && port_name[start + 2..end].chars().all(|c| c.is_ascii_digit()) =>
+                format!("{}{}", &port_name[..start], &port_name[end + 1..]),
+            _ => port_name.to_string(),
+        },
+    }
+}
+
+/// Names of the ports MIDI can be read from
+pub fn input_names(client_name: &str) -> Vec<String> {
+    match Backend::current() {
+        Backend::Alsa => MidiInput::new(client_name)
+            .map(|midi_in| (0..midi_in.port_count()).map(|i| midi_in.port_name(i).unwrap_or_default()).collect())
+            .unwrap_or_default(),
+        #[cfg(feature = "jack")]
+        Backend::Jack => jack_backend::port_names(client_name, jack::PortFlags::IS_OUTPUT),
+        #[cfg(not(feature = "jack"))]
+        Backend::Jack => Vec::new(),
+    }
+}
+
+/// Names of the ports MIDI can be sent to
+pub fn output_names(client_name: &str) -> Vec<String> {
+    match Backend::current() {
+        Backend::Alsa => MidiOutput::new(client_name)
+            .map(|midi_out| (0..midi_out.port_count()).map(|i| midi_out.port_name(i).unwrap_or_default()).collect())
+            .unwrap_or_default(),
+        #[cfg(feature = "jack")]
+        Backend::Jack => jack_backend::port_names(client_name, jack::PortFlags::IS_INPUT),
+        #[cfg(not(feature = "jack"))]
+        Backend::Jack => Vec::new(),
+    }
+}
+
+/// Calls `callback` with the timestamp in microseconds and the data of each message from `port_name`.
+pub fn connect_input<F>(client_name: &str, port_name: &str, mut callback: F) -> Result<InputConnection, String>
+    where F: FnMut(u64, &[u8]) + Send + 'static {
+    match Backend::current() {
+        Backend::Alsa => {
+            let midi_in = MidiInput::new(client_name).map_err(|e| e.to_string())?;
+            let port = (0..midi_in.port_count())
+                .find(|i| midi_in.port_name(*i).ok().as_ref().map(|n| n.as_str()) == Some(port_name))
+                .ok_or_else(|| format!("no input \"{}\"", port_name))?;
+            midi_in.connect(port, client_name, move |t, data, _| callback(t, data), ())
+                .map(InputConnection::Alsa)
+                .map_err(|e| e.to_string())
+        },
+        #[cfg(feature = "jack")]
+        Backend::Jack => jack_backend::connect_input(client_name, port_name, callback),
+        #[cfg(not(feature = "jack"))]
+        Backend::Jack => Err("built without JACK support".to_string()),
+    }
+}
+
+pub fn connect_output(client_name: &str, port_name: &str) -> Result<OutputConnection, String> {
+    match Backend::current() {
+        Backend::Alsa => {
+            let midi_out = MidiOutput::new(client_name).map_err(|e| e.to_string())?;
+            let port = (0..midi_out.port_count())
+                .find(|i| midi_out.port_name(*i).ok().as_ref().map(|n| n.as_str()) == Some(port_name))
+                .ok_or_else(|| format!("no output \"{}\"", port_name))?;
+            midi_out.connect(port, client_name)
+                .map(OutputConnection::Alsa)
+                .map_err(|e| e.to_string())
+        },
+        #[cfg(feature = "jack")]
+        Backend::Jack => jack_backend::connect_output(client_name, port_name).map(OutputConnection::Jack),
+        #[cfg(not(feature = "jack"))]
+        Backend::Jack => Err("built without JACK support".to_string()),
+    }
+}
+
+#[cfg(feature = "jack")]
+mod jack_backend {
+    use std::sync::mpsc::{channel, Sender};
+
+    use jack::{AsyncClient, Client, ClientOptions, ClosureProcessHandler, MidiIn, MidiOut, PortFlags, RawMidi};
+
+    use super::InputConnection;
+
+    static MIDI_TYPE: &str = "8 bit raw midi";
+
+    fn client(client_name: &str) -> Result<Client, String> {
+        // A colon would read as the client:port separator
+        Client::new(&client_name.replace(':', "_"), ClientOptions::NO_START_SERVER)
+            .map(|(client, _status)| client)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Full `client:port` names, leaving out this program's own clients
+    pub fn port_names(client_name: &str, flags: PortFlags) -> Vec<String> {
+        let client = match client(client_name) {
+            Ok(client) => client,
+            Err(_) => return Vec::new(),
+        };
+        client.ports(None, Some(MIDI_TYPE), flags).into_iter()
+            .filter(|name| !name.starts_with(crate::APP_NAME))
+            .collect()
+    }
+
+    pub fn connect_input<F>(client_name: &str, port_name: &str, mut callback: F) -> Result<InputConnection, String>
+        where F: FnMut(u64, &[u8]) + Send + 'static {
+        let client = client(client_name)?;
+        let port = client.register_port("in", MidiIn::default()).map_err(|e| e.to_string())?;
+        let own_name = port.name().map_err(|e| e.to_string())?;
+        let handler = ClosureProcessHandler::new(move |client: &Client, ps: &jack::ProcessScope| {
+            for event in port.iter(ps) {
+                let time = client.frames_to_time(ps.last_frame_time() + event.time);
+                callback(time, event.bytes);
+            }
+            jack::Control::Continue
+        });
+        let active = client.activate_async((), handler).map_err(|e| e.to_string())?;
+        active.as_client().connect_ports_by_name(port_name, &own_name).map_err(|e| e.to_string())?;
+        Ok(InputConnection::Jack(Box::new(active)))
+    }
+
+    /// Output queueing messages for the process callback
+    pub struct Output {
+        tx: Sender<Vec<u8>>,
+        _client: Box<dyn std::any::Any + Send>,
+    }
+
+    impl Output {
+        pub fn send(&mut self, message: &[u8]) -> Result<(), String> {
+            self.tx.send(message.to_vec()).map_err(|_| "JACK client stopped".to_string())
+        }
+    }
+
+    pub fn connect_output(client_name: &str, port_name: &str) -> Result<Output, String> {
+        let client = client(client_name)?;
+        let mut port = client.register_port("out", MidiOut::default()).map_err(|e| e.to_string())?;
+        let own_name = port.name().map_err(|e| e.to_string())?;
+        let (tx, rx) = channel::<Vec<u8>>();
+        let handler = ClosureProcessHandler::new(move |_client: &Client, ps: &jack::ProcessScope| {
+            let mut writer = port.writer(ps);
+            for message in rx.try_iter() {
+                let _ = writer.write(&RawMidi { time: 0, bytes: &message });
+            }
+            jack::Control::Continue
+        });
+        let active: AsyncClient<_, _> = client.activate_async((), handler).map_err(|e| e.to_string())?;
+        active.as_client().connect_ports_by_name(&own_name, port_name).map_err(|e| e.to_string())?;
+        Ok(Output {
+            tx,
+            _client: Box::new(active),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_names() {
+        assert_eq!(display_name("LPD8:LPD8 MIDI 1 20:0"), "LPD8:LPD8 MIDI 1");
+        assert_eq!(display_name("a2j:LPD8 [20] (capture): LPD8 MIDI 1"), "a2j:LPD8 (capture): LPD8 MIDI 1");
+        assert_eq!(display_name("system:midi_capture_1"), "system:midi_capture_1");
+        assert_eq!(display_name("LPD8 [mk2]"), "LPD8 [mk2]");
+        assert_eq!(display_name("Port 1"), "Port 1");
+    }
+
+    #[test]
+    fn check() {
+        assert!(Backend::Alsa.check().is_ok());
+        assert_eq!(Backend::Jack.check().is_ok(), cfg!(feature = "jack"));
+        assert_eq!(Backend::available().contains(&Backend::Jack), cfg!(feature = "jack"));
+        assert_eq!(Backend::from_name("jack"), Some(Backend::Jack));
+        assert_eq!(Backend::from_name("coremidi"), None);
+    }
+}
@@ -0,0 +1,576 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use std::time::Duration;
+
+use crate::backend::{self, Backend};
+use crate::program_file::{Names, ProgramFile};
+use crate::device_info::DeviceInfo;
+use crate::monitor::{Direction, Filter, Kind, Monitor, HISTORY_LEN};
+use crate::recorder::{self, Format, Recorder};
+use crate::settings::Settings;
+use crate::transport::{self, Reassembler};
+use crate::profile::{self, Profile, PROFILES};
+use crate::{ardour, emulator, library, logging, mapping, mixxx, osc, overlay, script, watcher, websocket, DeviceIDs, Program, APP_NAME, SYSEX_ALL};
+
+static USAGE: &str = "\
+Usage: lpd8_config [COMMAND] [--backend BACKEND] [--sysex-id ID] [--sysex-delay MS]
+                   [--verbose]... [--log-file FILE]
+
+Without a command the configuration window is opened.
+
+Commands:
+    devices
+        List connected devices with their nicknames.
+    info [--device DEVICE]
+        Show the manufacturer, model and firmware the device reports.
+    nickname NAME [--device DEVICE]
+        Give a device a nickname, an empty NAME removes it.
+    fetch SLOT FILE [--device DEVICE]
+        Save program SLOT (1-4) from the device to FILE.
+    push SLOT FILE [--device DEVICE]
+        Send the program in FILE to slot SLOT (1-4) of the device.
+    library [SEARCH]
+        List the presets in the library, or those matching SEARCH (words, #tag).
+    apply PRESET SLOT [--device DEVICE]
+        Send the library preset named PRESET to slot SLOT (1-4) of the device.
+    overlay OUTPUT FILE...
+        Draw the programs in FILE... at the LPD8's size, labelled, to OUTPUT (.svg or .pdf).
+    mixxx FILE OUTPUT [CONTROL=TARGET]...
+        Write a Mixxx mapping of the program in FILE to OUTPUT, each CONTROL (PAD1-8, K1-8)
+        bound to a TARGET like deck1-play or crossfader, or a Mixxx control written [Group],key.
+    ardour FILE OUTPUT [CONTROL=TARGET]...
+        Write an Ardour MIDI binding map of the program in FILE to OUTPUT. Pads take a function
+        (transport-roll...), a Group/action, mute:N, solo:N or rec:N for track N, knobs gain:N,
+        pan:N, trim:N or plugin:N:P:Q.
+    script [TEMPLATE FILE OUTPUT [CONTROL=TARGET]...]
+        Fill in TEMPLATE (bitwig, reaper or one in the templates directory) from the program in
+        FILE and write the DAW script to OUTPUT, controls bound as for mixxx. Without arguments
+        the templates and their targets are listed.
+    monitor [--device DEVICE] [--type note|cc|pc|sysex|other] [--channel 1-16]
+        Print decoded MIDI traffic to and from the device, identity exchange included.
+        While running, enter `p` to pause/resume and `c` to clear.
+    record FILE [--device DEVICE] [--format 0|1]
+        Record device input to a Standard MIDI File until Enter is pressed.
+        Format 0 keeps one track, format 1 writes a track per channel.
+    osc [HOST:PORT] [--device DEVICE] [--slot SLOT] [--pad-address ADDRESS] [--knob-address ADDRESS]
+        Send the device's pads and knobs as OSC messages to HOST:PORT until Enter is pressed,
+        controls found by program SLOT, the active one without it. A pad sends its velocity, a
+        knob its value from 0 to 1. In addresses {n} is the control's number and {program} the
+        program's. Defaults are kept in the settings file.
+    serve [HOST:PORT] [--device DEVICE]
+        Run a WebSocket server sending the device's pads and knobs as JSON to web pages until
+        Enter is pressed. Pages can switch the active program and push programs to the device.
+        Listens on the address kept in the settings file, 127.0.0.1:9001 by default.
+    emulate [--model lpd8|mk2|lpk25|mpkmini] [--sysex-id ID]
+        Create virtual ports that answer like a device, for trying the editor without one.
+        The emulated unit has SysEx device id ID, 0 by default.
+    help
+        Show this message.
+
+DEVICE is a nickname or part of a port name, the first device found is used without it.
+
+--backend alsa|jack picks the MIDI system, the choice is remembered for later runs.
+
+--sysex-id ID (0-127) talks only to the unit with that SysEx device id, for telling identical
+controllers on one MIDI merge apart. Without it every unit is asked and, when several answer
+through the same ports, each is addressed with the device id it answered with.
+
+--sysex-delay MS waits MS milliseconds after each SysEx message, for devices that drop messages
+sent too quickly. The default is kept in the settings file.
+
+--verbose (or -v) logs what the program does to stderr, repeat it for more detail; given three
+times, every SysEx message is traced, decoded. RUST_LOG overrides the level per target, e.g.
+RUST_LOG=lpd8_config::protocol=trace. --log-file FILE writes the log to FILE instead, for bug reports.
+";
+
+/// Options every command and the window take
+pub static SESSION_OPTIONS: &[&str] = &["--backend", "--sysex-id", "--sysex-delay", "--log-file"];
+
+/// Runs a command line invocation, returning the exit code, or `None` when the GUI should start.
+pub fn run(args: &[String]) -> Option<i32> {
+    // Logging is already set up, the flags can go anywhere
+    let args = args.iter().filter(|a| !logging::FLAGS.contains(&a.as_str())).cloned().collect::<Vec<_>>();
+    let command = args.get(1)?;
+    let options = &args[2..];
+    let run_command: fn(&[String]) -> Result<(), String> = match command.as_str() {
+        "devices" => devices,
+        "info" => info,
+        "nickname" => nickname,
+        "fetch" => fetch,
+        "push" => push,
+        "library" => library,
+        "apply" => apply,
+        "overlay" => overlay,
+        "mixxx" => |options| export_mapping(&mixxx::EXPORTER, options),
+        "ardour" => |options| export_mapping(&ardour::EXPORTER, options),
+        "script" => script,
+        "monitor" => monitor,
+        "record" => record,
+        "osc" => osc,
+        "serve" => serve,
+        "emulate" => emulate,
+        "help" | "--help" | "-h" => help,
+        _ => return None,
+    };
+    let result = apply_session_options(options, &mut Settings::load()).and_then(|()| run_command(options));
+    Some(match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}: {}", command, e);
+            1
+        },
+    })
+}
+
+fn option_value<'a>(options: &'a [String], name: &str) -> Result<Option<&'a str>, String> {
+    match options.iter().position(|o| o == name) {
+        Some(i) => options.get(i + 1)
+            .map(|v| Some(v.as_str()))
+            .ok_or_else(|| format!("{} needs a value", name)),
+        None => Ok(None),
+    }
+}
+
+/// Arguments that aren't an option or an option's value
+fn positional(options: &[String]) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut i = 0;
+    while i < options.len() {
+        if options[i].starts_with("--") {
+            i += 2;
+        } else {
+            args.push(options[i].as_str());
+            i += 1;
+        }
+    }
+    args
+}
+
+fn slot(arg: Option<&str>) -> Result<u8, String> {
+    match arg.map(|a| a.parse::<u8>()) {
+        Some(Ok(slot)) if slot >= 1 && slot <= 4 => Ok(slot),
+        _ => Err("SLOT must be 1-4".to_string()),
+    }
+}
+
+fn help(_options: &[String]) -> Result<(), String> {
+    print!("{}", USAGE);
+    Ok(())
+}
+
+/// Picks the backend and SysEx pacing from the options or the settings, saving a backend
+/// given with `--backend`.
+pub fn apply_session_options(options: &[String], settings: &mut Settings) -> Result<(), String> {
+    let delay_ms = match option_value(options, "--sysex-delay")? {
+        Some(ms) => ms.parse::<u64>().map_err(|_| format!("invalid SysEx delay \"{}\"", ms))?,
+        None => settings.sysex_delay_ms,
+    };
+    transport::set_message_delay(delay_ms);
+
+    if let Some(name) = option_value(options, "--backend")? {
+        let backend = Backend::from_name(name).ok_or_else(|| {
+            let names = Backend::available().iter().map(|b| b.id()).collect::<Vec<_>>();
+            format!("unknown backend \"{}\", expected one of {}", name, names.join(", "))
+        })?;
+        backend.check()?;
+        settings.backend = Some(backend);
+        settings.save().map_err(|e| e.to_string())?;
+    }
+    // A setting from a build with JACK support falls back to the default
+    let backend = match settings.backend {
+        Some(backend) => match backend.check() {
+            Ok(()) => backend,
+            Err(e) => {
+                warn!("{}, using {}", e, Backend::Alsa);
+                Backend::Alsa
+            },
+        },
+        None => Backend::Alsa,
+    };
+    Backend::set_current(backend);
+    Ok(())
+}
+
+/// The `--sysex-id` session option
+pub fn sysex_id(options: &[String]) -> Result<Option<u8>, String> {
+    match option_value(options, "--sysex-id")? {
+        Some(id) => match id.parse::<u8>() {
+            Ok(id) if id <= SYSEX_ALL => Ok(Some(id)),
+            _ => Err(format!("invalid SysEx device id \"{}\", expected 0-127", id)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Probes for devices and picks the one named by `--device`, or the first.
+fn find_device(monitor: &Monitor, settings: &Settings, options: &[String]) -> Result<(DeviceIDs, DeviceInfo), String> {
+    let pattern = option_value(options, "--device")?;
+    let devices = watcher::probe_all(monitor, sysex_id(options)?);
+    match pattern {
+        Some(pattern) => devices.into_iter()
+            .find(|(d, _)| d.matches(settings, pattern))
+            .ok_or_else(|| format!("no device matching \"{}\"", pattern)),
+        None => devices.into_iter().next().ok_or_else(|| "no device found".to_string()),
+    }
+}
+
+fn devices(options: &[String]) -> Result<(), String> {
+    let settings = Settings::load();
+    for (device, _) in watcher::probe_all(&Monitor::new(), sysex_id(options)?) {
+        println!("{}\t{} => {}", device.label(&settings), (device.1).1, (device.0).1);
+    }
+    Ok(())
+}
+
+fn info(options: &[String]) -> Result<(), String> {
+    let settings = Settings::load();
+    let (device, info) = find_device(&Monitor::new(), &settings, options)?;
+    println!("{}\n{}", device.label(&settings), info);
+    Ok(())
+}
+
+fn nickname(options: &[String]) -> Result<(), String> {
+    let name = positional(options).first().cloned().unwrap_or("");
+    let mut settings = Settings::load();
+    let (device, _) = find_device(&Monitor::new(), &settings, options)?;
+    settings.set_nickname(&device.key(), name);
+    settings.save().map_err(|e| e.to_string())
+}
+
+fn fetch(options: &[String]) -> Result<(), String> {
+    let args = positional(options);
+    let slot = slot(args.get(0).cloned())?;
+    let path = Path::new(args.get(1).ok_or("missing FILE")?);
+    let monitor = Monitor::new();
+    let (device, info) = find_device(&monitor, &Settings::load(), options)?;
+    let profile = info.profile().unwrap_or(&profile::LPD8);
+    let program = profile.download_program(&monitor, &device, slot)?;
+    // The device only has the numbers, names and metadata of a file being replaced are kept
+    let mut file = ProgramFile::load(path).unwrap_or_else(|_| ProgramFile::new(&program, &Names::default()));
+    file.program = program;
+    file.save(path).map_err(|e| e.to_string())
+}
+
+fn push(options: &[String]) -> Result<(), String> {
+    let args = positional(options);
+    let slot = slot(args.get(0).cloned())?;
+    let path = Path::new(args.get(1).ok_or("missing FILE")?);
+    let program = ProgramFile::load(path).map_err(|e| e.to_string())?.program;
+    let monitor = Monitor::new();
+    let (device, info) = find_device(&monitor, &Settings::load(), options)?;
+    info.profile().unwrap_or(&profile::LPD8).upload_program(&monitor, &device, slot, &program)
+}
+
+fn library(options: &[String]) -> Result<(), String> {
+    let query = positional(options).join(" ");
+    let presets = library::list();
+    if presets.is_empty() {
+        println!("No presets in {}", library::dir().display());
+    }
+    for preset in presets.iter().filter(|p| p.matches(&query)) {
+        println!("{}\t{}\t{}", preset.name(), preset.details(), preset.path.display());
+    }
+    Ok(())
+}
+
+fn apply(options: &[String]) -> Result<(), String> {
+    let args = positional(options);
+    let preset = library::find(args.get(0).ok_or("missing PRESET")?)?;
+    let slot = slot(args.get(1).cloned())?;
+    let monitor = Monitor::new();
+    let (device, info) = find_device(&monitor, &Settings::load(), options)?;
+    info.profile().unwrap_or(&profile::LPD8).upload_program(&monitor, &device, slot, &preset.program)
+}
+
+fn overlay(options: &[String]) -> Result<(), String> {
+    let args = positional(options);
+    let output = Path::new(args.get(0).ok_or("missing OUTPUT")?);
+    if args.len() < 2 {
+        return Err("missing FILE".to_string());
+    }
+    let files = args[1..].iter()
+        .map(|path| ProgramFile::load(Path::new(path)).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let sheets = files.iter().enumerate()
+        .map(|(i, file)| overlay::Sheet { id: i as u8 + 1, program: &file.program, names: &file.names })
+        .collect::<Vec<_>>();
+    overlay::export(output, &sheets)
+}
+
+fn export_mapping(exporter: &mapping::Exporter, options: &[String]) -> Result<(), String> {
+    let args = positional(options);
+    let path = args.get(0).ok_or("missing FILE")?;
+    let output = args.get(1).ok_or("missing OUTPUT")?;
+    let file = ProgramFile::load(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+    let assignments = mapping::Assignments::parse(&args[2..])?;
+    let mapping = (exporter.export)(1, &file.program, &file.names, &assignments)?;
+    fs::write(output, mapping).map_err(|e| e.to_string())
+}
+
+fn script(options: &[String]) -> Result<(), String> {
+    let args = positional(options);
+    let id = match args.get(0) {
+        Some(id) => id,
+        None => {
+            println!("Templates are read from {}", script::dir().display());
+            for template in script::list() {
+                println!("{}\t{} (.{})", template.id, template.name, template.extension);
+                for (kind, choices) in [("pad", &template.pad_choices), ("knob", &template.knob_choices)].iter() {
+                    for choice in choices.iter() {
+                        println!("\t{} {}\t{}", kind, choice.id, choice.label);
+                    }
+                }
+            }
+            return Ok(());
+        },
+    };
+    let template = script::find(id)?;
+    let path = args.get(1).ok_or("missing FILE")?;
+    let output = args.get(2).ok_or("missing OUTPUT")?;
+    let file = ProgramFile::load(Path::new(path)).map_err(|e| format!("{}: {}", path, e))?;
+    let assignments = mapping::Assignments::parse(&args[3..])?;
+    let script = template.render(1, &file.program, &file.names, &assignments)?;
+    fs::write(output, script).map_err(|e| e.to_string())
+}
+
+fn monitor(options: &[String]) -> Result<(), String> {
+    let filter = Filter {
+        kind: match option_value(options, "--type")? {
+            Some(name) => Some(Kind::from_name(name).ok_or_else(|| format!("unknown message type \"{}\"", name))?),
+            None => None,
+        },
+        channel: match option_value(options, "--channel")? {
+            Some(channel) => match channel.parse::<u8>() {
+                Ok(channel) if channel >= 1 && channel <= 16 => Some(channel - 1),
+                _ => return Err(format!("invalid channel \"{}\"", channel)),
+            },
+            None => None,
+        },
+    };
+
+    // Subscribed before probing, so the identity request and reply are shown too
+    let monitor = Monitor::new();
+    let events = monitor.subscribe();
+    let (device, _) = find_device(&monitor, &Settings::load(), options)?;
+    let port_name = (device.0).1.clone();
+    let ports = [port_name.clone(), (device.1).1.clone()];
+
+    let _connection = {
+        let (port_name, monitor) = (port_name.clone(), monitor.clone());
+        let mut reassembler = Reassembler::new();
+        backend::connect_input(&APP_NAME, &port_name.clone(), move |_t, data| {
+            reassembler.feed(data, |message| monitor.log(Direction::In, &port_name, message));
+        })?
+    };
+    println!("Monitoring \"{}\", enter p to pause, c to clear", port_name);
+
+    let (command_tx, command_rx) = channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            if let Ok(line) = line {
+                if command_tx.send(line.trim().to_string()).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    // Runs until interrupted, stdin closing only stops the pause/clear commands. Events that
+    // come in while paused are held and printed on resuming.
+    let mut paused = false;
+    let mut held = VecDeque::new();
+    loop {
+        for command in command_rx.try_iter() {
+            match command.as_str() {
+                "p" => {
+                    paused = !paused;
+                    println!("{}", if paused { "-- paused --" } else { "-- resumed --" });
+                    if !paused {
+                        for event in held.drain(..) {
+                            println!("{}", event);
+                        }
+                    }
+                },
+                "c" => {
+                    held.clear();
+                    print!("\x1B[2J\x1B[H");
+                },
+                _ => {},
+            }
+        }
+        let first = events.recv_timeout(Duration::from_millis(50)).ok();
+        let device_events = first.into_iter().chain(events.try_iter()).filter(|e| ports.contains(&e.port));
+        for event in device_events.filter(|e| filter.matches(e)) {
+            if paused {
+                held.push_back(event);
+                if held.len() > HISTORY_LEN {
+                    held.pop_front();
+                }
+            } else {
+                println!("{}", event);
+            }
+        }
+    }
+}
+
+fn record(options: &[String]) -> Result<(), String> {
+    let path = match positional(options).first() {
+        Some(path) => Path::new(*path),
+        None => return Err("missing output file".to_string()),
+    };
+    let format = match option_value(options, "--format")? {
+        Some(name) => Format::from_name(name).ok_or_else(|| format!("unknown format \"{}\"", name))?,
+        None => Format::SingleTrack,
+    };
+
+    let (device, _) = find_device(&Monitor::new(), &Settings::load(), options)?;
+    let port_name = (device.0).1.clone();
+
+    let recorder = Recorder::new();
+    recorder.start();
+    let connection = {
+        let recorder = recorder.clone();
+        let mut reassembler = Reassembler::new();
+        backend::connect_input(&APP_NAME, &port_name, move |t, data| {
+            reassembler.feed(data, |message| recorder.push(t, message));
+        })?
+    };
+    println!("Recording \"{}\", press Enter to stop", port_name);
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).map_err(|e| e.to_string())?;
+    drop(connection);
+
+    let events = recorder.stop();
+    recorder::write(path, format, &events).map_err(|e| e.to_string())?;
+    println!("Wrote {} events to {}", events.len(), path.display());
+    Ok(())
+}
+
+fn osc(options: &[String]) -> Result<(), String> {
+    let mut config = Settings::load().osc;
+    if let Some(target) = positional(options).first() {
+        config.target = target.to_string();
+    }
+    if let Some(address) = option_value(options, "--pad-address")? {
+        config.pad_address = address.to_string();
+    }
+    if let Some(address) = option_value(options, "--knob-address")? {
+        config.knob_address = address.to_string();
+    }
+
+    let monitor = Monitor::new();
+    let (device, info) = find_device(&monitor, &Settings::load(), options)?;
+    let profile = info.profile().unwrap_or(&profile::LPD8);
+    let slot = match option_value(options, "--slot")? {
+        Some(slot) => self::slot(Some(slot))?,
+        None => profile.get_active_program(&monitor, &device).unwrap_or(1),
+    };
+    let program = profile.download_program(&monitor, &device, slot)?;
+    let port_name = (device.0).1.clone();
+
+    let bridge = osc::Bridge::new();
+    bridge.start(&config).map_err(|e| format!("couldn't send to {}: {}", config.target, e))?;
+    let connection = {
+        let bridge = bridge.clone();
+        let mut reassembler = Reassembler::new();
+        backend::connect_input(&APP_NAME, &port_name, move |_, data| {
+            reassembler.feed(data, |message| bridge.forward(slot, &program, message));
+        })?
+    };
+    println!("Sending \"{}\" PROG {} to {}, press Enter to stop", port_name, slot, config.target);
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).map_err(|e| e.to_string())?;
+    drop(connection);
+    bridge.stop();
+    Ok(())
+}
+
+fn serve(options: &[String]) -> Result<(), String> {
+    let mut config = Settings::load().websocket;
+    if let Some(address) = positional(options).first() {
+        config.address = address.to_string();
+    }
+
+    let monitor = Monitor::new();
+    let (device, info) = find_device(&monitor, &Settings::load(), options)?;
+    let profile = info.profile().unwrap_or(&profile::LPD8);
+    let mut programs = [Program::default(); 4];
+    for (slot, program) in (1..=4).zip(programs.iter_mut()) {
+        *program = profile.download_program(&monitor, &device, slot)?;
+    }
+    // The active program and the slots, as the device has them
+    let state = Arc::new(Mutex::new((profile.get_active_program(&monitor, &device).unwrap_or(1), programs)));
+    let port_name = (device.0).1.clone();
+
+    let server = websocket::Server::new();
+    let commands = server.start(&config).map_err(|e| format!("couldn't listen on {}: {}", config.address, e))?;
+    let connection = {
+        let (server, state) = (server.clone(), state.clone());
+        let mut reassembler = Reassembler::new();
+        backend::connect_input(&APP_NAME, &port_name, move |_, data| {
+            let (active, programs) = *state.lock().unwrap();
+            reassembler.feed(data, |message| server.forward(active, &programs[usize::from(active) - 1], message));
+        })?
+    };
+    println!("Serving \"{}\" on ws://{}, press Enter to stop", port_name, config.address);
+
+    let (stop_tx, stop_rx) = channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        let _ = io::stdin().read_line(&mut line);
+        let _ = stop_tx.send(());
+    });
+    while stop_rx.try_recv().is_err() {
+        let command = match commands.recv_timeout(Duration::from_millis(50)) {
+            Ok(command) => command,
+            Err(_) => continue,
+        };
+        let result = match command {
+            websocket::Command::SetActive { program: id } => {
+                state.lock().unwrap().0 = id;
+                profile.set_active_program(&monitor, &device, id)
+            },
+            websocket::Command::Push { program: id, data } => {
+                let program = {
+                    let mut state = state.lock().unwrap();
+                    if let Some(file) = data {
+                        state.1[usize::from(id) - 1] = file.program;
+                    }
+                    state.1[usize::from(id) - 1]
+                };
+                profile.upload_program(&monitor, &device, id, &program)
+            },
+        };
+        if let Err(e) = result {
+            eprintln!("serve: {}", e);
+        }
+    }
+    drop(connection);
+    server.stop();
+    Ok(())
+}
+
+fn emulate(options: &[String]) -> Result<(), String> {
+    let profile = match option_value(options, "--model")? {
+        Some(id) => Profile::by_id(id).ok_or_else(|| {
+            let ids = PROFILES.iter().map(|p| p.id).collect::<Vec<_>>();
+            format!("unknown model \"{}\", expected one of {}", id, ids.join(", "))
+        })?,
+        None => &profile::LPD8,
+    };
+    let _emulator = emulator::start(profile, sysex_id(options)?.unwrap_or(0x00))?;
+    println!("Emulating {}, press Enter to stop", profile.name);
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+
+use crate::profile::Profile;
+use crate::MAN_AKAI;
+
+/// Universal identity request, `device_id` 0x7F asks every unit
+pub fn identity_request(device_id: u8) -> Vec<u8> {
+    vec![0xF0, 0x7E, device_id, 0x06, 0x01, 0xF7]
+}
+
+/// Universal identity reply, `F0 7E <device id> 06 02 <manufacturer> <family> <model> <version> ... F7`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    /// SysEx device id the unit answered with
+    pub device_id: u8,
+    /// One byte id, or three bytes starting with 0x00
+    pub manufacturer: Vec<u8>,
+    pub family: u16,
+    pub model: u16,
+    /// Software revision, as sent
+    pub version: [u8; 4],
+    /// Bytes after the revision that some units append
+    pub extra: Vec<u8>,
+}
+
+impl DeviceInfo {
+    pub fn parse(message: &[u8]) -> Result<DeviceInfo, String> {
+        if message.len() < 6 || message[..2] != [0xF0, 0x7E] || message[3..5] != [0x06, 0x02]
+            || message[message.len() - 1] != 0xF7 {
+            return Err("not an identity reply".to_string());
+        }
+        let body = &message[5..message.len() - 1];
+        let manufacturer_len = if body.first() == Some(&0x00) { 3 } else { 1 };
+        if body.len() < manufacturer_len + 8 {
+            return Err(format!("identity reply too short, {} bytes", message.len()));
+        }
+        let (manufacturer, body) = body.split_at(manufacturer_len);
+        let mut version = [0; 4];
+        version.copy_from_slice(&body[4..8]);
+        Ok(DeviceInfo {
+            device_id: message[2],
+            manufacturer: manufacturer.to_vec(),
+            // 14 bit values, LSB first
+            family: u16::from(body[0]) | u16::from(body[1]) << 7,
+            model: u16::from(body[2]) | u16::from(body[3]) << 7,
+            version,
+            extra: body[8..].to_vec(),
+        })
+    }
+
+    pub fn profile(&self) -> Option<&'static Profile> {
+        if self.manufacturer != MAN_AKAI {
+            return None;
+        }
+        Profile::find(self.family, self.model)
+    }
+
+    /// Builds the reply a unit with `profile` gives.
+    pub fn reply(profile: &Profile, device_id: u8) -> Vec<u8> {
+        let (family, model_id) = (profile.family, profile.model.unwrap_or(0));
+        let mut reply = vec![0xF0, 0x7E, device_id, 0x06, 0x02];
+        reply.extend(MAN_AKAI);
+        reply.extend(&[(family & 0x7F) as u8, (family >> 7) as u8, (model_id & 0x7F) as u8, (model_id >> 7) as u8]);
+        reply.extend(&[0x00, 0x00, 0x66, 0x00]);
+        reply.push(0xF7);
+        reply
+    }
+
+    pub fn manufacturer_name(&self) -> String {
+        if self.manufacturer == MAN_AKAI {
+            "Akai".to_string()
+        } else {
+            self.manufacturer.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+        }
+    }
+
+    pub fn model_name(&self) -> String {
+        match self.profile() {
+            Some(profile) => profile.name.to_string(),
+            None => format!("family {:04X} model {:04X}", self.family, self.model),
+        }
+    }
+
+    pub fn firmware(&self) -> String {
+        self.version.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(".")
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Manufacturer: {}", self.manufacturer_name())?;
+        writeln!(f, "Model: {}", self.model_name())?;
+        writeln!(f, "Family: {:04X}", self.family)?;
+        writeln!(f, "Model id: {:04X}", self.model)?;
+        writeln!(f, "Firmware: {}", self.firmware())?;
+        write!(f, "SysEx device id: {:02X}", self.device_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::profile;
+
+    /// What an LPD8 answers
+    const LPD8_REPLY: &[u8] = &[
+        0xF0, 0x7E, 0x00, 0x06, 0x02, 0x47, 0x75, 0x00, 0x19, 0x00, 0x00, 0x00, 0x66, 0x7F, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0xF7,
+    ];
+
+    #[test]
+    fn lpd8_reply() {
+        let info = DeviceInfo::parse(LPD8_REPLY).expect("the reply parses");
+        assert_eq!(info.device_id, 0x00);
+        assert_eq!(info.manufacturer, MAN_AKAI.to_vec());
+        assert_eq!((info.family, info.model), (0x75, 0x19));
+        assert_eq!(info.version, [0x00, 0x00, 0x66, 0x7F]);
+        assert_eq!(info.extra, vec![0; 20]);
+        assert_eq!(info.profile(), Some(&profile::LPD8));
+    }
+
+    #[test]
+    fn truncated_reply() {
+        assert!(DeviceInfo::parse(&[0xF0, 0x7E, 0x00, 0x06, 0x02, 0x47, 0x75, 0x00, 0xF7]).is_err());
+        // A three byte manufacturer id cut short
+        assert!(DeviceInfo::parse(&[0xF0, 0x7E, 0x00, 0x06, 0x02, 0x00, 0xF7]).is_err());
+        // Cut before the F7
+        assert!(DeviceInfo::parse(&LPD8_REPLY[..16]).is_err());
+        assert!(DeviceInfo::parse(&[0xF0, 0x7E, 0xF7]).is_err());
+        assert!(DeviceInfo::parse(&[]).is_err());
+    }
+
+    #[test]
+    fn emulator_reply() {
+        for profile in profile::PROFILES {
+            let info = DeviceInfo::parse(&DeviceInfo::reply(profile, 0x03)).unwrap();
+            assert_eq!(info.device_id, 0x03);
+            assert_eq!(info.profile(), Some(*profile));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Virtual ports that answer like one of the `profile::PROFILES` devices, so the editor and the command line
+//! can be tried without the hardware.
+
+use midir::MidiInputConnection;
+
+use crate::backend::{Backend, OutputConnection};
+use crate::device_info::DeviceInfo;
+use crate::profile::{Codec, Profile};
+use crate::transport;
+use crate::{mk2, LPD8Message, Program, MAN_AKAI, SYSEX_ALL};
+
+struct State {
+    profile: &'static Profile,
+    /// SysEx device id the unit answers to, besides `SYSEX_ALL`
+    sysex_id: u8,
+    programs: [Program; 4],
+    active: u8,
+}
+
+impl State {
+    fn program(&mut self, id: u8) -> Option<&mut Program> {
+        self.programs.get_mut((id as usize).checked_sub(1)?)
+    }
+
+    fn new(profile: &'static Profile, sysex_id: u8) -> State {
+        State {
+            profile,
+            sysex_id,
+            programs: [Program::default(); 4],
+            active: 1,
+        }
+    }
+
+    /// Takes in a message, giving what the device answers
+    fn handle(&mut self, message: &[u8]) -> Option<Vec<u8>> {
+        if message.len() < 5 || (message[2] != self.sysex_id && message[2] != SYSEX_ALL) {
+            return None;
+        }
+        if message.len() == 6 && message[..2] == [0xF0, 0x7E] && message[3..5] == [0x06, 0x01] {
+            return Some(DeviceInfo::reply(self.profile, self.sysex_id));
+        }
+        if message.len() < 8 || message[1..2] != *MAN_AKAI || message[3] != self.profile.product {
+            return None;
+        }
+        let id = message[7];
+        match self.profile.codec {
+            Codec::Shared(_) => self.handle_shared(message, id),
+            Codec::Mk2 => self.handle_mk2(message, id),
+        }
+    }
+
+    fn handle_shared(&mut self, message: &[u8], id: u8) -> Option<Vec<u8>> {
+        match LPD8Message::from_pattern(message[4])? {
+            LPD8Message::UploadProgram => self.store(message, id),
+            LPD8Message::SetActiveProgram => {
+                self.active = id;
+                None
+            },
+            LPD8Message::DownloadProgram => self.send_program(id),
+            LPD8Message::GetActiveProgram => Some(self.profile.active_program_reply(self.sysex_id, self.active)),
+        }
+    }
+
+    fn handle_mk2(&mut self, message: &[u8], id: u8) -> Option<Vec<u8>> {
+        match message[4] {
+            mk2::UPLOAD_PROGRAM => self.store(message, id),
+            mk2::DOWNLOAD_PROGRAM => self.send_program(id),
+            _ => None,
+        }
+    }
+
+    fn store(&mut self, message: &[u8], id: u8) -> Option<Vec<u8>> {
+        if let Some(program) = self.profile.parse_program(message) {
+            if let Some(slot) = self.program(id) {
+                *slot = program;
+            }
+        }
+        None
+    }
+
+    fn send_program(&mut self, id: u8) -> Option<Vec<u8>> {
+        let program = self.program(id).cloned()?;
+        Some(self.profile.download_reply(self.sysex_id, id, &program))
+    }
+}
+
+/// Keeps the virtual ports open until dropped
+pub struct Emulator {
+    _input: MidiInputConnection<(State, OutputConnection)>,
+}
+
+#[cfg(unix)]
+pub fn start(profile: &'static Profile, sysex_id: u8) -> Result<Emulator, String> {
+    use midir::os::unix::{VirtualInput, VirtualOutput};
+    use midir::{MidiInput, MidiOutput};
+
+    if Backend::current() != Backend::Alsa {
+        return Err(format!("virtual ports need {}, pick it with --backend alsa", Backend::Alsa));
+    }
+    let name = format!("{} emulator {}", profile.name, sysex_id);
+    let output = MidiOutput::new(&name).map_err(|e| e.to_string())?
+        .create_virtual(&name).map_err(|e| e.to_string())?;
+    let output = OutputConnection::Alsa(output);
+    let input = MidiInput::new(&name).map_err(|e| e.to_string())?
+        .create_virtual(&name, |_t, message, (state, output)| {
+            if let Some(reply) = state.handle(message) {
+                if transport::send(output, &reply).is_err() {
+                    warn!("Emulator couldn't reply");
+                }
+            }
+        }, (State::new(profile, sysex_id), output))
+        .map_err(|e| e.to_string())?;
+    Ok(Emulator {
+        _input: input,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn start(_profile: &'static Profile, _sysex_id: u8) -> Result<Emulator, String> {
+    Err("virtual ports need ALSA or CoreMIDI".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::profile;
+
+    #[test]
+    fn mk2_set_and_get_program() {
+        let mut state = State::new(&profile::LPD8_MK2, 0x00);
+        let mut program = Program { channel: 5, ..Program::default() };
+        program.pads[0].note = 40;
+        program.mk2.pads[0].on = mk2::Rgb(200, 10, 130);
+        program.mk2.knobs[7].channel = 9;
+
+        // Sent to every unit, an upload has no answer
+        assert_eq!(state.handle(&mk2::upload_message(SYSEX_ALL, 3, &program)), None);
+        let reply = state.handle(&mk2::download_request(SYSEX_ALL, 3)).expect("a program reply");
+        assert_eq!(reply, mk2::download_reply(0x00, 3, &program));
+
+        let stored = profile::LPD8_MK2.parse_program(&reply).expect("reply parses");
+        assert_eq!(stored.channel, 5);
+        assert_eq!(stored.pads[0].note, 40);
+        assert_eq!(stored.mk2.pads[0].on, mk2::Rgb(200, 10, 130));
+        assert_eq!(stored.mk2.knobs[7].channel, 9);
+        // Other slots are untouched
+        let other = state.handle(&mk2::download_request(SYSEX_ALL, 1)).unwrap();
+        assert_eq!(profile::LPD8_MK2.parse_program(&other).unwrap().channel, 0);
+    }
+
+    #[test]
+    fn other_units_and_slots_are_ignored() {
+        let mut state = State::new(&profile::LPD8_MK2, 0x02);
+        assert_eq!(state.handle(&mk2::download_request(0x01, 1)), None);
+        assert!(state.handle(&mk2::download_request(0x02, 1)).is_some());
+        assert_eq!(state.handle(&mk2::download_request(0x02, 5)), None);
+        assert_eq!(state.handle(&[0xF0, 0xF7]), None);
+    }
+}
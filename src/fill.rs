@@ -0,0 +1,279 @@
+//! Filling all pads or knobs at once.
+//!
+//! Pads get notes counted up from a root, by semitones or along a scale, or a drum kit's notes;
+//! knobs get consecutive control numbers or a preset set. The dialogs preview the result before
+//! anything is changed.
+
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+pub struct Scale {
+    pub id: &'static str,
+    pub name: &'static str,
+    /// Semitones above the root within an octave
+    pub steps: &'static [u8],
+}
+
+pub static SCALES: &[Scale] = &[
+    Scale { id: "major", name: "Major", steps: &[0, 2, 4, 5, 7, 9, 11] },
+    Scale { id: "minor", name: "Natural minor", steps: &[0, 2, 3, 5, 7, 8, 10] },
+    Scale { id: "harmonic-minor", name: "Harmonic minor", steps: &[0, 2, 3, 5, 7, 8, 11] },
+    Scale { id: "major-pentatonic", name: "Major pentatonic", steps: &[0, 2, 4, 7, 9] },
+    Scale { id: "minor-pentatonic", name: "Minor pentatonic", steps: &[0, 3, 5, 7, 10] },
+    Scale { id: "dorian", name: "Dorian", steps: &[0, 2, 3, 5, 7, 9, 10] },
+    Scale { id: "phrygian", name: "Phrygian", steps: &[0, 1, 3, 5, 7, 8, 10] },
+    Scale { id: "lydian", name: "Lydian", steps: &[0, 2, 4, 6, 7, 9, 11] },
+    Scale { id: "mixolydian", name: "Mixolydian", steps: &[0, 2, 4, 5, 7, 9, 10] },
+    Scale { id: "locrian", name: "Locrian", steps: &[0, 1, 3, 5, 6, 8, 10] },
+];
+
+/// Notes for PAD 1 to 8, the bottom row first
+pub struct DrumKit {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub notes: [u8; 8],
+}
+
+pub static DRUM_KITS: &[DrumKit] = &[
+    // Kick, snare, closed and open hi-hat, low and high tom, crash, ride
+    DrumKit { id: "gm", name: "General MIDI kit", notes: [36, 38, 42, 46, 45, 50, 49, 51] },
+    // Kick, rim, snare, clap, closed and open hi-hat, cowbell, crash
+    DrumKit { id: "808", name: "Drum machine", notes: [36, 37, 38, 39, 42, 46, 56, 49] },
+    // An MPC's default bank A
+    DrumKit { id: "mpc", name: "MPC", notes: [37, 36, 42, 82, 40, 38, 46, 44] },
+];
+
+/// Control numbers for K1 to K8
+pub struct KnobPreset {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub controls: [u8; 8],
+}
+
+pub static KNOB_PRESETS: &[KnobPreset] = &[
+    // Modulation, volume, pan, expression, resonance, cutoff, reverb and chorus
+    KnobPreset { id: "gm", name: "General MIDI controls", controls: [1, 7, 10, 11, 71, 74, 91, 93] },
+    // Cutoff, resonance, attack, decay, release, vibrato rate, depth and delay
+    KnobPreset { id: "synth", name: "Synth sound controllers", controls: [74, 71, 73, 75, 72, 76, 77, 78] },
+];
+
+/// How pads are filled
+#[derive(Clone, Copy)]
+pub enum PadFill {
+    Chromatic,
+    Scale(&'static Scale),
+    DrumKit(&'static DrumKit),
+}
+
+impl PadFill {
+    /// Note for each of `count` pads, starting at `root` where that applies
+    pub fn notes(self, root: u8, count: usize) -> Vec<u8> {
+        match self {
+            PadFill::Chromatic => (0..count).map(|i| clamp(usize::from(root) + i)).collect(),
+            PadFill::Scale(scale) => (0..count).map(|i| {
+                let (octave, degree) = (i / scale.steps.len(), i % scale.steps.len());
+                clamp(usize::from(root) + 12 * octave + usize::from(scale.steps[degree]))
+            }).collect(),
+            PadFill::DrumKit(kit) => kit.notes.iter().cloned().cycle().take(count).collect(),
+        }
+    }
+
+    fn uses_root(self) -> bool {
+        match self {
+            PadFill::DrumKit(_) => false,
+            _ => true,
+        }
+    }
+
+    fn id(self) -> String {
+        match self {
+            PadFill::Chromatic => "chromatic".to_string(),
+            PadFill::Scale(scale) => format!("scale-{}", scale.id),
+            PadFill::DrumKit(kit) => format!("kit-{}", kit.id),
+        }
+    }
+
+    fn all() -> Vec<(PadFill, &'static str)> {
+        let mut fills = vec![(PadFill::Chromatic, "Chromatic")];
+        fills.extend(SCALES.iter().map(|s| (PadFill::Scale(s), s.name)));
+        fills.extend(DRUM_KITS.iter().map(|k| (PadFill::DrumKit(k), k.name)));
+        fills
+    }
+}
+
+/// How knobs are filled
+#[derive(Clone, Copy)]
+pub enum KnobFill {
+    Consecutive,
+    Preset(&'static KnobPreset),
+}
+
+impl KnobFill {
+    /// Control number for each of `count` knobs, counting from `first` where that applies
+    pub fn controls(self, first: u8, count: usize) -> Vec<u8> {
+        match self {
+            KnobFill::Consecutive => (0..count).map(|i| clamp(usize::from(first) + i)).collect(),
+            KnobFill::Preset(preset) => preset.controls.iter().cloned().cycle().take(count).collect(),
+        }
+    }
+
+    fn id(self) -> String {
+        match self {
+            KnobFill::Consecutive => "consecutive".to_string(),
+            KnobFill::Preset(preset) => format!("preset-{}", preset.id),
+        }
+    }
+
+    fn all() -> Vec<(KnobFill, &'static str)> {
+        let mut fills = vec![(KnobFill::Consecutive, "Consecutive")];
+        fills.extend(KNOB_PRESETS.iter().map(|p| (KnobFill::Preset(p), p.name)));
+        fills
+    }
+}
+
+fn clamp(value: usize) -> u8 {
+    value.min(127) as u8
+}
+
+/// Note name the way Akai's editors show it, middle C (60) is C3
+pub fn note_name(note: u8) -> String {
+    const NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    format!("{}{}", NAMES[usize::from(note % 12)], i32::from(note / 12) - 2)
+}
+
+/// Dialog with a fill picker, a start value and a preview, returning the values when applied.
+/// `values` gives the values for a fill id and start, their preview and whether start is used.
+fn ask_fill<F>(window: &gtk::ApplicationWindow, title: &str, fills: &[(String, &str)], start_label: &str, start: u8, values: F) -> Option<Vec<u8>>
+    where F: Fn(&str, u8) -> Option<(Vec<u8>, String, bool)> + 'static {
+    crate::form_dialog(window, title, "_Apply", |grid| {
+        grid.attach(&gtk::Label::new(Some("Fill with")), 0, 0, 1, 1);
+        let fill_combo = gtk::ComboBoxText::new();
+        for (id, name) in fills {
+            fill_combo.append(Some(id.as_str()), name);
+        }
+        fill_combo.set_active_id(fills.first().map(|(id, _)| id.as_str()));
+        grid.attach(&fill_combo, 1, 0, 1, 1);
+
+        grid.attach(&gtk::Label::new(Some(start_label)), 0, 1, 1, 1);
+        let start_adj = gtk::Adjustment::new(f64::from(start),
+            0.0, 127.0,
+            1.0, 12.0, 0.0);
+        let start_entry = gtk::SpinButton::new(Some(&start_adj),
+            1.0, 0);
+        grid.attach(&start_entry, 1, 1, 1, 1);
+
+        let preview = gtk::Label::new(None);
+        preview.set_halign(gtk::Align::Start);
+        grid.attach(&preview, 0, 2, 2, 1);
+
+        let values = Rc::new(values);
+        let update = {
+            let values = values.clone();
+            let fill_combo = fill_combo.clone();
+            let start_adj = start_adj.clone();
+            let preview = preview.clone();
+            move || {
+                let id = fill_combo.get_active_id().unwrap_or_default();
+                if let Some((_, text, uses_start)) = values(id.as_str(), start_adj.get_value() as u8) {
+                    preview.set_text(&text);
+                    start_entry.set_sensitive(uses_start);
+                }
+            }
+        };
+        update();
+        let update = Rc::new(update);
+        {
+            let update = update.clone();
+            fill_combo.connect_changed(move |_combo| update());
+        }
+        start_adj.connect_value_changed(move |_adj| update());
+
+        move || {
+            let id = fill_combo.get_active_id().unwrap_or_default();
+            values(id.as_str(), start_adj.get_value() as u8).map(|(values, _, _)| values)
+        }
+    }).and_then(|values| values)
+}
+
+/// Asks how to fill `count` pads, returning their notes.
+pub fn ask_pad_notes(window: &gtk::ApplicationWindow, root: u8, count: usize) -> Option<Vec<u8>> {
+    let fills = PadFill::all().into_iter().map(|(fill, name)| (fill.id(), name)).collect::<Vec<_>>();
+    ask_fill(window, "Fill Pads", &fills, "Root note", root, move |id, root| {
+        let fill = PadFill::all().into_iter().map(|(fill, _)| fill).find(|fill| fill.id() == id)?;
+        let notes = fill.notes(root, count);
+        let text = notes.iter().enumerate()
+            .map(|(i, note)| format!("PAD {}: {} ({})", i + 1, note_name(*note), note))
+            .collect::<Vec<_>>().join("\n");
+        Some((notes, text, fill.uses_root()))
+    })
+}
+
+/// Asks how to fill `count` knobs, returning their control numbers.
+pub fn ask_knob_controls(window: &gtk::ApplicationWindow, first: u8, count: usize) -> Option<Vec<u8>> {
+    let fills = KnobFill::all().into_iter().map(|(fill, name)| (fill.id(), name)).collect::<Vec<_>>();
+    ask_fill(window, "Fill Knobs", &fills, "First CC", first, move |id, first| {
+        let fill = KnobFill::all().into_iter().map(|(fill, _)| fill).find(|fill| fill.id() == id)?;
+        let controls = fill.controls(first, count);
+        let text = controls.iter().enumerate()
+            .map(|(i, cc)| format!("K{}: CC {}", i + 1, cc))
+            .collect::<Vec<_>>().join("\n");
+        Some((controls, text, match fill {
+            KnobFill::Consecutive => true,
+            KnobFill::Preset(_) => false,
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale(id: &str) -> &'static Scale {
+        SCALES.iter().find(|s| s.id == id).unwrap()
+    }
+
+    #[test]
+    fn chromatic() {
+        assert_eq!(PadFill::Chromatic.notes(36, 8), vec![36, 37, 38, 39, 40, 41, 42, 43]);
+        // Notes past 127 stay at the top
+        assert_eq!(PadFill::Chromatic.notes(124, 6), vec![124, 125, 126, 127, 127, 127]);
+    }
+
+    #[test]
+    fn scales() {
+        assert_eq!(PadFill::Scale(scale("major")).notes(60, 8), vec![60, 62, 64, 65, 67, 69, 71, 72]);
+        assert_eq!(PadFill::Scale(scale("minor")).notes(57, 8), vec![57, 59, 60, 62, 64, 65, 67, 69]);
+        // Five steps, so the next octave starts on the sixth pad
+        assert_eq!(PadFill::Scale(scale("minor-pentatonic")).notes(48, 8), vec![48, 51, 53, 55, 58, 60, 63, 65]);
+        assert_eq!(PadFill::Scale(scale("major")).notes(120, 4), vec![120, 122, 124, 125]);
+        assert_eq!(PadFill::Scale(scale("major")).notes(125, 3), vec![125, 127, 127]);
+    }
+
+    #[test]
+    fn drum_kits() {
+        let kit = &DRUM_KITS[0];
+        assert_eq!(PadFill::DrumKit(kit).notes(0, 8), kit.notes.to_vec());
+        // The root doesn't matter and more pads start the kit over
+        let notes = PadFill::DrumKit(kit).notes(60, 10);
+        assert_eq!(&notes[8..], &kit.notes[..2]);
+    }
+
+    #[test]
+    fn knobs() {
+        assert_eq!(KnobFill::Consecutive.controls(20, 8), vec![20, 21, 22, 23, 24, 25, 26, 27]);
+        assert_eq!(KnobFill::Consecutive.controls(126, 3), vec![126, 127, 127]);
+        let preset = &KNOB_PRESETS[0];
+        assert_eq!(KnobFill::Preset(preset).controls(20, 8), vec![1, 7, 10, 11, 71, 74, 91, 93]);
+    }
+
+    #[test]
+    fn note_names() {
+        assert_eq!(note_name(60), "C3");
+        assert_eq!(note_name(61), "C#3");
+        assert_eq!(note_name(0), "C-2");
+        assert_eq!(note_name(11), "B-2");
+        assert_eq!(note_name(12), "C-1");
+        assert_eq!(note_name(127), "G8");
+    }
+}
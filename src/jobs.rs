@@ -0,0 +1,243 @@
+//! Device I/O off the GTK main loop.
+//!
+//! Fetching, pushing and switching programs wait on the device for up to two seconds, so they're
+//! queued to a worker thread, one at a time so their messages don't interleave. A spinner shows
+//! while jobs are pending, Cancel drops the ones not finished yet, and failures are described in
+//! the error bar.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+use gtk::prelude::*;
+
+type Work = Box<dyn FnOnce() -> Option<Result<Box<dyn Any + Send>, String>> + Send>;
+type Done = Box<dyn FnOnce(Box<dyn Any + Send>)>;
+
+struct Pending {
+    what: String,
+    done: Done,
+}
+
+/// The worker thread and the jobs waiting on it, without the widgets showing them
+struct Queue {
+    work_tx: Sender<(u64, Work)>,
+    results: Receiver<(u64, Option<Result<Box<dyn Any + Send>, String>>)>,
+    pending: RefCell<HashMap<u64, Pending>>,
+    next_id: Cell<u64>,
+    /// Bumped by cancelling, the worker skips jobs queued before
+    generation: Arc<AtomicUsize>,
+}
+
+impl Queue {
+    fn new() -> Queue {
+        let (work_tx, work_rx) = channel::<(u64, Work)>();
+        let (result_tx, results) = channel();
+        thread::spawn(move || {
+            for (id, work) in work_rx {
+                if result_tx.send((id, work())).is_err() {
+                    break;
+                }
+            }
+        });
+        Queue {
+            work_tx,
+            results,
+            pending: RefCell::new(HashMap::new()),
+            next_id: Cell::new(0),
+            generation: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn run<T, W, D>(&self, what: &str, work: W, done: D)
+        where T: Send + 'static, W: FnOnce() -> Result<T, String> + Send + 'static, D: FnOnce(T) + 'static {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.pending.borrow_mut().insert(id, Pending {
+            what: what.to_string(),
+            done: Box::new(move |result| done(*result.downcast::<T>().expect("job result of the wrong type"))),
+        });
+        let generation = self.generation.clone();
+        let queued = generation.load(Ordering::SeqCst);
+        let work: Work = Box::new(move || {
+            if generation.load(Ordering::SeqCst) != queued {
+                return None;
+            }
+            Some(work().map(|result| Box::new(result) as Box<dyn Any + Send>))
+        });
+        debug!("Queued job {}: {}", id, what);
+        if self.work_tx.send((id, work)).is_err() {
+            self.pending.borrow_mut().remove(&id);
+        }
+    }
+
+    /// Drops the jobs not finished yet, giving how many there were
+    fn cancel(&self) -> usize {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.pending.borrow_mut().drain().count()
+    }
+
+    /// Hands finished jobs' results to their `done`, and "Couldn't `what`: error" to `error` for
+    /// those that failed.
+    fn collect<E: FnMut(&str)>(&self, mut error: E) {
+        for (id, result) in self.results.try_iter() {
+            // Not borrowed while `done` runs, it may queue more
+            let pending = self.pending.borrow_mut().remove(&id);
+            let pending = match pending {
+                Some(pending) => pending,
+                // Cancelled
+                None => continue,
+            };
+            match result {
+                Some(Ok(result)) => (pending.done)(result),
+                Some(Err(e)) => error(&format!("Couldn't {}: {}", pending.what, e)),
+                None => {},
+            }
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        !self.pending.borrow().is_empty()
+    }
+}
+
+struct Inner {
+    queue: Queue,
+    spinner: gtk::Spinner,
+    cancel_button: gtk::Button,
+    error_bar: gtk::InfoBar,
+    error_label: gtk::Label,
+}
+
+/// Queue of device operations, cheap to clone into handlers
+#[derive(Clone)]
+pub struct Jobs {
+    inner: Rc<Inner>,
+}
+
+impl Jobs {
+    pub fn new(builder: &gtk::Builder) -> Jobs {
+        let inner = Rc::new(Inner {
+            queue: Queue::new(),
+            spinner: builder.get_object("device-spinner").expect("no device spinner"),
+            cancel_button: builder.get_object("cancel-button").expect("no cancel button"),
+            error_bar: builder.get_object("error-bar").expect("no error bar"),
+            error_label: builder.get_object("error-label").expect("no error label"),
+        });
+        let jobs = Jobs { inner };
+        {
+            let jobs = jobs.clone();
+            jobs.inner.cancel_button.clone().connect_clicked(move |_button| jobs.cancel());
+        }
+        jobs.inner.error_bar.connect_response(|error_bar, _response| error_bar.set_visible(false));
+        {
+            let jobs = jobs.clone();
+            gtk::timeout_add(50, move || {
+                jobs.collect();
+                glib::Continue(true)
+            });
+        }
+        jobs
+    }
+
+    /// Runs `work` on the worker thread and, unless cancelled, hands its result to `done` on the
+    /// main loop. An error is shown as "Couldn't `what`: error".
+    pub fn run<T, W, D>(&self, what: &str, work: W, done: D)
+        where T: Send + 'static, W: FnOnce() -> Result<T, String> + Send + 'static, D: FnOnce(T) + 'static {
+        self.inner.queue.run(what, work, done);
+        self.update();
+    }
+
+    /// Drops the jobs not finished yet, one talking to the device runs to the end but its result
+    /// is ignored.
+    pub fn cancel(&self) {
+        let cancelled = self.inner.queue.cancel();
+        if cancelled > 0 {
+            info!("Cancelled {} device jobs", cancelled);
+        }
+        self.update();
+    }
+
+    pub fn show_error(&self, message: &str) {
+        warn!("{}", message);
+        self.inner.error_label.set_text(message);
+        self.inner.error_bar.set_visible(true);
+    }
+
+    fn collect(&self) {
+        self.inner.queue.collect(|message| self.show_error(message));
+        self.update();
+    }
+
+    fn update(&self) {
+        let busy = self.inner.queue.is_busy();
+        self.inner.spinner.set_visible(busy);
+        self.inner.cancel_button.set_visible(busy);
+        if busy {
+            self.inner.spinner.start();
+        } else {
+            self.inner.spinner.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    /// Collects until every job is done, as the main loop would
+    fn finish(queue: &Queue) -> Vec<String> {
+        let mut errors = Vec::new();
+        let start = Instant::now();
+        while queue.is_busy() {
+            assert!(start.elapsed() < Duration::from_secs(5), "jobs didn't finish");
+            queue.collect(|message| errors.push(message.to_string()));
+            thread::sleep(Duration::from_millis(1));
+        }
+        errors
+    }
+
+    #[test]
+    fn in_order_with_errors() {
+        let queue = Queue::new();
+        let done = Rc::new(RefCell::new(Vec::new()));
+        for i in 0..5u8 {
+            let done = done.clone();
+            queue.run(&format!("run job {}", i), move || {
+                // Later jobs finish quicker, they still wait their turn
+                thread::sleep(Duration::from_millis(u64::from(5 - i)));
+                if i == 2 { Err("no answer".to_string()) } else { Ok(i) }
+            }, move |i| done.borrow_mut().push(i));
+        }
+        assert_eq!(finish(&queue), vec!["Couldn't run job 2: no answer".to_string()]);
+        assert_eq!(*done.borrow(), vec![0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn cancel() {
+        let queue = Queue::new();
+        let done = Rc::new(Cell::new(false));
+        queue.run("wait", || {
+            thread::sleep(Duration::from_millis(50));
+            Ok(())
+        }, |()| {});
+        {
+            let done = done.clone();
+            queue.run("skipped", || Err("ran".to_string()), move |()| done.set(true));
+        }
+        assert_eq!(queue.cancel(), 2);
+        assert!(!queue.is_busy());
+        // Cancelled results are dropped when they come in
+        thread::sleep(Duration::from_millis(100));
+        queue.collect(|message| panic!("cancelled job reported \"{}\"", message));
+        assert!(!done.get());
+    }
+}
@@ -0,0 +1,308 @@
+//! Preset library.
+//!
+//! Presets are program files with metadata kept in one directory under the user's data
+//! directory, so they can be shared by copying files. The window lists them in a searchable
+//! panel they're dragged from onto a program page, and the `library` and `apply` commands list
+//! and send them from the command line.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use gtk::prelude::*;
+
+use crate::program_file::{self, Metadata, Names, ProgramFile};
+use crate::Program;
+
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub path: PathBuf,
+    pub program: Program,
+    pub names: Names,
+    pub metadata: Metadata,
+}
+
+impl Preset {
+    fn load(path: PathBuf) -> io::Result<Preset> {
+        let file = ProgramFile::load(&path)?;
+        Ok(Preset {
+            path,
+            program: file.program,
+            names: file.names,
+            metadata: file.metadata,
+        })
+    }
+
+    /// The metadata's name or, without one, the file's
+    pub fn name(&self) -> String {
+        if !self.metadata.name.is_empty() {
+            return self.metadata.name.clone();
+        }
+        let file_name = self.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        file_name.trim_end_matches(&format!(".{}", program_file::EXTENSION)).to_string()
+    }
+
+    /// Instrument, tags and author on one line
+    pub fn details(&self) -> String {
+        let metadata = &self.metadata;
+        let mut details = Vec::new();
+        if !metadata.instrument.is_empty() {
+            details.push(metadata.instrument.clone());
+        }
+        if !metadata.tags.is_empty() {
+            details.push(metadata.tags.iter().map(|t| format!("#{}", t)).collect::<Vec<_>>().join(" "));
+        }
+        if !metadata.author.is_empty() {
+            details.push(format!("by {}", metadata.author));
+        }
+        details.join(" · ")
+    }
+
+    /// Whether every word of `query` is found in the name, author, description or instrument,
+    /// ignoring case, or, written `tag:word` or `#word`, is one of the tags. A `#` alone is
+    /// skipped.
+    pub fn matches(&self, query: &str) -> bool {
+        let metadata = &self.metadata;
+        let text = [self.name(), metadata.author.clone(), metadata.description.clone(), metadata.instrument.clone()]
+            .join("\n").to_lowercase();
+        query.to_lowercase().split_whitespace().all(|word| {
+            match word.trim_start_matches("tag:").trim_start_matches('#') {
+                "" => true,
+                tag if tag.len() < word.len() => metadata.tags.iter().any(|t| t.to_lowercase() == tag),
+                _ => text.contains(word) || metadata.tags.iter().any(|t| t.to_lowercase().contains(word)),
+            }
+        })
+    }
+}
+
+pub fn dir() -> PathBuf {
+    glib::get_user_data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(env!("CARGO_PKG_NAME"))
+        .join("library")
+}
+
+/// Presets in the library by name, files that can't be read are left out
+pub fn list() -> Vec<Preset> {
+    let entries = match fs::read_dir(dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut presets = entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.to_string_lossy().ends_with(program_file::EXTENSION))
+        .filter_map(|path| match Preset::load(path.clone()) {
+            Ok(preset) => Some(preset),
+            Err(e) => {
+                warn!("Skipping {} in the library: {}", path.display(), e);
+                None
+            },
+        })
+        .collect::<Vec<_>>();
+    presets.sort_by_key(|p| p.name().to_lowercase());
+    presets
+}
+
+/// The preset called `name`, or the only one with `name` in its name
+pub fn find(name: &str) -> Result<Preset, String> {
+    find_in(list(), name)
+}
+
+fn find_in(presets: Vec<Preset>, name: &str) -> Result<Preset, String> {
+    let lower = name.to_lowercase();
+    if let Some(preset) = presets.iter().find(|p| p.name().to_lowercase() == lower) {
+        return Ok(preset.clone());
+    }
+    let mut found = presets.into_iter().filter(|p| p.name().to_lowercase().contains(&lower)).collect::<Vec<_>>();
+    match found.len() {
+        0 => Err(format!("no preset called \"{}\" in {}", name, dir().display())),
+        1 => Ok(found.remove(0)),
+        _ => Err(format!("\"{}\" could be {}", name, found.iter().map(|p| p.name()).collect::<Vec<_>>().join(", "))),
+    }
+}
+
+/// Saves a preset under a file name made from its name, not replacing another.
+pub fn add(program: &Program, names: &Names, metadata: &Metadata) -> io::Result<PathBuf> {
+    add_to(&dir(), program, names, metadata)
+}
+
+fn add_to(dir: &Path, program: &Program, names: &Names, metadata: &Metadata) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let stem = metadata.name.chars()
+        .map(|c| if c.is_alphanumeric() || c == ' ' || c == '-' { c } else { '_' })
+        .collect::<String>();
+    let stem = if stem.trim().is_empty() { "Preset".to_string() } else { stem.trim().to_string() };
+    let path = (1..).map(|n| match n {
+            1 => dir.join(format!("{}.{}", stem, program_file::EXTENSION)),
+            n => dir.join(format!("{} {}.{}", stem, n, program_file::EXTENSION)),
+        })
+        .find(|path| !path.exists())
+        .expect("some file name is free");
+    let file = ProgramFile {
+        metadata: metadata.clone(),
+        ..ProgramFile::new(program, names)
+    };
+    file.save(&path)?;
+    Ok(path)
+}
+
+/// Asks for a new preset's metadata.
+pub fn ask_metadata(window: &gtk::ApplicationWindow, name: &str) -> Option<Metadata> {
+    crate::form_dialog(window, "Add to Library", "_Add", |grid| {
+        let fields = ["Name", "Author", "Tags", "Instrument", "Description"].iter().enumerate().map(|(row, label)| {
+            grid.attach(&gtk::Label::new(Some(*label)), 0, row as i32, 1, 1);
+            let entry = gtk::Entry::new();
+            entry.set_activates_default(true);
+            grid.attach(&entry, 1, row as i32, 1, 1);
+            entry
+        }).collect::<Vec<_>>();
+        fields[0].set_text(name);
+        fields[2].set_placeholder_text("Separated by commas");
+
+        move || {
+            let text = |i: usize| fields[i].get_text().map(|t| t.trim().to_string()).unwrap_or_default();
+            Metadata {
+                name: text(0),
+                author: text(1),
+                tags: text(2).split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect(),
+                instrument: text(3),
+                description: text(4),
+            }
+        }
+    })
+}
+
+/// Target library rows are dragged with, carrying the preset's path
+pub static DRAG_TARGET: &str = "lpd8-config/preset";
+
+/// The panel's list, filled from the library directory
+#[derive(Clone)]
+pub struct Panel {
+    list: gtk::ListStore,
+    view: gtk::TreeView,
+    search: gtk::SearchEntry,
+}
+
+impl Panel {
+    /// Reads the library again and lists the presets matching the search.
+    pub fn refresh(&self) {
+        let query = self.search.get_text().unwrap_or_default();
+        self.list.clear();
+        for preset in list().iter().filter(|p| p.matches(&query)) {
+            self.list.insert_with_values(None, &[0, 1, 2, 3], &[
+                &preset.name(),
+                &preset.details(),
+                &preset.path.to_string_lossy().into_owned(),
+                &preset.metadata.description]);
+        }
+    }
+
+    /// Calls `f` with a preset double-clicked.
+    pub fn connect_activated<F: Fn(&Preset) + 'static>(&self, f: F) {
+        self.view.connect_row_activated(move |view, _path, _column| {
+            if let Some(preset) = selected_path(view).and_then(|path| Preset::load(path).ok()) {
+                f(&preset);
+            }
+        });
+    }
+}
+
+fn selected_path(view: &gtk::TreeView) -> Option<PathBuf> {
+    let (model, it) = view.get_selection().get_selected()?;
+    model.get_value(&it, 2).get::<String>().map(PathBuf::from)
+}
+
+/// Fills the library panel and lets its rows be dragged.
+pub fn connect_panel(builder: &gtk::Builder) -> Panel {
+    let list: gtk::ListStore = builder.get_object("library-list").expect("no library list model");
+    let view: gtk::TreeView = builder.get_object("library-view").expect("no library view");
+    let search: gtk::SearchEntry = builder.get_object("library-search").expect("no library search");
+    let panel = Panel { list, view: view.clone(), search };
+
+    {
+        let panel = panel.clone();
+        panel.search.clone().connect_search_changed(move |_search| panel.refresh());
+    }
+
+    let targets = [gtk::TargetEntry::new(DRAG_TARGET, gtk::TargetFlags::SAME_APP, 0)];
+    view.enable_model_drag_source(gdk::ModifierType::BUTTON1_MASK, &targets, gdk::DragAction::COPY);
+    view.connect_drag_data_get(move |view, _context, data, _info, _time| {
+        if let Some(path) = selected_path(view) {
+            data.set_text(&path.to_string_lossy());
+        }
+    });
+
+    panel.refresh();
+    panel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+
+    fn preset(file_name: &str, metadata: Metadata) -> Preset {
+        Preset {
+            path: PathBuf::from(file_name),
+            program: Program::default(),
+            names: Names::default(),
+            metadata,
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let preset = preset("Bass Lines.lpd8.json", Metadata {
+            author: "Ana".to_string(),
+            tags: vec!["Techno".to_string(), "drums".to_string()],
+            instrument: "TR-8".to_string(),
+            ..Metadata::default()
+        });
+        assert_eq!(preset.name(), "Bass Lines");
+        assert!(preset.matches(""));
+        assert!(preset.matches("bass ana"));
+        assert!(preset.matches("tr-8"));
+        assert!(!preset.matches("bass keys"));
+        // Tags match whole, plain words anywhere in them
+        assert!(preset.matches("#techno"));
+        assert!(preset.matches("tag:Drums bass"));
+        assert!(!preset.matches("#tech"));
+        assert!(preset.matches("tech"));
+        assert!(!preset.matches("#bass"));
+        // A bare # is being typed, it doesn't hide everything
+        assert!(preset.matches("#"));
+        assert!(preset.matches("bass #"));
+        assert!(preset.matches("tag:"));
+    }
+
+    #[test]
+    fn finding() {
+        let named = |name: &str| preset("x.lpd8.json", Metadata { name: name.to_string(), ..Metadata::default() });
+        let presets = vec![named("Drums"), named("Drums 2"), named("Keys")];
+        assert_eq!(find_in(presets.clone(), "drums").unwrap().name(), "Drums");
+        assert_eq!(find_in(presets.clone(), "key").unwrap().name(), "Keys");
+        assert_eq!(find_in(presets.clone(), "2").unwrap().name(), "Drums 2");
+        assert!(find_in(presets.clone(), "rum").is_err());
+        assert!(find_in(presets, "bass").is_err());
+    }
+
+    #[test]
+    fn file_names() {
+        let dir = env::temp_dir().join(format!("lpd8-library-test-{}", process::id()));
+        let add = |name: &str| {
+            let metadata = Metadata { name: name.to_string(), ..Metadata::default() };
+            let path = add_to(&dir, &Program::default(), &Names::default(), &metadata).unwrap();
+            path.file_name().unwrap().to_string_lossy().into_owned()
+        };
+        assert_eq!(add("Drums"), "Drums.lpd8.json");
+        // Taken names get a number rather than being replaced
+        assert_eq!(add("Drums"), "Drums 2.lpd8.json");
+        assert_eq!(add("Drums"), "Drums 3.lpd8.json");
+        assert_eq!(add("AC/DC: live"), "AC_DC_ live.lpd8.json");
+        assert_eq!(add("  "), "Preset.lpd8.json");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 5);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+//! Log setup.
+//!
+//! Messages go to stderr, or to a file with `--log-file` for attaching to bug reports, at a level
+//! picked with `--verbose`, each repeat showing more. `RUST_LOG` overrides it per target the usual
+//! `env_logger` way, e.g. `RUST_LOG=lpd8_config::protocol=trace` traces only the SysEx exchanged
+//! with devices.
+
+use std::env;
+use std::fs::File;
+
+use env_logger::{Builder, Target, WriteStyle};
+use log::LevelFilter;
+
+/// Target every SysEx message sent or received is traced on, decoded
+pub const PROTOCOL: &str = "lpd8_config::protocol";
+
+/// Options taking no value
+pub static FLAGS: &[&str] = &["--verbose", "-v"];
+
+/// Sets up logging from the `--verbose` and `--log-file` options and `RUST_LOG`.
+pub fn init(args: &[String]) -> Result<(), String> {
+    let mut builder = builder(level(args), env::var("RUST_LOG").ok().as_ref().map(|f| f.as_str()));
+    if let Some(i) = args.iter().position(|a| a == "--log-file") {
+        let path = args.get(i + 1).ok_or_else(|| "--log-file needs a value".to_string())?;
+        let file = File::create(path).map_err(|e| format!("couldn't create {}: {}", path, e))?;
+        builder.target(Target::Pipe(Box::new(file)));
+        builder.write_style(WriteStyle::Never);
+    }
+    builder.try_init().map_err(|e| e.to_string())
+}
+
+/// Level for the number of `--verbose` flags
+fn level(args: &[String]) -> LevelFilter {
+    match args.iter().filter(|a| FLAGS.contains(&a.as_str())).count() {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// `level` for every target, unless `filters` in `RUST_LOG`'s syntax say otherwise
+fn builder(level: LevelFilter, filters: Option<&str>) -> Builder {
+    let mut builder = Builder::new();
+    builder.filter_level(level);
+    if let Some(filters) = filters {
+        builder.parse_filters(filters);
+    }
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use env_logger::Logger;
+    use log::{Level, Log, Metadata};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn enabled(logger: &Logger, target: &str, level: Level) -> bool {
+        logger.enabled(&Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(level(&args(&["lpd8_config", "monitor"])), LevelFilter::Warn);
+        assert_eq!(level(&args(&["lpd8_config", "-v", "monitor"])), LevelFilter::Info);
+        assert_eq!(level(&args(&["lpd8_config", "--verbose", "-v"])), LevelFilter::Debug);
+        assert_eq!(level(&args(&["-v", "-v", "-v"])), LevelFilter::Trace);
+        assert_eq!(level(&args(&["-v", "-v", "-v", "-v"])), LevelFilter::Trace);
+        // Only whole flags count
+        assert_eq!(level(&args(&["-vv", "--verbosity"])), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn filters() {
+        let default = builder(LevelFilter::Info, None).build();
+        assert!(enabled(&default, "lpd8_config", Level::Info));
+        assert!(!enabled(&default, "lpd8_config", Level::Debug));
+
+        let protocol = builder(LevelFilter::Warn, Some("lpd8_config::protocol=trace")).build();
+        assert!(enabled(&protocol, PROTOCOL, Level::Trace));
+        assert!(!enabled(&protocol, "lpd8_config::watcher", Level::Info));
+
+        let everything = builder(LevelFilter::Warn, Some("debug")).build();
+        assert!(enabled(&everything, "lpd8_config::jobs", Level::Debug));
+        assert!(!enabled(&everything, PROTOCOL, Level::Trace));
+    }
+
+    #[test]
+    fn log_file_needs_a_value() {
+        assert_eq!(init(&args(&["lpd8_config", "--log-file"])), Err("--log-file needs a value".to_string()));
+    }
+}
@@ -1,26 +1,82 @@
 extern crate midir;
+#[cfg(feature = "jack")]
+extern crate jack;
 
+extern crate gdk;
 extern crate gio;
 extern crate gtk;
+extern crate cairo;
 
 extern crate crossbeam_utils as cbu;
 
+extern crate serde;
+extern crate serde_json;
+extern crate tungstenite;
+
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+
+mod ardour;
+mod backend;
+mod cli;
+mod device_info;
+mod emulator;
+mod fill;
+mod jobs;
+mod library;
+mod logging;
+mod mapping;
+mod mixxx;
+mod mk2;
+mod monitor;
+mod osc;
+mod overlay;
+mod profile;
+mod program_file;
+mod recorder;
+mod script;
+mod settings;
+mod transport;
+mod watcher;
+mod websocket;
+
 use std::thread;
 
-use std::sync::mpsc::channel;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use std::collections::HashMap;
+
+use std::sync::mpsc::{channel, TryRecvError};
 use std::sync::Mutex;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use std::time::Duration;
 
 use std::ops::Range;
 
+use std::fs;
+use std::path::PathBuf;
+
 use gio::prelude::*;
 use gtk::prelude::*;
 
-use midir::{MidiInput, MidiOutput};
+use backend::{Backend, InputConnection};
+
+use serde::{Deserialize, Serialize};
 
-// TODO: Work on jack coremidi backend, SysEx max 66 bytes
+use device_info::DeviceInfo;
+use jobs::Jobs;
+use mk2::{Mk2Settings, Pressure, Rgb};
+use monitor::{Direction, Monitor};
+use program_file::{Control, Names, ProgramFile};
+use profile::{Codec, Profile};
+use recorder::Recorder;
+use settings::{LastDevice, Settings};
+use transport::Reassembler;
+use watcher::PortEvent;
 
 static APP_NAME: &str = "ldp8_config";
 static DEVICE_NAME: &str = "LPD8";
@@ -28,14 +84,15 @@ static UI_SRC: &str = include_str!("lpd8_config.ui");
 
 const BUF_LEN: usize = 1024;
 
-const REQ_DEVICE_INFO: &[u8] = &[0xF0, 0x7E, 0x00, 0x06, 0x01, 0xF7];
 
 const SYSEX_START: &[u8] = &[0xF0];
 const SYSEX_END: &[u8] = &[0xF7];
 const SYSEX_NON_REALTIME: &[u8] = &[0x7E];
 const MAN_AKAI: &[u8] = &[0x47];
-const MDL_LPD8: &[u8] = &[0x7F, 0x75];
+/// SysEx device id every unit listens to
+const SYSEX_ALL: u8 = 0x7F;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum LPD8Message {
   UploadProgram,
   SetActiveProgram,
@@ -46,20 +103,66 @@ enum LPD8Message {
 impl LPD8Message {
   fn to_pattern(&self) -> u8 {
     match self {
-      UploadProgram => 0x61,
-      SetActiveProgram => 0x62,
-      DownloadProgram => 0x63,
-      GetActiveProgram => 0x64,
+      LPD8Message::UploadProgram => 0x61,
+      LPD8Message::SetActiveProgram => 0x62,
+      LPD8Message::DownloadProgram => 0x63,
+      LPD8Message::GetActiveProgram => 0x64,
+    }
+  }
+
+  fn from_pattern(pattern: u8) -> Option<LPD8Message> {
+    match pattern {
+      0x61 => Some(LPD8Message::UploadProgram),
+      0x62 => Some(LPD8Message::SetActiveProgram),
+      0x63 => Some(LPD8Message::DownloadProgram),
+      0x64 => Some(LPD8Message::GetActiveProgram),
+      _ => None,
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match self {
+      LPD8Message::UploadProgram => "Upload program",
+      LPD8Message::SetActiveProgram => "Set active program",
+      LPD8Message::DownloadProgram => "Download program",
+      LPD8Message::GetActiveProgram => "Get active program",
     }
   }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 struct PortID(usize, String);
-#[derive(Debug, Clone)]
-struct DeviceIDs(PortID, PortID);
+/// Input and output ports, and the SysEx device id the unit is addressed with
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceIDs(PortID, PortID, u8);
 
-#[derive(Debug, Clone, Copy)]
+impl DeviceIDs {
+    /// Output port name without the numbers the backend adds, which change between plug-ins,
+    /// plus the SysEx device id of units that share a port
+    fn key(&self) -> String {
+        let name = backend::display_name(&(self.1).1);
+        if self.2 == SYSEX_ALL { name } else { format!("{} #{}", name, self.2) }
+    }
+    
+    fn label(&self, settings: &Settings) -> String {
+        let key = self.key();
+        settings.nickname(&key).map(|n| n.to_string()).unwrap_or(key)
+    }
+    
+    /// Whether both address the same unit: the same ports and SysEx device id. Port numbers
+    /// are left out, they're looked up again when a device is selected.
+    fn same_unit(&self, other: &DeviceIDs) -> bool {
+        (self.0).1 == (other.0).1 && (self.1).1 == (other.1).1 && self.2 == other.2
+    }
+    
+    /// Matches a nickname exactly or part of a port name
+    fn matches(&self, settings: &Settings, pattern: &str) -> bool {
+        settings.nickname(&self.key()) == Some(pattern)
+            || (self.0).1.contains(pattern) || (self.1).1.contains(pattern)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 struct Pad {
   note: u8,
   program_change: u8,
@@ -78,7 +181,7 @@ impl Default for Pad {
   }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 struct Knob {
   control_change: u8,
   low: u8,
@@ -95,11 +198,14 @@ impl Default for Knob {
   }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 struct Program {
   channel: u8,
   pads: [Pad; 8],
   knobs: [Knob; 8],
+  /// Only sent to an LPD8 MK2
+  #[serde(default)]
+  mk2: Mk2Settings,
 }
 
 impl Default for Program {
@@ -108,19 +214,30 @@ impl Default for Program {
       channel: 0,
       pads: [Pad::default(); 8],
       knobs: [Knob::default(); 8],
+      mk2: Mk2Settings::default(),
     }
   }
 }
-#[derive(Debug, Clone, Copy)]
-enum Response {
-    Program(Program),
-}
-
 //TODO: Wrap Programs in arc mutexes to avaid poison
 struct AppData {
     device_ids: Vec<DeviceIDs>,
     device_id: Arc<Mutex<Option<DeviceIDs>>>,
+    device_profile: Arc<Mutex<&'static Profile>>,
+    in_connection: Option<InputConnection>,
+    monitor: Monitor,
+    recorder: Recorder,
+    osc: osc::Bridge,
+    websocket: websocket::Server,
+    settings: Settings,
     programs: [Arc<Mutex<Program>>; 4],
+    /// Number of the program page shown, 1-4
+    shown_program: Arc<AtomicUsize>,
+    /// Slots of devices that aren't active, by device key
+    device_programs: HashMap<String, [Program; 4]>,
+    /// Identity replies, by device key
+    device_infos: HashMap<String, DeviceInfo>,
+    /// SysEx device id given with `--sysex-id`, every unit answers without one
+    sysex_id: Option<u8>,
 }
 
 impl AppData {
@@ -128,346 +245,703 @@ impl AppData {
         AppData {
             device_ids: Vec::new(),
             device_id: Arc::new(Mutex::new(None)),
+            device_profile: Arc::new(Mutex::new(&profile::LPD8)),
+            in_connection: None,
+            monitor: Monitor::new(),
+            recorder: Recorder::new(),
+            osc: osc::Bridge::new(),
+            websocket: websocket::Server::new(),
+            settings: Settings::load(),
             programs: [
                 Arc::new(Mutex::new(Program::default())),
                 Arc::new(Mutex::new(Program::default())),
                 Arc::new(Mutex::new(Program::default())),
                 Arc::new(Mutex::new(Program::default())),
             ],
+            shown_program: Arc::new(AtomicUsize::new(1)),
+            device_programs: HashMap::new(),
+            device_infos: HashMap::new(),
+            sysex_id: None,
+        }
+    }
+    
+    /// Keeps the active device's slots so switching back to it restores edits that weren't pushed
+    fn stash_programs(&mut self) {
+        if let Some(device_id) = self.device_id.lock().unwrap().clone() {
+            let mut programs = [Program::default(); 4];
+            for (program, program_mutex) in programs.iter_mut().zip(self.programs.iter()) {
+                *program = *program_mutex.lock().unwrap();
+            }
+            self.device_programs.insert(device_id.key(), programs);
         }
     }
 }
 
 // TODO: May be a race, pattern matching response?
-fn transact_sysex(in_name: &str, out_name: &str, request: &[u8], response_filter: &[u8], response_filter_ranges: &[Range<usize>]) -> Option<Vec<u8>> {
+fn transact_sysex(monitor: &Monitor, in_name: &str, out_name: &str, request: &[u8], response_filter: &[u8], response_filter_ranges: &[Range<usize>]) -> Result<Vec<u8>, String> {
     let (tx, rx) = channel();
-    let in_port = MidiInput::new(&APP_NAME).unwrap();
-    let (in_port_id, _) = (0..in_port.port_count())
-        .map(|i| (i, in_port.port_name(i).unwrap()))
-        .find(|(_i, name)| name.clone() == in_name).unwrap();
-    let out_port = MidiOutput::new(&APP_NAME).unwrap();
-    let (out_port_id, _) = (0..out_port.port_count())
-        .map(|i| (i, out_port.port_name(i).unwrap()))
-        .find(|(_i, name)| name.clone() == out_name).unwrap();
-        
-    let _in_connection = in_port.connect(in_port_id, &APP_NAME, |_t, message, (tx, response_filter, response_filter_ranges)| {
-        
-        let mut data_str = String::new();
-        for d in message.iter() {
-            data_str += format!("{:02X?}, ", d).as_str();
-        }
-        println!("{}", data_str);
-        let mut data_str = String::new();
-        for d in response_filter.iter() {
-            data_str += format!("{:02X?}, ", d).as_str();
-        }
-        println!("{}: {}", message.len(), data_str);
-        
-        if message.len() == response_filter.len() && response_filter_ranges.iter().all(|r| message[r.clone()] == response_filter[r.clone()]) {
-            tx.send(message.to_vec()).unwrap();
-        }
-    }, (tx, response_filter.to_vec(), response_filter_ranges.to_vec())).unwrap();
-    println!("{} => {}", in_name, out_name);
+    // The device may have been unplugged since it was found, connecting fails then
+    let _in_connection = {
+        let (response_filter, response_filter_ranges) = (response_filter.to_vec(), response_filter_ranges.to_vec());
+        let (monitor, in_name) = (monitor.clone(), in_name.to_string());
+        let mut reassembler = Reassembler::new();
+        backend::connect_input(&APP_NAME, &in_name.clone(), move |_t, data| {
+            reassembler.feed(data, |message| {
+                monitor.log(Direction::In, &in_name, message);
+                
+                if message.len() == response_filter.len() && response_filter_ranges.iter().all(|r| message[r.clone()] == response_filter[r.clone()]) {
+                    let _ = tx.send(message.to_vec());
+                }
+            });
+        }).map_err(|e| format!("couldn't listen to \"{}\": {}", in_name, e))?
+    };
+    debug!("Asking {} => {}", out_name, in_name);
     thread::sleep(Duration::from_millis(1));
-    let mut out_connection = out_port.connect(out_port_id, &APP_NAME).unwrap();
-    out_connection.send(request).unwrap();
-    if let Ok(response) = rx.recv_timeout(Duration::from_millis(2000)) { Some(response) } else { None }
+    let mut out_connection = backend::connect_output(&APP_NAME, out_name)
+        .map_err(|e| format!("couldn't open \"{}\": {}", out_name, e))?;
+    monitor.log(Direction::Out, out_name, request);
+    transport::send(&mut out_connection, request)?;
+    rx.recv_timeout(Duration::from_millis(2000))
+        .map_err(|_| format!("no answer on \"{}\"", in_name))
 }
 
-fn push_sysex(out_name: &str, request: &[u8]) {
-    let out_port = MidiOutput::new(&APP_NAME).unwrap();
-    let (out_port_id, _) = (0..out_port.port_count())
-        .map(|i| (i, out_port.port_name(i).unwrap()))
-        .find(|(_i, name)| name.clone() == out_name).unwrap();
-    let mut out_connection = out_port.connect(out_port_id, &"push_sysex").unwrap();
+fn push_sysex(monitor: &Monitor, out_name: &str, request: &[u8]) -> Result<(), String> {
+    let mut out_connection = backend::connect_output("push_sysex", out_name)
+        .map_err(|e| format!("couldn't open \"{}\": {}", out_name, e))?;
+    
+    debug!("Sending to '{}'", out_name);
     
-    println!("sending it '{}'", out_name);
-    //thread::sleep(Duration::from_millis(300));
+    monitor.log(Direction::Out, out_name, request);
+    transport::send(&mut out_connection, request)?;
+    transport::message_delay();
+    Ok(())
+}
+
+struct PadView {
+    frame: gtk::Frame,
+    /// Title the pad is dragged by
+    handle: gtk::EventBox,
+    title: gtk::Label,
+    note: gtk::Adjustment,
+    program_change: gtk::Adjustment,
+    control_change: gtk::Adjustment,
+    toggle: gtk::ToggleButton,
+    channel: gtk::Adjustment,
+    off: gtk::ColorButton,
+    on: gtk::ColorButton,
+}
+
+struct KnobView {
+    frame: gtk::Frame,
+    handle: gtk::EventBox,
+    title: gtk::Label,
+    control_change: gtk::Adjustment,
+    low: gtk::Adjustment,
+    high: gtk::Adjustment,
+    channel: gtk::Adjustment,
+}
+
+/// Widgets of one program page, the value handlers write edits back to the program
+struct ProgramView {
+    id: u8,
+    /// The program's page in the stack, titled with its name
+    page: gtk::Box,
+    stack: gtk::Stack,
+    description: gtk::Label,
+    names: RefCell<Names>,
+    channel: gtk::Adjustment,
+    pressure: gtk::ComboBoxText,
+    full_level: gtk::CheckButton,
+    pads: Vec<PadView>,
+    knobs: Vec<KnobView>,
+    /// Shown only while an LPD8 MK2 is selected
+    mk2_widgets: Vec<gtk::Widget>,
+}
+
+impl ProgramView {
+    fn update(&self, program: &Program) {
+        self.channel.set_value(f64::from(program.channel));
+        self.pressure.set_active_id(Some(pressure_id(program.mk2.pressure)));
+        self.full_level.set_active(program.mk2.full_level);
+        for (i, (view, pad)) in self.pads.iter().zip(program.pads.iter()).enumerate() {
+            view.note.set_value(f64::from(pad.note));
+            view.program_change.set_value(f64::from(pad.program_change));
+            view.control_change.set_value(f64::from(pad.control_change));
+            view.toggle.set_active(pad.toggle);
+            view.channel.set_value(f64::from(program.mk2.pads[i].channel));
+            view.off.set_rgba(&rgb_to_rgba(program.mk2.pads[i].off));
+            view.on.set_rgba(&rgb_to_rgba(program.mk2.pads[i].on));
+        }
+        for (i, (view, knob)) in self.knobs.iter().zip(program.knobs.iter()).enumerate() {
+            view.control_change.set_value(f64::from(knob.control_change));
+            view.low.set_value(f64::from(knob.low));
+            view.high.set_value(f64::from(knob.high));
+            view.channel.set_value(f64::from(program.mk2.knobs[i].channel));
+        }
+    }
+    
+    fn names(&self) -> Names {
+        self.names.borrow().clone()
+    }
+    
+    /// Shows the program's and controls' names in the sidebar, below the buttons and on the frames
+    fn set_names(&self, names: &Names) {
+        self.stack.set_child_title(&self.page, Some(names.program_title(self.id).as_str()));
+        self.description.set_text(&names.description);
+        self.description.set_visible(!names.description.is_empty());
+        for (i, pad) in self.pads.iter().enumerate() {
+            pad.title.set_text(&names.pad_title(i));
+        }
+        for (i, knob) in self.knobs.iter().enumerate() {
+            knob.title.set_text(&names.knob_title(i));
+        }
+        *self.names.borrow_mut() = names.clone();
+    }
     
-    let mut data_str = String::new();
-    for d in request.iter() {
-        data_str += format!("{:02X?}, ", d).as_str();
+    /// Shows a program read from a file or the clipboard, with its names
+    fn load(&self, program_mutex: &Mutex<Program>, file: &ProgramFile) {
+        *program_mutex.lock().unwrap() = file.program;
+        self.update(&file.program);
+        self.set_names(&file.names);
     }
-    println!("{}: {}", request.len(), data_str);
     
-    out_connection.send(request).unwrap();
-    thread::sleep(Duration::from_millis(18));
+    /// Shows the controls the device has
+    fn set_profile(&self, profile: &Profile) {
+        for widget in self.mk2_widgets.iter() {
+            widget.set_visible(profile.codec == Codec::Mk2);
+        }
+        for (i, pad) in self.pads.iter().enumerate() {
+            pad.frame.set_visible(i < profile.pads);
+        }
+        for (i, knob) in self.knobs.iter().enumerate() {
+            knob.frame.set_visible(i < profile.knobs);
+        }
+    }
 }
 
-fn check_info(message: &[u8]) -> bool {
-    const EXPECTED: &[u8] = &[
-        0xF0,
-        0x7E,
-        0x00, 0x06, 0x02, 0x47, 0x75, 0x00,
-        0x19, 0x00, 0x00, 0x00, 0x66, 0x7F, 0x00, 0x00,
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-        0x00, 0x00, 0xF7,
-    ];
-    //const EXPECTED: &[u8] = &[
-    //    0xF0, 0x7E, 0x00, 0x06, 0x02, 0x47, 0x75, 0x00,
-    //    0x19, 0x00, 0x00, 0x00, 0x66, 0x00, 0x00, 0x00,
-    //    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    //    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    //    0x00, 0x00, 0xF7,
-    //];
-    //const EXPECTED: &[u8] = &[
-    //    0xF0, 0x7E, 0x0A, 0x06, 0x02, 0x47, 0x75, 0x00,
-    //    0x19, 0x00, 0x00, 0x00, 0x66, 0x00, 0x00, 0x00,
-    //    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    //    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-    //    0x00, 0x00, 0xF7,
-    //];
-    const TEST_RANGES: &[Range<usize>] = &[
-        0..2,
-        3..13,
-        14..20,
-    ];
-    message.len() == EXPECTED.len() && TEST_RANGES.iter().all(|r| EXPECTED[r.clone()] == message[r.clone()])
-}
-
-fn download_program(device_id: &DeviceIDs, id: u8) -> Result<Program, ()> {
-    let program_request = SYSEX_START.iter()
-    .chain(MAN_AKAI)
-    .chain(MDL_LPD8)
-    .chain(&[
-      0x63,
-      0x00, 0x01,
-      id,
-    ])
-    .chain(SYSEX_END)
-    .cloned().collect::<Vec<_>>();
-    
-    const RES_EXP: &[u8] = &[
-        0xF0,
-        0x47,
-        0x7F, 0x75,
-        0x63,
-        0x00, 0x3A, 0x01, 0x00, 0x20, 0x01,
-        0x00, 0x00, 0x32, 0x01, 0x01, 0x00, 0x29, 0x00, 0x01, 0x00, 0x2A,
-        0x03, 0x01, 0x00, 0x31, 0x27, 0x01, 0x00, 0x27, 0x00, 0x01, 0x00,
-        0x33, 0x00, 0x01, 0x00, 0x39, 0x02, 0x00, 0x00, 0x01, 0x00, 0x7F,
-        0x07, 0x00, 0x7F, 0x08, 0x00, 0x7F, 0x0A, 0x00, 0x7F, 0x0B, 0x00,
-        0x7F, 0x02, 0x00, 0x7F, 0x04, 0x00, 0x7F, 0x54, 0x00, 0x7F, 0xF7,
-    ];
-    const REC_PROGRAM_TEST_RANGES: &[Range<usize>] = &[
-        0..7,
+fn pressure_id(pressure: Pressure) -> &'static str {
+    match pressure {
+        Pressure::Off => "off",
+        Pressure::Channel => "channel",
+        Pressure::Polyphonic => "polyphonic",
+    }
+}
+
+fn rgb_to_rgba(rgb: Rgb) -> gdk::RGBA {
+    gdk::RGBA {
+        red: f64::from(rgb.0) / 255.0,
+        green: f64::from(rgb.1) / 255.0,
+        blue: f64::from(rgb.2) / 255.0,
+        alpha: 1.0,
+    }
+}
+
+fn rgba_to_rgb(rgba: &gdk::RGBA) -> Rgb {
+    let c = |v: f64| (v * 255.0).round() as u8;
+    Rgb(c(rgba.red), c(rgba.green), c(rgba.blue))
+}
+
+/// Spin button that writes its value into the program through `set`
+fn program_spin<F: Fn(&mut Program, u8) + 'static>(program_mutex: &Arc<Mutex<Program>>, value: u8, upper: f64, set: F) -> (gtk::SpinButton, gtk::Adjustment) {
+    let adj = gtk::Adjustment::new(f64::from(value),
+        0.0, upper,
+        1.0, 0.0, 0.0);
+    let entry = gtk::SpinButton::new(Some(&adj),
+        1.0, 0);
+    let program_mutex = program_mutex.clone();
+    adj.connect_value_changed(move |adj| {
+        set(&mut program_mutex.lock().unwrap(), adj.get_value() as u8);
+    });
+    (entry, adj)
+}
+
+fn program_color<F: Fn(&mut Program, Rgb) + 'static>(program_mutex: &Arc<Mutex<Program>>, value: Rgb, set: F) -> gtk::ColorButton {
+    let button = gtk::ColorButton::new_with_rgba(&rgb_to_rgba(value));
+    let program_mutex = program_mutex.clone();
+    button.connect_color_set(move |button| {
+        set(&mut program_mutex.lock().unwrap(), rgba_to_rgb(&button.get_rgba()));
+    });
+    button
+}
+
+/// Frame title pads and knobs are dragged and right-clicked by
+fn control_handle(frame: &gtk::Frame, title: &str) -> (gtk::EventBox, gtk::Label) {
+    let handle = gtk::EventBox::new();
+    let label = gtk::Label::new(Some(title));
+    handle.add(&label);
+    handle.set_tooltip_text("Drag onto another one to copy it there, hold Shift to swap them. Right-click to copy, paste or rename.");
+    frame.set_label_widget(Some(&handle));
+    (handle, label)
+}
+
+/// Copies, or swaps, control `from` onto control `to`
+fn rearrange(program: &mut Program, control: fn(&Program, usize) -> Control, from: usize, to: usize, swap: bool) {
+    let dragged = control(program, from);
+    if swap {
+        control(program, to).apply(program, from);
+    }
+    dragged.apply(program, to);
+}
+
+/// Copies, or swaps, `items[from]` onto `items[to]`, used for the controls' names
+fn rearrange_items<T: Clone>(items: &mut [T], from: usize, to: usize, swap: bool) {
+    if swap {
+        items.swap(from, to);
+    } else {
+        items[to] = items[from].clone();
+    }
+}
+
+fn pad_names(names: &mut Names) -> &mut [String; 8] {
+    &mut names.pads
+}
+
+fn knob_names(names: &mut Names) -> &mut [String; 8] {
+    &mut names.knobs
+}
+
+/// Lets pads be dragged onto pads and knobs onto knobs, and each be copied and pasted on its own
+/// through the clipboard.
+fn connect_rearranging(window: &gtk::ApplicationWindow, view: &Rc<ProgramView>, program_mutex: &Arc<Mutex<Program>>, jobs: &Jobs) {
+    let pads = view.pads.iter().map(|pad| (pad.frame.clone(), pad.handle.clone())).collect::<Vec<_>>();
+    let knobs = view.knobs.iter().map(|knob| (knob.frame.clone(), knob.handle.clone())).collect::<Vec<_>>();
+    type Kind = (&'static str, fn(&Program, usize) -> Control, fn(&mut Names) -> &mut [String; 8], Vec<(gtk::Frame, gtk::EventBox)>);
+    let kinds: Vec<Kind> = vec![
+        ("pad", Control::pad, pad_names, pads),
+        ("knob", Control::knob, knob_names, knobs),
     ];
-    let response = transact_sysex(&(device_id.0).1, &(device_id.1).1,
-        &program_request, RES_EXP, &REC_PROGRAM_TEST_RANGES);
+    let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+    for (kind, control, names_of, frames) in kinds {
+        let targets = [gtk::TargetEntry::new(&format!("lpd8-config/{}", kind), gtk::TargetFlags::SAME_APP, 0)];
+        let actions = gdk::DragAction::COPY | gdk::DragAction::MOVE;
+        for (i, (frame, handle)) in frames.into_iter().enumerate() {
+            handle.drag_source_set(gdk::ModifierType::BUTTON1_MASK, &targets, actions);
+            handle.connect_drag_data_get(move |_handle, _context, data, _info, _time| {
+                data.set_text(&i.to_string());
+            });
+            frame.drag_dest_set(gtk::DestDefaults::ALL, &targets, actions);
+            {
+                let view = view.clone();
+                let program_mutex = program_mutex.clone();
+                frame.connect_drag_data_received(move |_frame, context, _x, _y, data, _info, _time| {
+                    let from = match data.get_text().and_then(|text| text.parse::<usize>().ok()) {
+                        Some(from) if from != i => from,
+                        _ => return,
+                    };
+                    // Copied by default, moving swaps them
+                    let swap = context.get_selected_action() == gdk::DragAction::MOVE;
+                    let program = {
+                        let mut program = program_mutex.lock().unwrap();
+                        rearrange(&mut program, control, from, i, swap);
+                        *program
+                    };
+                    view.update(&program);
+                    let mut names = view.names();
+                    rearrange_items(names_of(&mut names), from, i, swap);
+                    view.set_names(&names);
+                });
+            }
+            
+            let menu = gtk::Menu::new();
+            let copy_item = gtk::MenuItem::new_with_label("Copy");
+            let paste_item = gtk::MenuItem::new_with_label("Paste");
+            let rename_item = gtk::MenuItem::new_with_label("Rename…");
+            menu.append(&copy_item);
+            menu.append(&paste_item);
+            menu.append(&rename_item);
+            menu.show_all();
+            {
+                let view = view.clone();
+                let window = window.clone();
+                rename_item.connect_activate(move |_item| {
+                    let mut names = view.names();
+                    let title = match kind {
+                        "pad" => format!("PAD {}", i + 1),
+                        _ => format!("K{}", i + 1),
+                    };
+                    let current = names_of(&mut names)[i].clone();
+                    if let Some(mut answers) = ask_names(&window, &format!("Rename {}", title), &[("Name", &current)]) {
+                        names_of(&mut names)[i] = answers.remove(0);
+                        view.set_names(&names);
+                    }
+                });
+            }
+            {
+                let program_mutex = program_mutex.clone();
+                let clipboard = clipboard.clone();
+                copy_item.connect_activate(move |_item| {
+                    let copied = control(&program_mutex.lock().unwrap(), i);
+                    clipboard.set_text(&copied.text());
+                });
+            }
+            {
+                let view = view.clone();
+                let program_mutex = program_mutex.clone();
+                let clipboard = clipboard.clone();
+                let jobs = jobs.clone();
+                paste_item.connect_activate(move |_item| {
+                    let mut program = *program_mutex.lock().unwrap();
+                    let pasted = clipboard.wait_for_text().and_then(|text| Control::parse_like(&text, &control(&program, i)));
+                    match pasted {
+                        Some(pasted) => {
+                            pasted.apply(&mut program, i);
+                            *program_mutex.lock().unwrap() = program;
+                            view.update(&program);
+                        },
+                        _ => jobs.show_error(&format!("Couldn't paste: the clipboard doesn't hold a {}", kind)),
+                    }
+                });
+            }
+            handle.connect_button_press_event(move |_handle, event| {
+                if event.get_button() != 3 {
+                    return gtk::Inhibit(false);
+                }
+                menu.popup_easy(event.get_button(), event.get_time());
+                gtk::Inhibit(true)
+            });
+        }
+    }
+}
+
+/// Row that only appears for the LPD8 MK2
+fn mk2_row(list_box: &gtk::ListBox, content: &gtk::Box) -> gtk::Widget {
+    let row = gtk::ListBoxRow::new();
+    row.add(content);
+    content.show_all();
+    row.set_no_show_all(true);
+    list_box.add(&row);
+    row.upcast()
+}
+
+/// Index in `AppData.programs` of the program page shown
+fn visible_program(stack: &gtk::Stack) -> Option<usize> {
+    stack.get_visible_child_name()
+        .and_then(|name| name.parse::<usize>().ok())
+        .and_then(|id| id.checked_sub(1))
+}
+
+fn show_error(window: &gtk::ApplicationWindow, message: &str) {
+    let dialog = gtk::MessageDialog::new(Some(window), gtk::DialogFlags::MODAL,
+        gtk::MessageType::Error, gtk::ButtonsType::Close, message);
+    dialog.run();
+    dialog.destroy();
+}
+
+/// Runs a modal dialog around a grid filled by `build`, which returns how to read the answer
+/// once `accept` is pressed.
+fn form_dialog<T, R, B>(window: &gtk::ApplicationWindow, title: &str, accept: &str, build: B) -> Option<T>
+    where B: FnOnce(&gtk::Grid) -> R, R: FnOnce() -> T {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title(title);
+    dialog.set_transient_for(Some(window));
+    dialog.set_modal(true);
+    dialog.add_button("_Cancel", gtk::ResponseType::Cancel.into());
+    dialog.add_button(accept, gtk::ResponseType::Accept.into());
+    dialog.set_default_response(gtk::ResponseType::Accept.into());
+    
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(6);
+    grid.set_column_spacing(6);
+    grid.set_property_margin(6);
+    let read = build(&grid);
+    
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+    
+    let answer = if dialog.run() == gtk::ResponseType::Accept.into() {
+        Some(read())
+    } else { None };
+    dialog.destroy();
+    answer
+}
+
+fn program_file_dialog(window: &gtk::ApplicationWindow, action: gtk::FileChooserAction, current_name: &str) -> Option<PathBuf> {
+    let (title, accept) = match action {
+        gtk::FileChooserAction::Save => ("Save Program", "_Save"),
+        _ => ("Open Program", "_Open"),
+    };
+    let dialog = gtk::FileChooserDialog::new(Some(title), Some(window), action);
+    dialog.add_button("_Cancel", gtk::ResponseType::Cancel.into());
+    dialog.add_button(accept, gtk::ResponseType::Accept.into());
+    dialog.set_do_overwrite_confirmation(true);
+    if !current_name.is_empty() {
+        dialog.set_current_name(current_name);
+    }
     
-    if let Some(response) = response {
-      Ok(parse_program(&response).unwrap())
-    } else { Err(()) }
+    let filter = gtk::FileFilter::new();
+    filter.set_name("LPD8 program");
+    filter.add_pattern(&format!("*.{}", program_file::EXTENSION));
+    dialog.add_filter(&filter);
+    
+    let path = if dialog.run() == gtk::ResponseType::Accept.into() { dialog.get_filename() } else { None };
+    dialog.destroy();
+    path
 }
 
-fn upload_program(device_id: &DeviceIDs, id: u8, program: &Program) {
-    let mut program_upload_request = SYSEX_START.iter()
-    .chain(MAN_AKAI)
-    .chain(MDL_LPD8)
-    .chain(&[
-        0x61, 0x00, 0x3A, id,
-        program.channel
-    ])
-    .cloned().collect::<Vec<_>>();
+/// Asks where to write an export, offering a filter for each `(name, extension)` and showing
+/// `extra` below the files
+fn export_dialog(window: &gtk::ApplicationWindow, title: &str, current_name: &str, filters: &[(&str, &str)], extra: Option<&gtk::Widget>) -> Option<PathBuf> {
+    let dialog = gtk::FileChooserDialog::new(Some(title), Some(window), gtk::FileChooserAction::Save);
+    dialog.add_button("_Cancel", gtk::ResponseType::Cancel.into());
+    dialog.add_button("_Export", gtk::ResponseType::Accept.into());
+    dialog.set_do_overwrite_confirmation(true);
+    dialog.set_current_name(current_name);
     
-    for pad in program.pads.iter() {
-        program_upload_request.extend(&[pad.note, pad.program_change, pad.control_change, if pad.toggle {1} else {0}]);
+    for (name, extension) in filters {
+        let filter = gtk::FileFilter::new();
+        filter.set_name(name);
+        filter.add_pattern(&format!("*.{}", extension));
+        dialog.add_filter(&filter);
     }
-    for knob in program.knobs.iter() {
-        program_upload_request.extend(&[knob.control_change, knob.low, knob.high]);
+    if let Some(extra) = extra {
+        dialog.set_extra_widget(extra);
     }
-    program_upload_request.push(0xF7);
     
-    push_sysex(&(device_id.1).1, &program_upload_request);
-    //transact_sysex(&(device_id.0).1, &(device_id.1).1,
-    //    &program_upload_request, &[], &[]);
+    let path = if dialog.run() == gtk::ResponseType::Accept.into() { dialog.get_filename() } else { None };
+    dialog.destroy();
+    path
+}
+
+/// Asks what the program's controls are assigned to, from `choices` for pads and knobs, then
+/// where to write what `export` makes of them. `kind` is the export's name, file type and
+/// extension.
+fn export_assigned(window: &gtk::ApplicationWindow, view: &ProgramView, kind: (&str, &str, &str), choices: (Vec<mapping::Choice>, Vec<mapping::Choice>), export: &dyn Fn(&Names, &mapping::Assignments) -> Result<String, String>) {
+    let (name, file_type, extension) = kind;
+    let names = view.names();
+    let assignments = match mapping::ask_assignments(window, name, &names, &choices.0, &choices.1) {
+        Some(assignments) => assignments,
+        None => return,
+    };
+    let contents = match export(&names, &assignments) {
+        Ok(contents) => contents,
+        Err(e) => return show_error(window, &format!("Couldn't export: {}", e)),
+    };
+    let current_name = format!("{}.{}", names.program_title(view.id), extension);
+    if let Some(path) = export_dialog(window, &format!("Export {}", name), &current_name, &[(file_type, extension)], None) {
+        if let Err(e) = fs::write(&path, contents) {
+            show_error(window, &format!("Couldn't export {}: {}", path.display(), e));
+        }
+    }
 }
 
-fn set_active_program_id(device_id: &DeviceIDs, p_id: u8) {
-    let set_program_request = [0xF0, 0x47, 0x7F, 0x75, 0x62, 0x00, 0x01, p_id, 0xF7];
-    push_sysex(&(device_id.1).1, &set_program_request);
+fn ask_nickname(window: &gtk::ApplicationWindow, key: &str, current: &str) -> Option<String> {
+    form_dialog(window, "Rename Device", "_Rename", |grid| {
+        grid.attach(&gtk::Label::new(Some(format!("Nickname for {}, leave empty to use the port name", key).as_str())), 0, 0, 1, 1);
+        let entry = gtk::Entry::new();
+        entry.set_text(current);
+        entry.set_activates_default(true);
+        grid.attach(&entry, 0, 1, 1, 1);
+        move || entry.get_text().map(|t| t.trim().to_string())
+    }).and_then(|nickname| nickname)
 }
 
-fn get_active_program_id(device_id: &DeviceIDs) -> Option<u8> {
-    let p_id_request = SYSEX_START.iter()
-    .chain(MAN_AKAI)
-    .chain(MDL_LPD8)
-    .chain(&[
-        0x64, 0x00, 0x00,
-    ])
-    .chain(SYSEX_END)
-    .cloned().collect::<Vec<_>>();
+/// Asks for the texts labelled by `fields`, starting from their current values
+fn ask_names(window: &gtk::ApplicationWindow, title: &str, fields: &[(&str, &str)]) -> Option<Vec<String>> {
+    form_dialog(window, title, "_Rename", |grid| {
+        let entries = fields.iter().enumerate().map(|(row, (label, current))| {
+            grid.attach(&gtk::Label::new(Some(*label)), 0, row as i32, 1, 1);
+            let entry = gtk::Entry::new();
+            entry.set_text(current);
+            entry.set_activates_default(true);
+            grid.attach(&entry, 1, row as i32, 1, 1);
+            entry
+        }).collect::<Vec<_>>();
+        move || entries.iter().map(|entry| entry.get_text().map(|t| t.trim().to_string()).unwrap_or_default()).collect()
+    })
+}
+
+fn ask_preferences(window: &gtk::ApplicationWindow, backend: Backend, delay_ms: u64) -> Option<(Backend, u64)> {
+    form_dialog(window, "Preferences", "_Apply", |grid| {
+        grid.attach(&gtk::Label::new(Some("MIDI system")), 0, 0, 1, 1);
+        let backend_combo = gtk::ComboBoxText::new();
+        for b in Backend::available() {
+            backend_combo.append(Some(b.id()), b.label());
+        }
+        backend_combo.set_active_id(Some(backend.id()));
+        grid.attach(&backend_combo, 1, 0, 1, 1);
     
-    const P_ID_RESP: &[u8] = &[
-        0xF0, 0x47, 0x7F, 0x75, 0x64, 0x00, 0x01, 0x04, 0xF7,
-    ];
-    const P_ID_TEST_RANGES: &[Range<usize>] = &[
-        0..7,
-    ];
-    let response = transact_sysex(&(device_id.0).1, &(device_id.1).1,
-        &p_id_request, P_ID_RESP, &P_ID_TEST_RANGES);
-    if let Some(response) = response {
-      Some(response[7])
-    } else { None }
-}
-
-fn parse_program(message: &[u8]) -> Result<Program, ()> {
-    let pads = {
-        let mut i = message[9..41].chunks(4).map(|p| {
-            Pad {
-                note: p[0],
-                program_change: p[1],
-                control_change: p[2],
-                toggle: p[3] == 1,
-            }
-        });
-        [
-            i.next().unwrap(), i.next().unwrap(), i.next().unwrap(), i.next().unwrap(), 
-            i.next().unwrap(), i.next().unwrap(), i.next().unwrap(), i.next().unwrap(),
-        ]
-    };
-    let knobs = {
-        let mut i = message[41..65].chunks(3).map(|p| {
-            Knob {
-                control_change: p[0],
-                low: p[1],
-                high: p[2],
+        grid.attach(&gtk::Label::new(Some("Pause after SysEx (ms)")), 0, 1, 1, 1);
+        let delay_adj = gtk::Adjustment::new(delay_ms as f64,
+            0.0, 1000.0,
+            1.0, 10.0, 0.0);
+        let delay_entry = gtk::SpinButton::new(Some(&delay_adj),
+            1.0, 0);
+        grid.attach(&delay_entry, 1, 1, 1, 1);
+    
+        move || {
+            let backend = backend_combo.get_active_id()
+                .and_then(|id| Backend::from_name(id.as_str()))
+                .unwrap_or(backend);
+            (backend, delay_adj.get_value() as u64)
+        }
+    })
+}
+
+/// Asks where the OSC bridge sends to and the addresses it uses.
+fn ask_osc(window: &gtk::ApplicationWindow, config: &osc::Config) -> Option<osc::Config> {
+    form_dialog(window, "OSC Bridge", "_Start", |grid| {
+        let fields = [
+            ("Send to (host:port)", config.target.as_str()),
+            ("Pad address", config.pad_address.as_str()),
+            ("Knob address", config.knob_address.as_str()),
+        ];
+        let entries = fields.iter().enumerate().map(|(row, (label, current))| {
+            let label = gtk::Label::new(Some(*label));
+            label.set_halign(gtk::Align::Start);
+            grid.attach(&label, 0, row as i32, 1, 1);
+            let entry = gtk::Entry::new();
+            entry.set_text(current);
+            entry.set_activates_default(true);
+            grid.attach(&entry, 1, row as i32, 1, 1);
+            entry
+        }).collect::<Vec<_>>();
+        let hint = gtk::Label::new(Some("{n} is the pad or knob number, {program} the program's"));
+        hint.set_halign(gtk::Align::Start);
+        grid.attach(&hint, 0, fields.len() as i32, 2, 1);
+    
+        move || {
+            let text = |entry: &gtk::Entry| entry.get_text().map(|t| t.trim().to_string()).unwrap_or_default();
+            osc::Config {
+                target: text(&entries[0]),
+                pad_address: text(&entries[1]),
+                knob_address: text(&entries[2]),
             }
-        });
-        [
-            i.next().unwrap(), i.next().unwrap(), i.next().unwrap(), i.next().unwrap(), 
-            i.next().unwrap(), i.next().unwrap(), i.next().unwrap(), i.next().unwrap(),
-        ]
-    };
+        }
+    })
+}
+
+/// Asks where the WebSocket server listens.
+fn ask_websocket(window: &gtk::ApplicationWindow, config: &websocket::Config) -> Option<websocket::Config> {
+    form_dialog(window, "WebSocket Server", "_Start", |grid| {
+        grid.attach(&gtk::Label::new(Some("Listen on (host:port)")), 0, 0, 1, 1);
+        let entry = gtk::Entry::new();
+        entry.set_text(&config.address);
+        entry.set_activates_default(true);
+        grid.attach(&entry, 1, 0, 1, 1);
+        let hint = gtk::Label::new(Some("Use 0.0.0.0 to let other computers connect"));
+        hint.set_halign(gtk::Align::Start);
+        grid.attach(&hint, 0, 1, 2, 1);
     
-    Ok(Program {
-        channel: message[8],
-        pads,
-        knobs,
+        move || websocket::Config {
+            address: entry.get_text().map(|t| t.trim().to_string()).unwrap_or_default(),
+        }
     })
 }
 
+fn save_recording(window: &gtk::ApplicationWindow, events: &[(u64, Vec<u8>)]) {
+    let dialog = gtk::FileChooserDialog::new(Some("Save Recording"), Some(window), gtk::FileChooserAction::Save);
+    dialog.add_button("_Cancel", gtk::ResponseType::Cancel.into());
+    dialog.add_button("_Save", gtk::ResponseType::Accept.into());
+    dialog.set_do_overwrite_confirmation(true);
+    dialog.set_current_name("recording.mid");
+    
+    let filter = gtk::FileFilter::new();
+    filter.set_name("Standard MIDI File");
+    filter.add_pattern("*.mid");
+    dialog.add_filter(&filter);
+    
+    let format_select = gtk::ComboBoxText::new();
+    format_select.append(Some("0"), "Single track (type 0)");
+    format_select.append(Some("1"), "Track per channel (type 1)");
+    format_select.set_active_id(Some("0"));
+    dialog.set_extra_widget(&format_select);
+    
+    if dialog.run() == gtk::ResponseType::Accept.into() {
+        if let Some(path) = dialog.get_filename() {
+            let format = format_select.get_active_id()
+                .and_then(|id| recorder::Format::from_name(&id))
+                .unwrap_or(recorder::Format::SingleTrack);
+            if let Err(e) = recorder::write(&path, format, events) {
+                error!("Couldn't save recording to {:?}: {}", path, e);
+            }
+        }
+    }
+    dialog.destroy();
+}
+
 fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>) {
     let builder = gtk::Builder::new();
     builder.add_from_string(UI_SRC).unwrap();
     
     let window: gtk::ApplicationWindow = builder.get_object("window").expect("Couldn't get window");
     window.set_application(application);
+    if let Some((width, height)) = app_data_mutex.lock().unwrap().settings.window_size {
+        window.set_default_size(width, height);
+    }
     
     let stack: gtk::Stack = builder.get_object("prog-stack").expect("no prog stack");
     
     let device_list: gtk::ListStore = builder.get_object("device-list").expect("no midi list model");
     let device_select: gtk::ComboBox = builder.get_object("device-select").expect("dev sel not good");
     
-    let midi_in = MidiInput::new(&APP_NAME).unwrap();
-    let midi_out = MidiOutput::new(&APP_NAME).unwrap();
-    
-    println!("in: {}\nout: {}", midi_in.port_count(), midi_out.port_count());
+    let monitor = app_data_mutex.lock().unwrap().monitor.clone();
+    monitor::connect_panel(&builder, &monitor);
+    let monitor_toggle: gtk::ToggleButton = builder.get_object("monitor-toggle").expect("no monitor toggle");
+    monitor_toggle.set_active(app_data_mutex.lock().unwrap().settings.monitor_visible);
     
-    let (input_id, output_id) = {
-        let input_id = (0..midi_in.port_count()).map(|i| (i, midi_in.port_name(i).unwrap_or_default()))
-            //.filter(|(i, name)| true)
-            .collect::<Vec<_>>();
-        let output_id = (0..midi_out.port_count()).map(|i| (i, midi_out.port_name(i).unwrap_or_default()))
-            //.filter(|(i, name)| true)
-            .collect::<Vec<_>>();
-        
-        (input_id, output_id)
-    };
-    
-    for i in input_id.iter() {
-        println!("{:?}", i);
-    }
-    for i in output_id.iter() {
-        println!("{:?}", i);
+    {
+        let record_toggle: gtk::ToggleButton = builder.get_object("record-toggle").expect("no record toggle");
+        let recorder = app_data_mutex.lock().unwrap().recorder.clone();
+        let window = window.clone();
+        record_toggle.connect_toggled(move |record_toggle| {
+            if record_toggle.get_active() {
+                recorder.start();
+            } else {
+                let events = recorder.stop();
+                if !events.is_empty() {
+                    save_recording(&window, &events);
+                }
+            }
+        });
     }
     
     {
-        let (tx, rx) = channel();
-        let _connections = output_id.iter().map(|(i, device_name)| {
-            let i = *i;
-            let device_name = device_name.clone();
-            let name = format!("{}_response:{}", APP_NAME, i);
-            let port = MidiInput::new(&name).unwrap();
-            let tx = tx.clone();
-            port.connect(i, &name, move |_t, data: &[u8], _app_data_mutex| {
-                if check_info(&data) {
-                    tx.send((i, device_name.clone())).unwrap();
-                }
-                let mut data_str = String::new();
-                for d in data.iter() {
-                    data_str += format!("{:02X?}, ", d).as_str();
+        let osc_toggle: gtk::ToggleButton = builder.get_object("osc-toggle").expect("no osc toggle");
+        let app_data_mutex = app_data_mutex.clone();
+        let window = window.clone();
+        osc_toggle.connect_toggled(move |osc_toggle| {
+            let bridge = app_data_mutex.lock().unwrap().osc.clone();
+            if !osc_toggle.get_active() {
+                bridge.stop();
+                return;
+            }
+            let current = app_data_mutex.lock().unwrap().settings.osc.clone();
+            let config = match ask_osc(&window, &current) {
+                Some(config) => config,
+                None => {
+                    osc_toggle.set_active(false);
+                    return;
+                },
+            };
+            if let Err(e) = bridge.start(&config) {
+                show_error(&window, &format!("Couldn't send OSC to {}: {}", config.target, e));
+                osc_toggle.set_active(false);
+                return;
+            }
+            let mut app_data = app_data_mutex.lock().unwrap();
+            if app_data.settings.osc != config {
+                app_data.settings.osc = config;
+                if let Err(e) = app_data.settings.save() {
+                    error!("Couldn't save settings: {}", e);
                 }
-                println!("{}: {}", data.len(), data_str);
-            }, app_data_mutex.clone())
-        }).collect::<Vec<_>>();
-        
-        for (i, device_name) in input_id.iter() {
-            let name = format!("{}_call:{}", APP_NAME, i);
-            let port = MidiOutput::new(&name).unwrap();
-            let mut connection = port.connect(*i, &name).unwrap();
-            connection.send(REQ_DEVICE_INFO).unwrap();
-            if let Ok(id) = rx.recv_timeout(Duration::from_millis(100)) {
-                println!("LPD8 is \"{:?}\"", id);
-                device_list.insert_with_values(None, &[0, 1, 2, 3, 4], &[
-                    &format!("{}/{}", device_name, id.1),
-                    &(*i as u64), &device_name.to_string(),
-                    &(id.0 as u64), &id.1]);
-                let mut app_data = app_data_mutex.lock().unwrap();
-                app_data.device_ids.push(DeviceIDs(PortID(*i, device_name.to_string()), PortID(id.0, id.1)));
-                *app_data.device_id.lock().unwrap() = Some(app_data.device_ids.iter().next().unwrap().clone());
             }
-        }
+        });
     }
     
-    let output_id = output_id.iter().next().unwrap().0;
-    let input_id = input_id.iter().next().unwrap().0;
-    
-    println!("{}", midi_in.port_name(input_id).unwrap());
-    let _in_connection = midi_in.connect(input_id, &APP_NAME, |_t, data, _app_data_mutex| {
-        println!("message");
-        let mut data_str = String::new();
-        for d in data.iter() {
-            data_str += format!("{:02X?}, ", d).as_str();
-        }
-        println!("{}: {}", data.len(), data_str);
-    }, app_data_mutex.clone()).unwrap();
-    let _out_connection = midi_out.connect(output_id, &APP_NAME).unwrap();
-    
-    let initial_p_id = {
-        let app_data = app_data_mutex.lock().unwrap();
-        let device_id_mutex = app_data.device_id.clone();
-        {
-            let device_id = device_id_mutex.lock().unwrap().clone();
-            if let Some(device_id) = device_id {
-                if let Some(id) = get_active_program_id(&device_id) { id } else { 1 }
-            } else { 1 }
-        }
-    };
+    let jobs = Jobs::new(&builder);
+    let library_panel = library::connect_panel(&builder);
     
-    // let (a_send, a_rec) = channel();
+    let script_templates = script::list();
+    let mut views = Vec::new();
     {
         let mut app_data = app_data_mutex.lock().unwrap();
         let device_id_mutex = app_data.device_id.clone();
+        let device_profile_mutex = app_data.device_profile.clone();
         for (i, program_mutex) in app_data.programs.iter_mut().enumerate() {
             let id = (1 + i) as u8;
             
-            let program = {
-                let program = if let Some(device_id) = device_id_mutex.lock().unwrap().clone() {
-                    if let Ok(program) = download_program(&device_id, id) { program } else { Program::default() }
-                } else {
-                    Program::default()
-                };
-                
-                *program_mutex.lock().unwrap() = program;
-                program
-                //let p = download_program(app_data_mutex.clone(), id).unwrap();
-                //let mut program = program_mutex.lock().unwrap();
-                //*program = p;
-                //p.clone()
-            };
-            println!("ez, {:?}", program);
+            // Filled in from the device once one is selected
+            let program = *program_mutex.lock().unwrap();
             
             let layout = gtk::Box::new(gtk::Orientation::Horizontal, 6);
             layout.set_spacing(6);
@@ -476,45 +950,76 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
             let prog_prof = gtk::Box::new(gtk::Orientation::Vertical, 6);
             layout.add(&prog_prof);
             
+            // The program's description, when it has one
+            let description = gtk::Label::new(None);
+            description.set_line_wrap(true);
+            description.set_max_width_chars(20);
+            description.set_no_show_all(true);
+            prog_prof.add(&description);
+            
             let pull_button = gtk::Button::new();
             pull_button.set_label("Fetch");
             prog_prof.add(&pull_button);
             
-            {
-                let program_mutex = program_mutex.clone();
-                let device_id_mutex = device_id_mutex.clone();
-                pull_button.connect_clicked(move |_button| {
-                    if let Some(device_id) = device_id_mutex.lock().unwrap().clone() {
-                        let mut program = program_mutex.lock().unwrap();
-                        *program = download_program(&device_id, id).unwrap();
-                    }
-                    // TODO: update ui
-                    //let program = download_program(app_data_mutex.clone(), id).unwrap();
-                    //println!("{:?}", program);
-                    //{
-                    //    let mut app_data = app_data_mutex.lock().unwrap();
-                    //    *app_data.programs[i].lock().unwrap() = program;
-                    //}
-                    println!("pull {}", i);
-                });
-            }
-            
             let push_button = gtk::Button::new();
             push_button.set_label("Push");
             prog_prof.add(&push_button);
             {
                 let program_mutex = program_mutex.clone();
                 let device_id_mutex = device_id_mutex.clone();
+                let device_profile_mutex = device_profile_mutex.clone();
+                let monitor = monitor.clone();
+                let jobs = jobs.clone();
                 push_button.connect_clicked(move |_button| {
-                    println!("push PROG {}", id);
+                    debug!("Push PROG {}", id);
                     if let Some(device_id) = device_id_mutex.lock().unwrap().clone() {
-                        let program = program_mutex.lock().unwrap();
-                        upload_program(&device_id, id, &program);
+                        let program = *program_mutex.lock().unwrap();
+                        let profile = *device_profile_mutex.lock().unwrap();
+                        let monitor = monitor.clone();
+                        jobs.run(&format!("push PROG {}", id),
+                            move || profile.upload_program(&monitor, &device_id, id, &program),
+                            |()| {});
                     }
-                    // set_program(&output, id, &*program);
                 });
             }
             
+            let open_button = gtk::Button::new();
+            open_button.set_label("Open…");
+            prog_prof.add(&open_button);
+            
+            let save_button = gtk::Button::new();
+            save_button.set_label("Save…");
+            prog_prof.add(&save_button);
+            
+            let fill_pads_button = gtk::Button::new();
+            fill_pads_button.set_label("Fill Pads…");
+            prog_prof.add(&fill_pads_button);
+            
+            let fill_knobs_button = gtk::Button::new();
+            fill_knobs_button.set_label("Fill Knobs…");
+            prog_prof.add(&fill_knobs_button);
+            
+            let export_button = gtk::MenuButton::new();
+            export_button.set_label("Export");
+            let export_menu = gtk::Menu::new();
+            let mut exports = vec![("Overlay…".to_string(), "win.export-overlay".to_string())];
+            exports.extend(mapping::EXPORTERS.iter()
+                .map(|exporter| (format!("{}…", exporter.name), format!("win.export-{}", exporter.id))));
+            exports.extend(script_templates.iter()
+                .map(|template| (format!("{}…", template.name), format!("win.export-script::{}", template.id))));
+            for (label, action) in exports {
+                let item = gtk::MenuItem::new_with_label(&label);
+                item.set_detailed_action_name(&action);
+                export_menu.append(&item);
+            }
+            export_menu.show_all();
+            export_button.set_popup(Some(&export_menu));
+            prog_prof.add(&export_button);
+            
+            let add_preset_button = gtk::Button::new();
+            add_preset_button.set_label("Add to Library…");
+            prog_prof.add(&add_preset_button);
+            
             let chan_adj = gtk::Adjustment::new(f64::from(program.channel),
                 0.0, 127.0,
                 1.0, 0.0, 0.0);
@@ -525,11 +1030,44 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
                 chan_adj.connect_value_changed(move |adj| {
                     let mut program = program_mutex.lock().unwrap();
                     program.channel = adj.get_value() as u8;
-                    println!("{}", adj.get_value());
+                    trace!("{}", adj.get_value());
                 });
             }
             prog_prof.add(&chan_entry);
             
+            let mut mk2_widgets = Vec::new();
+            let pressure = gtk::ComboBoxText::new();
+            pressure.append(Some("off"), "No pressure");
+            pressure.append(Some("channel"), "Channel pressure");
+            pressure.append(Some("polyphonic"), "Polyphonic aftertouch");
+            pressure.set_active_id(Some(pressure_id(program.mk2.pressure)));
+            {
+                let program_mutex = program_mutex.clone();
+                pressure.connect_changed(move |pressure| {
+                    let mut program = program_mutex.lock().unwrap();
+                    program.mk2.pressure = match pressure.get_active_id().as_ref().map(|id| id.as_str()) {
+                        Some("channel") => Pressure::Channel,
+                        Some("polyphonic") => Pressure::Polyphonic,
+                        _ => Pressure::Off,
+                    };
+                });
+            }
+            pressure.set_no_show_all(true);
+            prog_prof.add(&pressure);
+            mk2_widgets.push(pressure.clone().upcast::<gtk::Widget>());
+            
+            let full_level = gtk::CheckButton::new_with_label("Full level");
+            full_level.set_active(program.mk2.full_level);
+            {
+                let program_mutex = program_mutex.clone();
+                full_level.connect_toggled(move |full_level| {
+                    program_mutex.lock().unwrap().mk2.full_level = full_level.get_active();
+                });
+            }
+            full_level.set_no_show_all(true);
+            prog_prof.add(&full_level);
+            mk2_widgets.push(full_level.clone().upcast::<gtk::Widget>());
+            
             let conf_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
             conf_box.set_homogeneous(true);
             layout.add(&conf_box);
@@ -542,12 +1080,13 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
             pad_grid.set_row_spacing(6);
             conf_box.add(&pad_grid);
             
+            let mut pad_views = Vec::new();
             for p_id in 0..8 {
                 let pad = program.pads[p_id as usize];
                 let pad_conf = gtk::Frame::new(format!("PAD {}", p_id + 1).as_str());
+                let (pad_handle, pad_title) = control_handle(&pad_conf, &format!("PAD {}", p_id + 1));
                 let pad_lb = gtk::ListBox::new();
                 pad_lb.set_property("selection-mode", &gtk::SelectionMode::None).unwrap();
-                //pad_lb.set_property("activate-on-single-click", &false); ???
                 
                 let note_adj = gtk::Adjustment::new(f64::from(pad.note),
                     0.0, 127.0,
@@ -559,7 +1098,7 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
                     note_adj.connect_value_changed(move |adj| {
                         let mut program = program_mutex.lock().unwrap();
                         program.pads[p_id as usize].note = adj.get_value() as u8;
-                        println!("{}", adj.get_value());
+                        trace!("{}", adj.get_value());
                     });
                 }
                 pad_lb.add(&note_entry);
@@ -574,7 +1113,7 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
                     prog_adj.connect_value_changed(move |prog_adj| {
                         let mut program = program_mutex.lock().unwrap();
                         program.pads[p_id as usize].program_change = prog_adj.get_value() as u8;
-                        println!("{}", prog_adj.get_value());
+                        trace!("{}", prog_adj.get_value());
                     });
                 }
                 pad_lb.add(&prog_entry);
@@ -590,7 +1129,7 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
                     ctrl_adj.connect_value_changed(move |adj| {
                         let mut program = program_mutex.lock().unwrap();
                         program.pads[p_id as usize].control_change = adj.get_value() as u8;
-                        println!("{}", adj.get_value());
+                        trace!("{}", adj.get_value());
                     });
                 }
                 pad_lb.add(&ctrl_entry);
@@ -616,8 +1155,37 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
                     });
                 }
                 pad_lb.add(&toggle);
+                
+                let mk2_pad = program.mk2.pads[p_id as usize];
+                let mk2_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+                let (pad_chan_entry, pad_chan_adj) = program_spin(program_mutex, mk2_pad.channel, 15.0,
+                    move |program, value| program.mk2.pads[p_id as usize].channel = value);
+                pad_chan_entry.set_tooltip_text("Channel");
+                mk2_box.add(&pad_chan_entry);
+                let off_color = program_color(program_mutex, mk2_pad.off,
+                    move |program, value| program.mk2.pads[p_id as usize].off = value);
+                off_color.set_tooltip_text("Colour when off");
+                mk2_box.add(&off_color);
+                let on_color = program_color(program_mutex, mk2_pad.on,
+                    move |program, value| program.mk2.pads[p_id as usize].on = value);
+                on_color.set_tooltip_text("Colour when on");
+                mk2_box.add(&on_color);
+                mk2_widgets.push(mk2_row(&pad_lb, &mk2_box));
+                
                 pad_conf.add(&pad_lb);
                 pad_grid.attach(&pad_conf, p_id % 4, 1 - p_id / 4, 1, 1);
+                pad_views.push(PadView {
+                    frame: pad_conf,
+                    handle: pad_handle,
+                    title: pad_title,
+                    note: note_adj,
+                    program_change: prog_adj,
+                    control_change: ctrl_adj,
+                    toggle,
+                    channel: pad_chan_adj,
+                    off: off_color,
+                    on: on_color,
+                });
             }
             
             let knob_grid = gtk::Grid::new();
@@ -627,9 +1195,11 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
             knob_grid.set_column_spacing(6);
             knob_grid.set_row_spacing(6);
             conf_box.add(&knob_grid);
+            let mut knob_views = Vec::new();
             for k_id in 0..8 {
                 let knob = program.knobs[k_id as usize];
                 let knob_conf = gtk::Frame::new(format!("K{}", k_id + 1).as_str());
+                let (knob_handle, knob_title) = control_handle(&knob_conf, &format!("K{}", k_id + 1));
                 let knob_lb = gtk::ListBox::new();
                 
                 let ctrl_adj = gtk::Adjustment::new(f64::from(knob.control_change),
@@ -643,7 +1213,7 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
                     ctrl_adj.connect_value_changed(move |adj| {
                         let mut program = program_mutex.lock().unwrap();
                         program.knobs[k_id as usize].control_change = adj.get_value() as u8;
-                        println!("{}", adj.get_value());
+                        trace!("{}", adj.get_value());
                     });
                 }
                 knob_lb.add(&ctrl_entry);
@@ -658,7 +1228,7 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
                     low_adj.connect_value_changed(move |adj| {
                         let mut program = program_mutex.lock().unwrap();
                         program.knobs[k_id as usize].low = adj.get_value() as u8;
-                        println!("{}", adj.get_value());
+                        trace!("{}", adj.get_value());
                     });
                 }
                 knob_lb.add(&low_entry);
@@ -673,26 +1243,496 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
                     high_adj.connect_value_changed(move |adj| {
                         let mut program = program_mutex.lock().unwrap();
                         program.knobs[k_id as usize].high = adj.get_value() as u8;
-                        println!("{}", adj.get_value());
+                        trace!("{}", adj.get_value());
                     });
                 }
                 knob_lb.add(&high_entry);
                 
+                let mk2_box = gtk::Box::new(gtk::Orientation::Horizontal, 6);
+                let (knob_chan_entry, knob_chan_adj) = program_spin(program_mutex, program.mk2.knobs[k_id as usize].channel, 15.0,
+                    move |program, value| program.mk2.knobs[k_id as usize].channel = value);
+                knob_chan_entry.set_tooltip_text("Channel");
+                mk2_box.add(&knob_chan_entry);
+                mk2_widgets.push(mk2_row(&knob_lb, &mk2_box));
+                
                 knob_conf.add(&knob_lb);
                 knob_grid.attach(&knob_conf, k_id % 4, k_id / 4, 1, 1);
+                knob_views.push(KnobView {
+                    frame: knob_conf,
+                    handle: knob_handle,
+                    title: knob_title,
+                    control_change: ctrl_adj,
+                    low: low_adj,
+                    high: high_adj,
+                    channel: knob_chan_adj,
+                });
             }
             
             let name = format!("PROG {}", id);
             stack.add_titled(&layout.clone(), &id.to_string(), &name);
+            
+            let view = Rc::new(ProgramView {
+                id,
+                page: layout.clone(),
+                stack: stack.clone(),
+                description,
+                names: RefCell::new(Names::default()),
+                channel: chan_adj,
+                pressure,
+                full_level,
+                pads: pad_views,
+                knobs: knob_views,
+                mk2_widgets,
+            });
+            connect_rearranging(&window, &view, program_mutex, &jobs);
+            {
+                let program_mutex = program_mutex.clone();
+                let window = window.clone();
+                let library_panel = library_panel.clone();
+                let view = view.clone();
+                add_preset_button.connect_clicked(move |_button| {
+                    let names = view.names();
+                    let name = if names.program.is_empty() { format!("PROG {}", id) } else { names.program.clone() };
+                    if let Some(metadata) = library::ask_metadata(&window, &name) {
+                        let program = *program_mutex.lock().unwrap();
+                        match library::add(&program, &names, &metadata) {
+                            Ok(_path) => library_panel.refresh(),
+                            Err(e) => show_error(&window, &format!("Couldn't add to the library: {}", e)),
+                        }
+                    }
+                });
+            }
+            {
+                let program_mutex = program_mutex.clone();
+                let window = window.clone();
+                let view = view.clone();
+                save_button.connect_clicked(move |_button| {
+                    let name = format!("{}.{}", view.names().program_title(id), program_file::EXTENSION);
+                    if let Some(path) = program_file_dialog(&window, gtk::FileChooserAction::Save, &name) {
+                        let program = *program_mutex.lock().unwrap();
+                        if let Err(e) = ProgramFile::new(&program, &view.names()).save(&path) {
+                            show_error(&window, &format!("Couldn't save {}: {}", path.display(), e));
+                        }
+                    }
+                });
+            }
+            {
+                // Presets dragged from the library
+                let targets = [gtk::TargetEntry::new(library::DRAG_TARGET, gtk::TargetFlags::SAME_APP, 0)];
+                layout.drag_dest_set(gtk::DestDefaults::ALL, &targets, gdk::DragAction::COPY);
+                let program_mutex = program_mutex.clone();
+                let view = view.clone();
+                let jobs = jobs.clone();
+                layout.connect_drag_data_received(move |_layout, _context, _x, _y, data, _info, _time| {
+                    let path = match data.get_text() {
+                        Some(path) => PathBuf::from(path),
+                        None => return,
+                    };
+                    match ProgramFile::load(&path) {
+                        Ok(file) => view.load(&program_mutex, &file),
+                        Err(e) => jobs.show_error(&format!("Couldn't open {}: {}", path.display(), e)),
+                    }
+                });
+            }
+            {
+                let program_mutex = program_mutex.clone();
+                let device_profile_mutex = device_profile_mutex.clone();
+                let window = window.clone();
+                let view = view.clone();
+                fill_pads_button.connect_clicked(move |_button| {
+                    let count = device_profile_mutex.lock().unwrap().pads;
+                    let root = program_mutex.lock().unwrap().pads[0].note;
+                    if let Some(notes) = fill::ask_pad_notes(&window, root, count) {
+                        let program = {
+                            let mut program = program_mutex.lock().unwrap();
+                            for (pad, note) in program.pads.iter_mut().zip(notes) {
+                                pad.note = note;
+                            }
+                            *program
+                        };
+                        view.update(&program);
+                    }
+                });
+            }
+            {
+                let program_mutex = program_mutex.clone();
+                let device_profile_mutex = device_profile_mutex.clone();
+                let window = window.clone();
+                let view = view.clone();
+                fill_knobs_button.connect_clicked(move |_button| {
+                    let count = device_profile_mutex.lock().unwrap().knobs;
+                    let first = program_mutex.lock().unwrap().knobs[0].control_change;
+                    if let Some(controls) = fill::ask_knob_controls(&window, first, count) {
+                        let program = {
+                            let mut program = program_mutex.lock().unwrap();
+                            for (knob, control) in program.knobs.iter_mut().zip(controls) {
+                                knob.control_change = control;
+                            }
+                            *program
+                        };
+                        view.update(&program);
+                    }
+                });
+            }
+            {
+                let program_mutex = program_mutex.clone();
+                let device_id_mutex = device_id_mutex.clone();
+                let device_profile_mutex = device_profile_mutex.clone();
+                let monitor = monitor.clone();
+                let view = view.clone();
+                let jobs = jobs.clone();
+                pull_button.connect_clicked(move |_button| {
+                    if let Some(device_id) = device_id_mutex.lock().unwrap().clone() {
+                        let profile = *device_profile_mutex.lock().unwrap();
+                        let monitor = monitor.clone();
+                        let program_mutex = program_mutex.clone();
+                        let view = view.clone();
+                        jobs.run(&format!("fetch PROG {}", id),
+                            move || profile.download_program(&monitor, &device_id, id),
+                            move |program| {
+                                *program_mutex.lock().unwrap() = program;
+                                view.update(&program);
+                            });
+                    }
+                    debug!("Fetch PROG {}", id);
+                });
+            }
+            {
+                let program_mutex = program_mutex.clone();
+                let window = window.clone();
+                let view = view.clone();
+                open_button.connect_clicked(move |_button| {
+                    if let Some(path) = program_file_dialog(&window, gtk::FileChooserAction::Open, "") {
+                        match ProgramFile::load(&path) {
+                            Ok(file) => view.load(&program_mutex, &file),
+                            Err(e) => show_error(&window, &format!("Couldn't open {}: {}", path.display(), e)),
+                        }
+                    }
+                });
+            }
+            views.push(view);
+        }
+    }
+    
+    
+    
+    let views = Rc::new(views);
+    {
+        let app_data = app_data_mutex.lock().unwrap();
+        if let Some(page) = app_data.settings.last_page.clone() {
+            stack.set_visible_child_name(&page);
+            if let Ok(i @ 1..=4) = page.parse::<usize>() {
+                app_data.shown_program.store(i, Ordering::Relaxed);
+            }
+        }
+    }
+    // Set while the device used last time is being selected again, so its page is kept
+    let restoring = Rc::new(Cell::new(false));
+    
+    {
+        let programs = app_data_mutex.lock().unwrap().programs.clone();
+        let views = views.clone();
+        let stack = stack.clone();
+        library_panel.connect_activated(move |preset| {
+            if let Some(i) = visible_program(&stack) {
+                views[i].load(&programs[i], &ProgramFile::new(&preset.program, &preset.names));
+            }
+        });
+    }
+    
+    {
+        // The shown program is copied as a program file's text, so it can be pasted into another window too
+        let programs = app_data_mutex.lock().unwrap().programs.clone();
+        let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+        
+        let copy_action = gio::SimpleAction::new("copy-program", None);
+        {
+            let programs = programs.clone();
+            let views = views.clone();
+            let stack = stack.clone();
+            let clipboard = clipboard.clone();
+            copy_action.connect_activate(move |_action, _param| {
+                if let Some(i) = visible_program(&stack) {
+                    let program = *programs[i].lock().unwrap();
+                    clipboard.set_text(&ProgramFile::new(&program, &views[i].names()).text());
+                }
+            });
+        }
+        window.add_action(&copy_action);
+        
+        let paste_action = gio::SimpleAction::new("paste-program", None);
+        {
+            let programs = programs.clone();
+            let views = views.clone();
+            let stack = stack.clone();
+            let jobs = jobs.clone();
+            paste_action.connect_activate(move |_action, _param| {
+                let i = match visible_program(&stack) {
+                    Some(i) => i,
+                    None => return,
+                };
+                match clipboard.wait_for_text().map(|text| ProgramFile::parse(&text)) {
+                    Some(Ok(file)) => views[i].load(&programs[i], &file),
+                    _ => jobs.show_error("Couldn't paste: the clipboard doesn't hold a program"),
+                }
+            });
         }
+        window.add_action(&paste_action);
+        
+        // The shown program, or all of them when ticked in the dialog
+        let overlay_action = gio::SimpleAction::new("export-overlay", None);
+        {
+            let programs = programs.clone();
+            let views = views.clone();
+            let stack = stack.clone();
+            let window = window.clone();
+            overlay_action.connect_activate(move |_action, _param| {
+                let i = match visible_program(&stack) {
+                    Some(i) => i,
+                    None => return,
+                };
+                let name = format!("{}.pdf", views[i].names().program_title(i as u8 + 1));
+                let all_check = gtk::CheckButton::new_with_label("All programs");
+                let filters = [("PDF", "pdf"), ("SVG image", "svg")];
+                let path = match export_dialog(&window, "Export Overlay", &name, &filters, Some(all_check.upcast_ref())) {
+                    Some(path) => path,
+                    None => return,
+                };
+                let shown = if all_check.get_active() { 0..programs.len() } else { i..i + 1 };
+                let copies = shown.map(|j| (j as u8 + 1, *programs[j].lock().unwrap(), views[j].names())).collect::<Vec<_>>();
+                let sheets = copies.iter()
+                    .map(|(id, program, names)| overlay::Sheet { id: *id, program, names })
+                    .collect::<Vec<_>>();
+                if let Err(e) = overlay::export(&path, &sheets) {
+                    show_error(&window, &format!("Couldn't export {}: {}", path.display(), e));
+                }
+            });
+        }
+        window.add_action(&overlay_action);
+        
+        // A mapping of the shown program for each exporter, after asking what the controls do
+        for exporter in mapping::EXPORTERS.iter().cloned() {
+            let action = gio::SimpleAction::new(&format!("export-{}", exporter.id), None);
+            let programs = programs.clone();
+            let views = views.clone();
+            let stack = stack.clone();
+            let window = window.clone();
+            action.connect_activate(move |_action, _param| {
+                if let Some(i) = visible_program(&stack) {
+                    let program = *programs[i].lock().unwrap();
+                    let choices = ((exporter.pad_choices)(), (exporter.knob_choices)());
+                    export_assigned(&window, &views[i], (exporter.name, exporter.file_type, exporter.extension), choices,
+                        &|names, assignments| (exporter.export)(views[i].id, &program, names, assignments));
+                }
+            });
+            window.add_action(&action);
+        }
+        
+        // Takes the id of the template to fill in
+        let script_action = gio::SimpleAction::new("export-script", Some(glib::VariantTy::new("s").unwrap()));
+        {
+            let programs = programs.clone();
+            let views = views.clone();
+            let stack = stack.clone();
+            let window = window.clone();
+            script_action.connect_activate(move |_action, param| {
+                let id = match param.as_ref().and_then(|param| param.get::<String>()) {
+                    Some(id) => id,
+                    None => return,
+                };
+                // Read again, it may have been edited since
+                let template = match script::find(&id) {
+                    Ok(template) => template,
+                    Err(e) => return show_error(&window, &format!("Couldn't export: {}", e)),
+                };
+                if let Some(i) = visible_program(&stack) {
+                    let program = *programs[i].lock().unwrap();
+                    let choices = (template.pad_choices.clone(), template.knob_choices.clone());
+                    let file_type = format!("{} file", template.name);
+                    export_assigned(&window, &views[i], (template.name.as_str(), file_type.as_str(), template.extension.as_str()), choices,
+                        &|names, assignments| template.render(views[i].id, &program, names, assignments));
+                }
+            });
+        }
+        window.add_action(&script_action);
+        
+        // Takes the number of the program to swap the shown one with
+        let swap_action = gio::SimpleAction::new("swap-program", Some(glib::VariantTy::new("i").unwrap()));
+        {
+            let programs = programs.clone();
+            let views = views.clone();
+            let stack = stack.clone();
+            swap_action.connect_activate(move |_action, param| {
+                let j = match param.as_ref().and_then(|param| param.get::<i32>()) {
+                    Some(id) if id >= 1 && id as usize <= programs.len() => id as usize - 1,
+                    _ => return,
+                };
+                let i = match visible_program(&stack) {
+                    Some(i) if i != j => i,
+                    _ => return,
+                };
+                let (a, b) = (*programs[i].lock().unwrap(), *programs[j].lock().unwrap());
+                *programs[i].lock().unwrap() = b;
+                *programs[j].lock().unwrap() = a;
+                views[i].update(&b);
+                views[j].update(&a);
+                let (a, b) = (views[i].names(), views[j].names());
+                views[i].set_names(&b);
+                views[j].set_names(&a);
+            });
+        }
+        window.add_action(&swap_action);
+        
+        // Plain Ctrl+C and Ctrl+V stay with the text fields
+        application.set_accels_for_action("win.copy-program", &["<Primary><Shift>c"]);
+        application.set_accels_for_action("win.paste-program", &["<Primary><Shift>v"]);
+        for id in 1..=programs.len() {
+            application.set_accels_for_action(&format!("win.swap-program({})", id), &[&format!("<Primary><Alt>{}", id)]);
+        }
+        
+        let sidebar: gtk::StackSidebar = builder.get_object("prog-sidebar").expect("no prog sidebar");
+        let menu = gtk::Menu::new();
+        let copy_item = gtk::MenuItem::new_with_label("Copy");
+        let paste_item = gtk::MenuItem::new_with_label("Paste");
+        let rename_item = gtk::MenuItem::new_with_label("Rename…");
+        menu.append(&copy_item);
+        menu.append(&paste_item);
+        menu.append(&rename_item);
+        menu.append(&gtk::SeparatorMenuItem::new());
+        {
+            let window = window.clone();
+            copy_item.connect_activate(move |_item| window.activate_action("copy-program", None));
+        }
+        {
+            let window = window.clone();
+            paste_item.connect_activate(move |_item| window.activate_action("paste-program", None));
+        }
+        {
+            let window = window.clone();
+            let views = views.clone();
+            let stack = stack.clone();
+            rename_item.connect_activate(move |_item| {
+                let i = match visible_program(&stack) {
+                    Some(i) => i,
+                    None => return,
+                };
+                let mut names = views[i].names();
+                let fields = [("Name", names.program.as_str()), ("Description", names.description.as_str())];
+                if let Some(answers) = ask_names(&window, &format!("Rename PROG {}", i + 1), &fields) {
+                    names.program = answers[0].clone();
+                    names.description = answers[1].clone();
+                    views[i].set_names(&names);
+                }
+            });
+        }
+        let swap_items = (1..=programs.len() as i32).map(|id| {
+            let item = gtk::MenuItem::new_with_label(&format!("Swap with PROG {}", id));
+            let window = window.clone();
+            item.connect_activate(move |_item| window.activate_action("swap-program", Some(&glib::Variant::from(id))));
+            menu.append(&item);
+            item
+        }).collect::<Vec<_>>();
+        menu.show_all();
+        let stack = stack.clone();
+        sidebar.connect_button_press_event(move |_sidebar, event| {
+            if event.get_button() != 3 {
+                return gtk::Inhibit(false);
+            }
+            if let Some(i) = visible_program(&stack) {
+                copy_item.set_label(&format!("Copy PROG {}", i + 1));
+                paste_item.set_label(&format!("Paste into PROG {}", i + 1));
+                for (j, item) in swap_items.iter().enumerate() {
+                    item.set_visible(j != i);
+                }
+                menu.popup_easy(event.get_button(), event.get_time());
+            }
+            gtk::Inhibit(true)
+        });
+    }
+    let offline_bar: gtk::InfoBar = builder.get_object("offline-bar").expect("no offline bar");
+    let offline_label: gtk::Label = builder.get_object("offline-label").expect("no offline label");
+    let rescan_button: gtk::Button = builder.get_object("rescan-button").expect("no rescan button");
+    let sysex_id = app_data_mutex.lock().unwrap().sysex_id;
+    let watcher = Rc::new(watcher::spawn(monitor.clone(), sysex_id));
+    {
+        let watcher = watcher.clone();
+        rescan_button.connect_clicked(move |_button| watcher.rescan());
     }
     
+    {
+        let info_button: gtk::Button = builder.get_object("info-button").expect("no info button");
+        let app_data_mutex = app_data_mutex.clone();
+        let device_list = device_list.clone();
+        let device_select = device_select.clone();
+        let window = window.clone();
+        info_button.connect_clicked(move |_button| {
+            let it = match device_select.get_active_iter() {
+                Some(it) => it,
+                None => return,
+            };
+            let label = device_list.get_value(&it, 0).get::<String>().unwrap_or_default();
+            let key = device_list.get_value(&it, 5).get::<String>().unwrap_or_default();
+            let info = app_data_mutex.lock().unwrap().device_infos.get(&key).cloned();
+            if let Some(info) = info {
+                let dialog = gtk::MessageDialog::new(Some(&window), gtk::DialogFlags::MODAL,
+                    gtk::MessageType::Info, gtk::ButtonsType::Close, &label);
+                dialog.set_property_secondary_text(Some(info.to_string().as_str()));
+                dialog.run();
+                dialog.destroy();
+            }
+        });
+    }
     
+    {
+        let rename_button: gtk::Button = builder.get_object("rename-button").expect("no rename button");
+        let app_data_mutex = app_data_mutex.clone();
+        let device_list = device_list.clone();
+        let device_select = device_select.clone();
+        let window = window.clone();
+        rename_button.connect_clicked(move |_button| {
+            let it = match device_select.get_active_iter() {
+                Some(it) => it,
+                None => return,
+            };
+            let key = device_list.get_value(&it, 5).get::<String>().unwrap_or_default();
+            let current = app_data_mutex.lock().unwrap().settings.nickname(&key).unwrap_or("").to_string();
+            if let Some(nickname) = ask_nickname(&window, &key, &current) {
+                let mut app_data = app_data_mutex.lock().unwrap();
+                app_data.settings.set_nickname(&key, &nickname);
+                if let Err(e) = app_data.settings.save() {
+                    error!("Couldn't save settings: {}", e);
+                }
+                let label = if nickname.is_empty() { key } else { nickname };
+                device_list.set_value(&it, 0, &label.to_value());
+            }
+        });
+    }
     
-    //glib::timeout_add(5200, move || {
-    //    println!("hi");
-    //    glib::source::Continue(true)
-    //});
+    {
+        let preferences_button: gtk::Button = builder.get_object("preferences-button").expect("no preferences button");
+        let app_data_mutex = app_data_mutex.clone();
+        let watcher = watcher.clone();
+        let window = window.clone();
+        preferences_button.connect_clicked(move |_button| {
+            let delay_ms = app_data_mutex.lock().unwrap().settings.sysex_delay_ms;
+            let (backend, delay_ms) = match ask_preferences(&window, Backend::current(), delay_ms) {
+                Some(preferences) => preferences,
+                None => return,
+            };
+            let mut app_data = app_data_mutex.lock().unwrap();
+            app_data.settings.sysex_delay_ms = delay_ms;
+            app_data.settings.backend = Some(backend);
+            if let Err(e) = app_data.settings.save() {
+                error!("Couldn't save settings: {}", e);
+            }
+            transport::set_message_delay(delay_ms);
+            if backend != Backend::current() {
+                // Devices on the old backend's ports are reported gone, the new ones are probed
+                Backend::set_current(backend);
+                watcher.rescan();
+            }
+        });
+    }
     
     {
         let app_data_mutex = app_data_mutex.clone();
@@ -700,99 +1740,353 @@ fn startup(application: &gtk::Application, app_data_mutex: &Arc<Mutex<AppData>>)
           let app_data = app_data_mutex.lock().unwrap();
           app_data.device_id.clone()
         };
+        let programs = app_data_mutex.lock().unwrap().programs.clone();
+        let views = views.clone();
+        let stack = stack.clone();
+        let offline_bar = offline_bar.clone();
+        let restoring = restoring.clone();
+        let jobs = jobs.clone();
         device_select.connect_changed(move |device_select| {
             // Change in_connection and out_connection
-            let it = device_select.get_active_iter().unwrap();
+            let it = match device_select.get_active_iter() {
+                Some(it) => it,
+                // The active device was unplugged
+                None => return,
+            };
             let device_list = device_select.get_model().unwrap();
             let _device_name = device_list.get_value(&it, 0).get::<String>();
             let in_port_name = device_list.get_value(&it, 2).get::<String>().unwrap();
             let out_port_name = device_list.get_value(&it, 4).get::<String>().unwrap();
-            println!("Device select is in: {}, out: {}", in_port_name, out_port_name);
-            let in_port = MidiInput::new(&APP_NAME).unwrap();
-            let (in_port_id, _) = (0..in_port.port_count())
-                .map(|i| (i, in_port.port_name(i).unwrap()))
-                .find(|(_i, name)| name.clone() == in_port_name).unwrap();
-            let out_port = MidiOutput::new(&APP_NAME).unwrap();
-            let (out_port_id, _) = (0..out_port.port_count())
-                .map(|i| (i, out_port.port_name(i).unwrap()))
-                .find(|(_i, name)| name.clone() == out_port_name).unwrap();
+            let sysex_id = device_list.get_value(&it, 6).get::<i32>().unwrap_or(i32::from(SYSEX_ALL)) as u8;
+            info!("Selected device in: {}, out: {}", in_port_name, out_port_name);
+            let in_port_id = backend::input_names(&APP_NAME).iter().position(|name| *name == in_port_name);
+            let out_port_id = backend::output_names(&APP_NAME).iter().position(|name| *name == out_port_name);
+            let (in_port_id, out_port_id) = match (in_port_id, out_port_id) {
+                (Some(in_port_id), Some(out_port_id)) => (in_port_id, out_port_id),
+                // Gone since the watcher last looked, it will remove the entry shortly
+                _ => return,
+            };
             
-            let d_id = DeviceIDs(PortID(in_port_id, in_port_name), PortID(out_port_id, out_port_name));
+            let d_id = DeviceIDs(PortID(in_port_id, in_port_name.clone()), PortID(out_port_id, out_port_name), sysex_id);
             
+            // Whatever was queued is for the previous device
+            jobs.cancel();
+            app_data_mutex.lock().unwrap().stash_programs();
             *device_id_mutex.lock().unwrap() = Some(d_id);
             
-            /*let in_connection = in_port.connect(in_port_id, &APP_NAME, |t, message, app_data_mutex| {
-                let mut response = None;
-                // TODO: This is a race
-                thread::sleep(Duration::from_millis(1));
-                
-                const RES_EXP: &[u8] = &[
-                    0xF0, 0x47, 0x7F, 0x75, 0x63, 0x00, 0x3A, 0x01, 0x00, 0x20, 0x01,
-                    0x00, 0x00, 0x32, 0x01, 0x01, 0x00, 0x29, 0x00, 0x01, 0x00, 0x2A,
-                    0x03, 0x01, 0x00, 0x31, 0x27, 0x01, 0x00, 0x27, 0x00, 0x01, 0x00,
-                    0x33, 0x00, 0x01, 0x00, 0x39, 0x02, 0x00, 0x00, 0x01, 0x00, 0x7F,
-                    0x07, 0x00, 0x7F, 0x08, 0x00, 0x7F, 0x0A, 0x00, 0x7F, 0x0B, 0x00,
-                    0x7F, 0x02, 0x00, 0x7F, 0x04, 0x00, 0x7F, 0x54, 0x00, 0x7F, 0xF7,
-                ];
-                const REC_PROGRAM_TEST_RANGES: &[Range<usize>] = &[
-                    0..7,
-                ];
-                
-                if REC_PROGRAM_TEST_RANGES.iter().all(|r| RES_EXP[r.clone()] == message[r.clone()]) {
-                    println!("He");
-                    let program = parse_program(&message).unwrap();
-                    response = Some(Response::Program(program));
+            // Keep the device's input open so the monitor, recorder, OSC bridge and WebSocket
+            // server see pads and knobs
+            let (monitor, recorder, osc, websocket, programs, shown_program) = {
+                let app_data = app_data_mutex.lock().unwrap();
+                (app_data.monitor.clone(), app_data.recorder.clone(), app_data.osc.clone(),
+                    app_data.websocket.clone(), app_data.programs.clone(), app_data.shown_program.clone())
+            };
+            let in_connection = {
+                let monitor = monitor.clone();
+                let mut reassembler = Reassembler::new();
+                backend::connect_input(&APP_NAME, &in_port_name.clone(), move |t, data| {
+                    reassembler.feed(data, |message| {
+                        monitor.log(Direction::In, &in_port_name, message);
+                        recorder.push(t, message);
+                        let id = shown_program.load(Ordering::Relaxed);
+                        let program = *programs[id - 1].lock().unwrap();
+                        osc.forward(id as u8, &program, message);
+                        websocket.forward(id as u8, &program, message);
+                    });
+                }).ok()
+            };
+            app_data_mutex.lock().unwrap().in_connection = in_connection;
+            offline_bar.set_visible(false);
+            
+            let device_id = device_id_mutex.lock().unwrap().clone().unwrap();
+            let (stashed, profile) = {
+                let mut app_data = app_data_mutex.lock().unwrap();
+                let last_device = app_data.device_infos.get(&device_id.key())
+                    .map(|info| LastDevice::new(&device_id.key(), info));
+                if last_device.is_some() && app_data.settings.last_device != last_device {
+                    app_data.settings.last_device = last_device;
+                    if let Err(e) = app_data.settings.save() {
+                        error!("Couldn't save settings: {}", e);
+                    }
                 }
-                
-                
-                println!("{:?}", &RES_EXP[0..7]);
-                let mut data_str = String::new();
-                for d in message.iter() {
-                    data_str += format!("{:02X?}, ", d).as_str();
+                let profile = app_data.device_infos.get(&device_id.key())
+                    .and_then(|info| info.profile())
+                    .unwrap_or(&profile::LPD8);
+                *app_data.device_profile.lock().unwrap() = profile;
+                (app_data.device_programs.get(&device_id.key()).cloned(), profile)
+            };
+            for view in views.iter() {
+                view.set_profile(profile);
+            }
+            if let Some(stashed) = stashed {
+                for ((program_mutex, view), program) in programs.iter().zip(views.iter()).zip(stashed.iter()) {
+                    *program_mutex.lock().unwrap() = *program;
+                    view.update(program);
                 }
-                println!("{}", data_str);
-                println!("{:?}", message);
-                if let Some(response) = response {
-                    if let Ok(app_data) = app_data_mutex.try_lock() {
-                        app_data.response_tx.send(response).unwrap();
+            }
+            // Back to where the last session left off, or to what the device has active
+            let restore_page = if restoring.replace(false) {
+                stack.get_visible_child_name().and_then(|name| name.parse::<u8>().ok())
+            } else {
+                None
+            };
+            let count = programs.len() as u8;
+            let programs = programs.clone();
+            let views = views.clone();
+            let stack = stack.clone();
+            jobs.run("read the programs from the device", move || {
+                let downloaded = match stashed {
+                    Some(_) => None,
+                    None => Some((1..=count)
+                        .map(|id| profile.download_program(&monitor, &device_id, id))
+                        .collect::<Result<Vec<_>, _>>()?),
+                };
+                let active = match restore_page {
+                    Some(p_id) => profile.set_active_program(&monitor, &device_id, p_id).map(|()| None)?,
+                    None => profile.get_active_program(&monitor, &device_id),
+                };
+                Ok((downloaded, active))
+            }, move |(downloaded, active)| {
+                if let Some(downloaded) = downloaded {
+                    for ((program_mutex, view), program) in programs.iter().zip(views.iter()).zip(downloaded.iter()) {
+                        *program_mutex.lock().unwrap() = *program;
+                        view.update(program);
                     }
                 }
-                
-            }, app_data_mutex.clone()).unwrap();
-            let out_connection = out_port.connect(out_port_id, &APP_NAME).unwrap();
+                if let Some(p_id) = active {
+                    stack.set_visible_child_name(&p_id.to_string());
+                }
+            });
+        });
+    }
+    
+    {
+        let app_data_mutex = app_data_mutex.clone();
+        let device_list = device_list.clone();
+        let device_select = device_select.clone();
+        let jobs = jobs.clone();
+        gtk::timeout_add(200, move || {
+            // Picked once the whole batch is in, so the device used last time wins over the first one found
+            let mut to_select = None;
+            for event in watcher.events.try_iter() {
+                let mut app_data = app_data_mutex.lock().unwrap();
+                match event {
+                    PortEvent::Added(device, info) => {
+                        let DeviceIDs(PortID(in_id, ref in_name), PortID(out_id, ref out_name), sysex_id) = device;
+                        let it = device_list.insert_with_values(None, &[0, 1, 2, 3, 4, 5, 6], &[
+                            &device.label(&app_data.settings),
+                            &(in_id as u64), in_name,
+                            &(out_id as u64), out_name,
+                            &device.key(),
+                            &i32::from(sysex_id)]);
+                        let last = app_data.settings.last_device.as_ref()
+                            .map_or(false, |last| last.matches(&device.key(), &info));
+                        app_data.device_ids.push(device.clone());
+                        app_data.device_infos.insert(device.key(), info);
+                        drop(app_data);
+                        if device_select.get_active_iter().is_none() {
+                            let better = match to_select {
+                                Some((_, was_last)) => last && !was_last,
+                                None => true,
+                            };
+                            if better {
+                                to_select = Some((it, last));
+                            }
+                        }
+                    },
+                    PortEvent::Removed(device) => {
+                        // Other units sharing the ports stay, they're reported on their own
+                        app_data.device_ids.retain(|d| !d.same_unit(&device));
+                        let active = app_data.device_id.lock().unwrap().as_ref()
+                            .map_or(false, |d| d.same_unit(&device));
+                        if active {
+                            jobs.cancel();
+                            app_data.stash_programs();
+                            *app_data.device_id.lock().unwrap() = None;
+                            app_data.in_connection = None;
+                            offline_label.set_text("The controller was disconnected. Programs can still be edited, opened and saved.");
+                            offline_bar.set_visible(true);
+                        }
+                        drop(app_data);
+                        if let Some(it) = device_list.get_iter_first() {
+                            loop {
+                                let row_in = device_list.get_value(&it, 2).get::<String>();
+                                let row_out = device_list.get_value(&it, 4).get::<String>();
+                                let row_sysex_id = device_list.get_value(&it, 6).get::<i32>();
+                                if row_in.as_ref() == Some(&(device.0).1) && row_out.as_ref() == Some(&(device.1).1)
+                                    && row_sysex_id == Some(i32::from(device.2)) {
+                                    device_list.remove(&it);
+                                    break;
+                                }
+                                if !device_list.iter_next(&it) {
+                                    break;
+                                }
+                            }
+                        }
+                    },
+                }
+            }
+            if let Some((it, last)) = to_select {
+                restoring.set(last);
+                device_select.set_active_iter(Some(&it));
+                restoring.set(false);
+            }
+            glib::Continue(true)
+        });
+    }
+    
+    {
+        let websocket_toggle: gtk::ToggleButton = builder.get_object("websocket-toggle").expect("no websocket toggle");
+        let app_data_mutex = app_data_mutex.clone();
+        let window = window.clone();
+        let stack = stack.clone();
+        let views = views.clone();
+        let jobs = jobs.clone();
+        websocket_toggle.connect_toggled(move |websocket_toggle| {
+            let server = app_data_mutex.lock().unwrap().websocket.clone();
+            if !websocket_toggle.get_active() {
+                server.stop();
+                websocket_toggle.set_tooltip_text("Stream pads and knobs to web pages");
+                return;
+            }
+            let current = app_data_mutex.lock().unwrap().settings.websocket.clone();
+            let config = match ask_websocket(&window, &current) {
+                Some(config) => config,
+                None => {
+                    websocket_toggle.set_active(false);
+                    return;
+                },
+            };
+            let commands = match server.start(&config) {
+                Ok(commands) => commands,
+                Err(e) => {
+                    show_error(&window, &format!("Couldn't listen on {}: {}", config.address, e));
+                    websocket_toggle.set_active(false);
+                    return;
+                },
+            };
+            if let Some(address) = server.address() {
+                websocket_toggle.set_tooltip_text(&format!("Listening on ws://{}", address));
+            }
             {
                 let mut app_data = app_data_mutex.lock().unwrap();
-                app_data.in_connection = Some(in_connection);
-                app_data.out_connection = Some(out_connection);
+                if app_data.settings.websocket != config {
+                    app_data.settings.websocket = config;
+                    if let Err(e) = app_data.settings.save() {
+                        error!("Couldn't save settings: {}", e);
+                    }
+                }
             }
-            println!("Device select is {:?}", device_name);*/
+            
+            let app_data_mutex = app_data_mutex.clone();
+            let programs = app_data_mutex.lock().unwrap().programs.clone();
+            let stack = stack.clone();
+            let views = views.clone();
+            let jobs = jobs.clone();
+            gtk::timeout_add(100, move || {
+                loop {
+                    let command = match commands.try_recv() {
+                        Ok(command) => command,
+                        Err(TryRecvError::Empty) => return glib::Continue(true),
+                        // Stopped and every page gone
+                        Err(TryRecvError::Disconnected) => return glib::Continue(false),
+                    };
+                    match command {
+                        // Switching the page switches the device
+                        websocket::Command::SetActive { program } => stack.set_visible_child_name(&program.to_string()),
+                        websocket::Command::Push { program: id, data } => {
+                            let program_mutex = &programs[usize::from(id) - 1];
+                            if let Some(file) = data {
+                                views[usize::from(id) - 1].load(program_mutex, &file);
+                            }
+                            let app_data = app_data_mutex.lock().unwrap();
+                            if let Some(device_id) = app_data.device_id.lock().unwrap().clone() {
+                                let program = *program_mutex.lock().unwrap();
+                                let profile = *app_data.device_profile.lock().unwrap();
+                                let monitor = app_data.monitor.clone();
+                                jobs.run(&format!("push PROG {}", id),
+                                    move || profile.upload_program(&monitor, &device_id, id, &program),
+                                    |()| {});
+                            }
+                        },
+                    }
+                }
+            });
         });
     }
     
     {
         let app_data_mutex = app_data_mutex.clone();
-        stack.connect_notify("visible-child", move |stack, param| {
+        stack.connect_notify("visible-child", move |stack, _param| {
             // Switch device program
-            let i = stack.get_visible_child_name().unwrap().parse::<u8>().unwrap();
-            println!("visible child is {}", i);
-            let app_data = app_data_mutex.lock().unwrap();
+            let i = match stack.get_visible_child_name().and_then(|name| name.parse::<u8>().ok()) {
+                Some(i) => i,
+                None => return,
+            };
+            debug!("Showing PROG {}", i);
+            let mut app_data = app_data_mutex.lock().unwrap();
+            app_data.settings.last_page = Some(i.to_string());
+            app_data.shown_program.store(usize::from(i), Ordering::Relaxed);
+            // Kept right away, the window isn't always closed before quitting
+            if let Err(e) = app_data.settings.save() {
+                error!("Couldn't save settings: {}", e);
+            }
             if let Some(device_id) = app_data.device_id.lock().unwrap().clone() {
-                set_active_program_id(&device_id, i);
+                let profile = *app_data.device_profile.lock().unwrap();
+                let monitor = app_data.monitor.clone();
+                jobs.run(&format!("switch the device to PROG {}", i),
+                    move || profile.set_active_program(&monitor, &device_id, i),
+                    |()| {});
             }
-            println!("hey {:?} : {:?}", stack, param);
         });
     }
     
-    window.show_all();
+    {
+        let app_data_mutex = app_data_mutex.clone();
+        window.connect_delete_event(move |window, _event| {
+            let mut app_data = app_data_mutex.lock().unwrap();
+            app_data.settings.window_size = Some(window.get_size());
+            app_data.settings.monitor_visible = monitor_toggle.get_active();
+            if let Err(e) = app_data.settings.save() {
+                error!("Couldn't save settings: {}", e);
+            }
+            gtk::Inhibit(false)
+        });
+    }
     
-    device_select.set_active(0);
-    stack.set_visible_child_name(&initial_p_id.to_string());
+    window.show_all();
 }
 
 fn main() {
     use std::env::args;
     
-    let app_data_mutex = Arc::new(Mutex::new(AppData::new()));
+    let mut args = args().collect::<Vec<_>>();
+    if let Err(e) = logging::init(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+    
+    let mut app_data = AppData::new();
+    let session = cli::sysex_id(&args)
+        .and_then(|sysex_id| cli::apply_session_options(&args, &mut app_data.settings).map(|()| sysex_id));
+    match session {
+        Ok(sysex_id) => app_data.sysex_id = sysex_id,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        },
+    }
+    // GTK doesn't know these
+    for option in cli::SESSION_OPTIONS {
+        if let Some(i) = args.iter().position(|a| a == option) {
+            args.drain(i..i + 2);
+        }
+    }
+    args.retain(|a| !logging::FLAGS.contains(&a.as_str()));
+    let app_data_mutex = Arc::new(Mutex::new(app_data));
     
     let application = gtk::Application::new("org.kinloch.colin.lpd8_config",
         gio::ApplicationFlags::empty())
@@ -801,5 +2095,122 @@ fn main() {
     application.connect_startup(move |application| { startup(application, &app_data_mutex) });
     application.connect_activate(|_| {});
     
-    application.run(&args().collect::<Vec<_>>());
+    application.run(&args);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(program: &Program) -> Vec<u8> {
+        program.pads.iter().map(|pad| pad.note).collect()
+    }
+
+    fn numbered() -> Program {
+        let mut program = Program::default();
+        for (i, (pad, knob)) in program.pads.iter_mut().zip(program.knobs.iter_mut()).enumerate() {
+            pad.note = 36 + i as u8;
+            program.mk2.pads[i].channel = i as u8;
+            knob.control_change = 70 + i as u8;
+        }
+        program
+    }
+
+    #[test]
+    fn rearrange_controls() {
+        let mut program = numbered();
+        rearrange(&mut program, Control::pad, 1, 6, true);
+        assert_eq!(notes(&program), vec![36, 42, 38, 39, 40, 41, 37, 43]);
+        // The MK2 settings go along
+        assert_eq!((program.mk2.pads[1].channel, program.mk2.pads[6].channel), (6, 1));
+
+        let mut program = numbered();
+        rearrange(&mut program, Control::pad, 0, 7, false);
+        assert_eq!(notes(&program), vec![36, 37, 38, 39, 40, 41, 42, 36]);
+        assert_eq!(program.mk2.pads[7].channel, 0);
+
+        let mut program = numbered();
+        rearrange(&mut program, Control::knob, 2, 2, true);
+        rearrange(&mut program, Control::knob, 3, 3, false);
+        assert_eq!(notes(&program), notes(&numbered()));
+        assert_eq!(program.knobs.iter().map(|knob| knob.control_change).collect::<Vec<_>>(), (70..78).collect::<Vec<_>>());
+        // Knobs leave the pads alone
+        rearrange(&mut program, Control::knob, 0, 1, true);
+        assert_eq!((program.knobs[0].control_change, program.knobs[1].control_change), (71, 70));
+        assert_eq!(notes(&program), notes(&numbered()));
+    }
+
+    fn device(client: u8, sysex_id: u8) -> DeviceIDs {
+        DeviceIDs(PortID(1, format!("LPD8:LPD8 MIDI 1 {}:0", client)), PortID(2, format!("LPD8:LPD8 MIDI 1 {}:0", client)), sysex_id)
+    }
+
+    #[test]
+    fn device_ids_across_reconnects() {
+        let mut settings = Settings::default();
+        let before = device(20, SYSEX_ALL);
+        assert_eq!(before.key(), "LPD8:LPD8 MIDI 1");
+        settings.set_nickname(&before.key(), "Left");
+
+        // Plugged back in, ALSA gave it another client number
+        let after = DeviceIDs(PortID(3, "LPD8:LPD8 MIDI 1 24:0".to_string()), PortID(5, "LPD8:LPD8 MIDI 1 24:0".to_string()), SYSEX_ALL);
+        assert_eq!(after.key(), before.key());
+        assert_eq!(after.label(&settings), "Left");
+        assert!(after.matches(&settings, "Left"));
+        assert!(!after.same_unit(&before));
+        // Port numbers alone don't matter
+        assert!(device(20, SYSEX_ALL).same_unit(&DeviceIDs(PortID(7, (before.0).1.clone()), PortID(9, (before.1).1.clone()), SYSEX_ALL)));
+    }
+
+    #[test]
+    fn device_ids_behind_a_merge() {
+        let mut settings = Settings::default();
+        let (a, b) = (device(20, 1), device(20, 2));
+        assert_eq!(a.key(), "LPD8:LPD8 MIDI 1 #1");
+        assert_eq!(b.key(), "LPD8:LPD8 MIDI 1 #2");
+        settings.set_nickname(&a.key(), "Left");
+        assert_eq!(a.label(&settings), "Left");
+        assert_eq!(b.label(&settings), "LPD8:LPD8 MIDI 1 #2");
+        assert!(a.matches(&settings, "Left"));
+        assert!(!b.matches(&settings, "Left"));
+        // Part of the port name picks either, the first found wins
+        assert!(a.matches(&settings, "MIDI 1") && b.matches(&settings, "MIDI 1"));
+        assert!(!a.matches(&settings, "Lef"));
+        assert!(!a.same_unit(&b));
+        assert!(a.same_unit(&a.clone()));
+    }
+
+    #[test]
+    fn no_device() {
+        let monitor = Monitor::new();
+        let events = monitor.subscribe();
+        let gone = "LPD8 that isn't plugged in";
+        let request = profile::LPD8.active_program_reply(SYSEX_ALL, 1);
+        assert!(push_sysex(&monitor, gone, &request).is_err());
+        assert!(transact_sysex(&monitor, gone, gone, &request, &request, &[0..2]).is_err());
+
+        let device = DeviceIDs(PortID(0, gone.to_string()), PortID(0, gone.to_string()), SYSEX_ALL);
+        for profile in profile::PROFILES {
+            assert!(profile.download_program(&monitor, &device, 1).is_err(), "{}", profile.id);
+            assert!(profile.upload_program(&monitor, &device, 1, &Program::default()).is_err(), "{}", profile.id);
+        }
+        assert!(profile::LPD8.set_active_program(&monitor, &device, 2).is_err());
+        assert_eq!(profile::LPD8.get_active_program(&monitor, &device), None);
+        // Nothing was sent
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn rearrange_names() {
+        let names = || (1..=8).map(|i| format!("PAD {}", i)).collect::<Vec<_>>();
+        let mut swapped = names();
+        rearrange_items(&mut swapped, 0, 7, true);
+        assert_eq!((swapped[0].as_str(), swapped[7].as_str()), ("PAD 8", "PAD 1"));
+        let mut copied = names();
+        rearrange_items(&mut copied, 0, 7, false);
+        assert_eq!((copied[0].as_str(), copied[7].as_str()), ("PAD 1", "PAD 1"));
+        let mut same = names();
+        rearrange_items(&mut same, 4, 4, true);
+        rearrange_items(&mut same, 5, 5, false);
+        assert_eq!(same, names());
+    }
 }
@@ -0,0 +1,127 @@
+//! What pads and knobs are assigned to in other software.
+//!
+//! The DAW and DJ software exporters turn a program into a mapping for their software, each pad
+//! and knob bound to a target of its own (a Mixxx control, an Ardour action...). Targets are
+//! given by id, picked in a dialog or written `PAD1=ID` and `K1=ID` on the command line.
+
+use gtk::prelude::*;
+
+use crate::program_file::Names;
+use crate::{ardour, mixxx, Program};
+
+/// Software a program can be exported for
+pub struct Exporter {
+    /// Command line command and window action name
+    pub id: &'static str,
+    pub name: &'static str,
+    /// What its files are called in file dialogs
+    pub file_type: &'static str,
+    pub extension: &'static str,
+    /// Targets offered in the dialog
+    pub pad_choices: fn() -> Vec<Choice>,
+    pub knob_choices: fn() -> Vec<Choice>,
+    /// The file for program `id`
+    pub export: fn(id: u8, program: &Program, names: &Names, assignments: &Assignments) -> Result<String, String>,
+}
+
+pub static EXPORTERS: &[&Exporter] = &[&mixxx::EXPORTER, &ardour::EXPORTER];
+
+/// Target id for each control, empty for those left out
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Assignments {
+    pub pads: [String; 8],
+    pub knobs: [String; 8],
+}
+
+impl Assignments {
+    /// Reads `PAD1=ID` and `K1=ID` arguments, case doesn't matter for the control.
+    pub fn parse(args: &[&str]) -> Result<Assignments, String> {
+        let mut assignments = Assignments::default();
+        for arg in args {
+            let (control, target) = match arg.find('=') {
+                Some(i) => (arg[..i].to_uppercase(), arg[i + 1..].trim().to_string()),
+                None => return Err(format!("expected CONTROL=TARGET, got \"{}\"", arg)),
+            };
+            let (targets, number) = match (control.strip_prefix("PAD"), control.strip_prefix('K')) {
+                (Some(number), _) => (&mut assignments.pads, number),
+                (_, Some(number)) => (&mut assignments.knobs, number),
+                _ => return Err(format!("unknown control \"{}\", expected PAD1-8 or K1-8", control)),
+            };
+            match number.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= 8 => targets[n - 1] = target,
+                _ => return Err(format!("unknown control \"{}\", expected PAD1-8 or K1-8", control)),
+            }
+        }
+        Ok(assignments)
+    }
+
+    /// Pad and knob indices with their target, pads first
+    pub fn assigned(&self) -> impl Iterator<Item = (Control, usize, &str)> {
+        let pads = self.pads.iter().enumerate().map(|(i, t)| (Control::Pad, i, t.as_str()));
+        let knobs = self.knobs.iter().enumerate().map(|(i, t)| (Control::Knob, i, t.as_str()));
+        pads.chain(knobs).filter(|(_, _, target)| !target.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Pad,
+    Knob,
+}
+
+impl Control {
+    pub fn title(self, names: &Names, i: usize) -> String {
+        match self {
+            Control::Pad => names.pad_title(i),
+            Control::Knob => names.knob_title(i),
+        }
+    }
+}
+
+/// Target offered in the dialog
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub id: String,
+    pub label: String,
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Asks what each control is assigned to, from `pad_choices` and `knob_choices` or typed in.
+pub fn ask_assignments(window: &gtk::ApplicationWindow, title: &str, names: &Names, pad_choices: &[Choice], knob_choices: &[Choice]) -> Option<Assignments> {
+    crate::form_dialog(window, title, "_Export", |grid| {
+        let combo = |row: i32, column: i32, label: &str, choices: &[Choice]| {
+            let label = gtk::Label::new(Some(label));
+            label.set_halign(gtk::Align::Start);
+            grid.attach(&label, column, row, 1, 1);
+            let combo = gtk::ComboBoxText::new_with_entry();
+            combo.append(Some(""), "Nothing");
+            for choice in choices {
+                combo.append(Some(choice.id.as_str()), &choice.label);
+            }
+            combo.set_active_id(Some(""));
+            grid.attach(&combo, column + 1, row, 1, 1);
+            combo
+        };
+        let pads = (0..8).map(|i| combo(i as i32, 0, &names.pad_title(i), pad_choices)).collect::<Vec<_>>();
+        let knobs = (0..8).map(|i| combo(i as i32, 2, &names.knob_title(i), knob_choices)).collect::<Vec<_>>();
+
+        move || {
+            // A typed in target has no id
+            let target = |combo: &gtk::ComboBoxText| match combo.get_active_id() {
+                Some(id) => id.to_string(),
+                None => combo.get_active_text().map(|t| t.trim().to_string()).unwrap_or_default(),
+            };
+            let mut assignments = Assignments::default();
+            for (id, combo) in assignments.pads.iter_mut().zip(&pads) {
+                *id = target(combo);
+            }
+            for (id, combo) in assignments.knobs.iter_mut().zip(&knobs) {
+                *id = target(combo);
+            }
+            assignments
+        }
+    })
+}
@@ -0,0 +1,198 @@
+//! Mixxx controller mappings.
+//!
+//! Writes a `.midi.xml` mapping binding each assigned pad's note and knob's CC, on the program's
+//! channel, to a Mixxx control. Targets are ids from the lists below or any control written
+//! `[Group],key` as in Mixxx's documentation.
+
+use crate::mapping::{escape, Assignments, Choice, Control, Exporter};
+use crate::program_file::Names;
+use crate::Program;
+
+pub static EXPORTER: Exporter = Exporter {
+    id: "mixxx",
+    name: "Mixxx Mapping",
+    file_type: "Mixxx mapping",
+    extension: "midi.xml",
+    pad_choices: || choices(PAD_TARGETS),
+    knob_choices: || choices(KNOB_TARGETS),
+    export,
+};
+
+pub struct Target {
+    pub id: &'static str,
+    pub label: &'static str,
+    pub group: &'static str,
+    pub key: &'static str,
+}
+
+macro_rules! target {
+    ($id:expr, $label:expr, $group:expr, $key:expr) => {
+        Target { id: $id, label: $label, group: $group, key: $key }
+    };
+}
+
+/// Buttons, for pads
+pub static PAD_TARGETS: &[Target] = &[
+    target!("deck1-play", "Deck 1 play", "[Channel1]", "play"),
+    target!("deck1-cue", "Deck 1 cue", "[Channel1]", "cue_default"),
+    target!("deck1-sync", "Deck 1 sync", "[Channel1]", "sync_enabled"),
+    target!("deck1-headphones", "Deck 1 headphones", "[Channel1]", "pfl"),
+    target!("deck1-loop", "Deck 1 4 beat loop", "[Channel1]", "beatloop_4_toggle"),
+    target!("deck1-hotcue1", "Deck 1 hot cue 1", "[Channel1]", "hotcue_1_activate"),
+    target!("deck1-hotcue2", "Deck 1 hot cue 2", "[Channel1]", "hotcue_2_activate"),
+    target!("deck2-play", "Deck 2 play", "[Channel2]", "play"),
+    target!("deck2-cue", "Deck 2 cue", "[Channel2]", "cue_default"),
+    target!("deck2-sync", "Deck 2 sync", "[Channel2]", "sync_enabled"),
+    target!("deck2-headphones", "Deck 2 headphones", "[Channel2]", "pfl"),
+    target!("deck2-loop", "Deck 2 4 beat loop", "[Channel2]", "beatloop_4_toggle"),
+    target!("deck2-hotcue1", "Deck 2 hot cue 1", "[Channel2]", "hotcue_1_activate"),
+    target!("deck2-hotcue2", "Deck 2 hot cue 2", "[Channel2]", "hotcue_2_activate"),
+    target!("sampler1-play", "Sampler 1 play", "[Sampler1]", "cue_gotoandplay"),
+    target!("sampler2-play", "Sampler 2 play", "[Sampler2]", "cue_gotoandplay"),
+];
+
+/// Sliders and knobs
+pub static KNOB_TARGETS: &[Target] = &[
+    target!("crossfader", "Crossfader", "[Master]", "crossfader"),
+    target!("master-gain", "Master gain", "[Master]", "gain"),
+    target!("headphone-mix", "Headphone mix", "[Master]", "headMix"),
+    target!("deck1-volume", "Deck 1 volume", "[Channel1]", "volume"),
+    target!("deck1-rate", "Deck 1 tempo", "[Channel1]", "rate"),
+    target!("deck1-eq-high", "Deck 1 high EQ", "[EqualizerRack1_[Channel1]_Effect1]", "parameter3"),
+    target!("deck1-eq-mid", "Deck 1 mid EQ", "[EqualizerRack1_[Channel1]_Effect1]", "parameter2"),
+    target!("deck1-eq-low", "Deck 1 low EQ", "[EqualizerRack1_[Channel1]_Effect1]", "parameter1"),
+    target!("deck1-filter", "Deck 1 filter", "[QuickEffectRack1_[Channel1]]", "super1"),
+    target!("deck2-volume", "Deck 2 volume", "[Channel2]", "volume"),
+    target!("deck2-rate", "Deck 2 tempo", "[Channel2]", "rate"),
+    target!("deck2-eq-high", "Deck 2 high EQ", "[EqualizerRack1_[Channel2]_Effect1]", "parameter3"),
+    target!("deck2-eq-mid", "Deck 2 mid EQ", "[EqualizerRack1_[Channel2]_Effect1]", "parameter2"),
+    target!("deck2-eq-low", "Deck 2 low EQ", "[EqualizerRack1_[Channel2]_Effect1]", "parameter1"),
+    target!("deck2-filter", "Deck 2 filter", "[QuickEffectRack1_[Channel2]]", "super1"),
+];
+
+fn choices(targets: &[Target]) -> Vec<Choice> {
+    targets.iter().map(|t| Choice { id: t.id.to_string(), label: t.label.to_string() }).collect()
+}
+
+/// Group and key of a target id or a `[Group],key` control
+fn resolve(control: Control, target: &str) -> Result<(String, String), String> {
+    let targets = match control {
+        Control::Pad => PAD_TARGETS,
+        Control::Knob => KNOB_TARGETS,
+    };
+    if let Some(t) = targets.iter().find(|t| t.id == target) {
+        return Ok((t.group.to_string(), t.key.to_string()));
+    }
+    match target.rfind(',') {
+        Some(i) if target.starts_with('[') && target[..i].ends_with(']') => {
+            Ok((target[..i].to_string(), target[i + 1..].trim().to_string()))
+        },
+        _ => Err(format!("unknown Mixxx target \"{}\", expected [Group],key or one of {}",
+            target, targets.iter().map(|t| t.id).collect::<Vec<_>>().join(", "))),
+    }
+}
+
+/// The mapping for program `id`, with its name and description when it has them
+pub fn export(id: u8, program: &Program, names: &Names, assignments: &Assignments) -> Result<String, String> {
+    let channel = program.channel & 0x0F;
+    let mut controls = String::new();
+    for (control, i, target) in assignments.assigned() {
+        let (group, key) = resolve(control, target)?;
+        let description = control.title(names, i);
+        // Pads press and release a button, knobs send their value
+        let messages = match control {
+            Control::Pad => vec![(0x90 | channel, program.pads[i].note), (0x80 | channel, program.pads[i].note)],
+            Control::Knob => vec![(0xB0 | channel, program.knobs[i].control_change)],
+        };
+        for (status, midino) in messages {
+            controls.push_str(&format!("            <control>
+                <group>{}</group>
+                <key>{}</key>
+                <description>{}</description>
+                <status>0x{:02X}</status>
+                <midino>0x{:02X}</midino>
+                <options>
+                    <normal/>
+                </options>
+            </control>
+", escape(&group), escape(&key), escape(&description), status, midino));
+        }
+    }
+    let name = if names.program.is_empty() { format!("LPD8 PROG {}", id) } else { names.program.clone() };
+    Ok(format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<MixxxControllerPreset mixxxVersion="2.2.0+" schemaVersion="1">
+    <info>
+        <name>{}</name>
+        <author>{}</author>
+        <description>{}</description>
+    </info>
+    <controller id="LPD8">
+        <scriptfiles/>
+        <controls>
+{}        </controls>
+        <outputs/>
+    </controller>
+</MixxxControllerPreset>
+"#, escape(&name), env!("CARGO_PKG_NAME"), escape(&names.description), controls))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use roxmltree::Node;
+
+    fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Node<'a, 'input> {
+        node.children()
+            .find(|n| n.tag_name().name() == name)
+            .unwrap_or_else(|| panic!("no <{}>", name))
+    }
+
+    fn text(node: Node, name: &str) -> String {
+        child(node, name).text().unwrap_or("").to_string()
+    }
+
+    #[test]
+    fn mapping_structure() {
+        let mut program = Program { channel: 2, ..Program::default() };
+        program.pads[0].note = 36;
+        program.knobs[1].control_change = 74;
+        let mut names = Names { program: "Decks & FX".to_string(), ..Names::default() };
+        names.pads[0] = "Play <1>".to_string();
+        let assignments = Assignments::parse(&["PAD1=deck1-play", "k2=[Master],crossfader"]).unwrap();
+
+        let xml = export(1, &program, &names, &assignments).unwrap();
+        let document = roxmltree::Document::parse(&xml).expect("well formed XML");
+        let root = document.root_element();
+        assert_eq!(root.tag_name().name(), "MixxxControllerPreset");
+        assert_eq!(root.attribute("schemaVersion"), Some("1"));
+        assert_eq!(text(child(root, "info"), "name"), "Decks & FX");
+
+        let controls = child(child(root, "controller"), "controls").children()
+            .filter(|n| n.is_element())
+            .collect::<Vec<_>>();
+        // Note on and off for the pad, one CC for the knob
+        assert_eq!(controls.len(), 3);
+        for control in controls.iter() {
+            for name in ["group", "key", "description", "status", "midino", "options"].iter() {
+                child(*control, name);
+            }
+        }
+        assert_eq!(text(controls[0], "group"), "[Channel1]");
+        assert_eq!(text(controls[0], "key"), "play");
+        assert_eq!(text(controls[0], "description"), "PAD 1 Play <1>");
+        assert_eq!(text(controls[0], "status"), "0x92");
+        assert_eq!(text(controls[0], "midino"), "0x24");
+        assert_eq!(text(controls[1], "status"), "0x82");
+        assert_eq!(text(controls[2], "group"), "[Master]");
+        assert_eq!(text(controls[2], "key"), "crossfader");
+        assert_eq!(text(controls[2], "status"), "0xB2");
+        assert_eq!(text(controls[2], "midino"), "0x4A");
+    }
+
+    #[test]
+    fn unknown_target() {
+        let assignments = Assignments::parse(&["K1=volume"]).unwrap();
+        assert!(export(1, &Program::default(), &Names::default(), &assignments).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! LPD8 MK2 programs.
+//!
+//! The MK2 talks to its own product id and packs more into a program: per pad MIDI channels and
+//! RGB colours, a pressure (aftertouch) mode, full level and per knob channels. The settings the
+//! original LPD8 shares live in `Program`, the rest in `Mk2Settings`.
+//!
+//! Program payload, after the two length bytes:
+//!
+//! | Offset    | Content                                                                 |
+//! |-----------|-------------------------------------------------------------------------|
+//! | 0         | program id                                                              |
+//! | 1         | MIDI channel                                                            |
+//! | 2         | pressure message, 0 off, 1 channel pressure, 2 polyphonic aftertouch     |
+//! | 3         | full level                                                              |
+//! | 4..140    | 8 pads of 17 bytes: note, CC, PC, channel, toggle, off RGB, on RGB      |
+//! | 140..172  | 8 knobs of 4 bytes: CC, channel, low, high                              |
+//!
+//! Colour components are 0-255, sent as a high bit byte followed by the low 7 bits.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::monitor::Monitor;
+use crate::{push_sysex, transact_sysex, DeviceIDs, Program, MAN_AKAI, SYSEX_END, SYSEX_START};
+
+pub const PRODUCT_LPD8_MK2: u8 = 0x4C;
+
+pub const UPLOAD_PROGRAM: u8 = 0x01;
+pub const DOWNLOAD_PROGRAM: u8 = 0x03;
+
+pub const PAYLOAD_LEN: usize = 4 + 8 * PAD_LEN + 8 * KNOB_LEN;
+const PAD_LEN: usize = 17;
+const KNOB_LEN: usize = 4;
+// F0, manufacturer, device id, product, command and two length bytes
+const HEADER_LEN: usize = 1 + 1 + 2 + 1 + 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Pressure {
+    Off,
+    Channel,
+    Polyphonic,
+}
+
+impl Default for Pressure {
+    fn default() -> Pressure {
+        Pressure::Off
+    }
+}
+
+impl Pressure {
+    fn to_byte(self) -> u8 {
+        match self {
+            Pressure::Off => 0,
+            Pressure::Channel => 1,
+            Pressure::Polyphonic => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Pressure {
+        match byte {
+            1 => Pressure::Channel,
+            2 => Pressure::Polyphonic,
+            _ => Pressure::Off,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Mk2Pad {
+    pub channel: u8,
+    pub off: Rgb,
+    pub on: Rgb,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Mk2Knob {
+    pub channel: u8,
+}
+
+/// Program settings only the MK2 has
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Mk2Settings {
+    pub pressure: Pressure,
+    pub full_level: bool,
+    pub pads: [Mk2Pad; 8],
+    pub knobs: [Mk2Knob; 8],
+}
+
+fn push_rgb(buf: &mut Vec<u8>, rgb: Rgb) {
+    for c in &[rgb.0, rgb.1, rgb.2] {
+        buf.extend(&[c >> 7, c & 0x7F]);
+    }
+}
+
+fn parse_rgb(bytes: &[u8]) -> Rgb {
+    let c = |i: usize| ((bytes[i] & 0x01) << 7) | (bytes[i + 1] & 0x7F);
+    Rgb(c(0), c(2), c(4))
+}
+
+fn message(sysex_id: u8, command: u8, payload: &[u8]) -> Vec<u8> {
+    SYSEX_START.iter()
+        .chain(MAN_AKAI)
+        .chain(&[sysex_id, PRODUCT_LPD8_MK2, command, (payload.len() >> 7) as u8, (payload.len() & 0x7F) as u8])
+        .chain(payload)
+        .chain(SYSEX_END)
+        .cloned().collect()
+}
+
+pub fn encode_program(id: u8, program: &Program) -> Vec<u8> {
+    let mk2 = &program.mk2;
+    let mut payload = vec![id, program.channel, mk2.pressure.to_byte(), if mk2.full_level { 1 } else { 0 }];
+    for (pad, mk2_pad) in program.pads.iter().zip(mk2.pads.iter()) {
+        payload.extend(&[pad.note, pad.control_change, pad.program_change, mk2_pad.channel,
+            if pad.toggle { 1 } else { 0 }]);
+        push_rgb(&mut payload, mk2_pad.off);
+        push_rgb(&mut payload, mk2_pad.on);
+    }
+    for (knob, mk2_knob) in program.knobs.iter().zip(mk2.knobs.iter()) {
+        payload.extend(&[knob.control_change, mk2_knob.channel, knob.low, knob.high]);
+    }
+    payload
+}
+
+/// Decodes an upload or download reply message.
+pub fn parse_program(message: &[u8]) -> Option<Program> {
+    if message.len() != HEADER_LEN + PAYLOAD_LEN + 1 {
+        return None;
+    }
+    let payload = &message[HEADER_LEN..HEADER_LEN + PAYLOAD_LEN];
+    let mut program = Program::default();
+    program.channel = payload[1];
+    program.mk2.pressure = Pressure::from_byte(payload[2]);
+    program.mk2.full_level = payload[3] == 1;
+    for (i, p) in payload[4..4 + 8 * PAD_LEN].chunks(PAD_LEN).enumerate() {
+        program.pads[i].note = p[0];
+        program.pads[i].control_change = p[1];
+        program.pads[i].program_change = p[2];
+        program.mk2.pads[i].channel = p[3];
+        program.pads[i].toggle = p[4] == 1;
+        program.mk2.pads[i].off = parse_rgb(&p[5..11]);
+        program.mk2.pads[i].on = parse_rgb(&p[11..17]);
+    }
+    for (i, k) in payload[4 + 8 * PAD_LEN..].chunks(KNOB_LEN).enumerate() {
+        program.knobs[i].control_change = k[0];
+        program.mk2.knobs[i].channel = k[1];
+        program.knobs[i].low = k[2];
+        program.knobs[i].high = k[3];
+    }
+    Some(program)
+}
+
+/// Asks for program `id`, also what the emulator answers.
+pub fn download_request(sysex_id: u8, id: u8) -> Vec<u8> {
+    message(sysex_id, DOWNLOAD_PROGRAM, &[id])
+}
+
+pub fn upload_message(sysex_id: u8, id: u8, program: &Program) -> Vec<u8> {
+    message(sysex_id, UPLOAD_PROGRAM, &encode_program(id, program))
+}
+
+pub fn download_program(monitor: &Monitor, device_id: &DeviceIDs, id: u8) -> Result<Program, String> {
+    let request = download_request(device_id.2, id);
+    let mut response_filter = message(device_id.2, DOWNLOAD_PROGRAM, &[0; PAYLOAD_LEN]);
+    response_filter[HEADER_LEN] = id;
+    // Skipping the device id, units answer with their own
+    const RESPONSE_TEST_RANGES: &[Range<usize>] = &[
+        0..2,
+        3..HEADER_LEN + 1,
+    ];
+    transact_sysex(monitor, &(device_id.0).1, &(device_id.1).1,
+        &request, &response_filter, RESPONSE_TEST_RANGES)
+        .and_then(|response| parse_program(&response).ok_or_else(|| "the device sent a program that couldn't be read".to_string()))
+}
+
+pub fn upload_program(monitor: &Monitor, device_id: &DeviceIDs, id: u8, program: &Program) -> Result<(), String> {
+    push_sysex(monitor, &(device_id.1).1, &upload_message(device_id.2, id, program))
+}
+
+/// Reply an MK2 sends to a download request.
+pub fn download_reply(sysex_id: u8, id: u8, program: &Program) -> Vec<u8> {
+    message(sysex_id, DOWNLOAD_PROGRAM, &encode_program(id, program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> Program {
+        let mut program = Program { channel: 3, ..Program::default() };
+        program.mk2.pressure = Pressure::Polyphonic;
+        program.mk2.full_level = true;
+        for (i, (pad, mk2_pad)) in program.pads.iter_mut().zip(program.mk2.pads.iter_mut()).enumerate() {
+            let i = i as u8;
+            pad.note = 36 + i;
+            pad.control_change = 20 + i;
+            pad.program_change = i;
+            pad.toggle = i % 2 == 0;
+            mk2_pad.channel = i;
+            // Components over 127 need the high bit byte
+            mk2_pad.off = Rgb(i, 128 + i, 255);
+            mk2_pad.on = Rgb(255 - i, 127, 0);
+        }
+        for (i, (knob, mk2_knob)) in program.knobs.iter_mut().zip(program.mk2.knobs.iter_mut()).enumerate() {
+            let i = i as u8;
+            knob.control_change = 70 + i;
+            knob.low = i;
+            knob.high = 127 - i;
+            mk2_knob.channel = 15 - i;
+        }
+        program
+    }
+
+    fn assert_same(a: &Program, b: &Program) {
+        assert_eq!(a.channel, b.channel);
+        assert_eq!(a.mk2.pressure, b.mk2.pressure);
+        assert_eq!(a.mk2.full_level, b.mk2.full_level);
+        for i in 0..8 {
+            let (pa, pb) = (&a.pads[i], &b.pads[i]);
+            assert_eq!((pa.note, pa.control_change, pa.program_change, pa.toggle),
+                (pb.note, pb.control_change, pb.program_change, pb.toggle), "pad {}", i + 1);
+            let (ma, mb) = (&a.mk2.pads[i], &b.mk2.pads[i]);
+            assert_eq!((ma.channel, ma.off, ma.on), (mb.channel, mb.off, mb.on), "pad {}", i + 1);
+            let (ka, kb) = (&a.knobs[i], &b.knobs[i]);
+            assert_eq!((ka.control_change, ka.low, ka.high, a.mk2.knobs[i].channel),
+                (kb.control_change, kb.low, kb.high, b.mk2.knobs[i].channel), "knob {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = program();
+        let payload = encode_program(2, &program);
+        assert_eq!(payload.len(), PAYLOAD_LEN);
+        assert!(payload.iter().all(|b| *b < 0x80), "SysEx data bytes are 7 bit");
+
+        let upload = upload_message(0x7F, 2, &program);
+        assert_eq!(&upload[..HEADER_LEN], &[0xF0, 0x47, 0x7F, PRODUCT_LPD8_MK2, UPLOAD_PROGRAM, 0x01, 0x2C]);
+        assert_eq!(upload.last(), Some(&0xF7));
+        assert_same(&parse_program(&upload).expect("upload parses"), &program);
+        assert_same(&parse_program(&download_reply(0x00, 2, &program)).expect("reply parses"), &program);
+    }
+
+    #[test]
+    fn wrong_length() {
+        let upload = upload_message(0x7F, 1, &program());
+        assert!(parse_program(&upload[..upload.len() - 2]).is_none());
+        assert!(parse_program(&[0xF0, 0xF7]).is_none());
+    }
+}
@@ -0,0 +1,433 @@
+use std::fmt;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use std::time::{Duration, Instant};
+
+use gtk::prelude::*;
+
+use crate::logging::PROTOCOL;
+use crate::profile::Profile;
+use crate::{LPD8Message, Pad, Program, MAN_AKAI};
+
+/// Events kept for refiltering, and held while paused
+pub const HISTORY_LEN: usize = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::In => write!(f, "IN"),
+            Direction::Out => write!(f, "OUT"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Note,
+    ControlChange,
+    ProgramChange,
+    SysEx,
+    Other,
+}
+
+impl Kind {
+    pub fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "note" => Some(Kind::Note),
+            "cc" => Some(Kind::ControlChange),
+            "pc" => Some(Kind::ProgramChange),
+            "sysex" => Some(Kind::SysEx),
+            "other" => Some(Kind::Other),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SysEx {
+    IdentityRequest,
+    IdentityReply,
+    /// Program message of a device in `profile::PROFILES`
+    Program { device: &'static str, message: LPD8Message, program: Option<u8> },
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    ControlChange { channel: u8, control: u8, value: u8 },
+    ProgramChange { channel: u8, program: u8 },
+    SysEx(SysEx),
+    Other,
+}
+
+impl Message {
+    pub fn kind(&self) -> Kind {
+        match self {
+            Message::NoteOff { .. } | Message::NoteOn { .. } => Kind::Note,
+            Message::ControlChange { .. } => Kind::ControlChange,
+            Message::ProgramChange { .. } => Kind::ProgramChange,
+            Message::SysEx(_) => Kind::SysEx,
+            Message::Other => Kind::Other,
+        }
+    }
+
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            Message::NoteOff { channel, .. }
+            | Message::NoteOn { channel, .. }
+            | Message::ControlChange { channel, .. }
+            | Message::ProgramChange { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Message::NoteOff { channel, note, velocity } =>
+                write!(f, "Note Off  ch {:2} note {:3} vel {:3}", channel + 1, note, velocity),
+            Message::NoteOn { channel, note, velocity } =>
+                write!(f, "Note On   ch {:2} note {:3} vel {:3}", channel + 1, note, velocity),
+            Message::ControlChange { channel, control, value } =>
+                write!(f, "CC        ch {:2} cc {:3} val {:3}", channel + 1, control, value),
+            Message::ProgramChange { channel, program } =>
+                write!(f, "Program   ch {:2} prog {:3}", channel + 1, program),
+            Message::SysEx(SysEx::IdentityRequest) => write!(f, "SysEx Identity request"),
+            Message::SysEx(SysEx::IdentityReply) => write!(f, "SysEx Identity reply"),
+            Message::SysEx(SysEx::Program { device, message, program: Some(program) }) =>
+                write!(f, "SysEx {} {} (PROG {})", device, message.name(), program),
+            Message::SysEx(SysEx::Program { device, message, program: None }) =>
+                write!(f, "SysEx {} {}", device, message.name()),
+            Message::SysEx(SysEx::Unknown) => write!(f, "SysEx"),
+            Message::Other => write!(f, "Other"),
+        }
+    }
+}
+
+/// What a pad or knob of a program did, by index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlEvent {
+    PadPressed { pad: usize, velocity: u8 },
+    PadReleased { pad: usize },
+    Knob { knob: usize, value: u8 },
+}
+
+impl ControlEvent {
+    /// The control of `program` that sent `message`, `None` for messages on other channels or
+    /// numbers. Knobs are looked for first when a pad in CC mode shares their number.
+    pub fn identify(program: &Program, message: &Message) -> Option<ControlEvent> {
+        if message.channel() != Some(program.channel & 0x0F) {
+            return None;
+        }
+        let pad = |matches: &dyn Fn(&Pad) -> bool| program.pads.iter().position(|pad| matches(pad));
+        match *message {
+            Message::NoteOn { note, velocity, .. } => pad(&|pad| pad.note == note)
+                .map(|pad| ControlEvent::PadPressed { pad, velocity }),
+            Message::NoteOff { note, .. } => pad(&|pad| pad.note == note)
+                .map(|pad| ControlEvent::PadReleased { pad }),
+            Message::ControlChange { control, value, .. } => {
+                match program.knobs.iter().position(|knob| knob.control_change == control) {
+                    Some(knob) => Some(ControlEvent::Knob { knob, value }),
+                    None => pad(&|pad| pad.control_change == control).map(|pad| match value {
+                        0 => ControlEvent::PadReleased { pad },
+                        velocity => ControlEvent::PadPressed { pad, velocity },
+                    }),
+                }
+            },
+            Message::ProgramChange { program: number, .. } => pad(&|pad| pad.program_change == number)
+                .map(|pad| ControlEvent::PadPressed { pad, velocity: 127 }),
+            _ => None,
+        }
+    }
+}
+
+pub fn decode(data: &[u8]) -> Message {
+    let status = match data.first() {
+        Some(status) => *status,
+        None => return Message::Other,
+    };
+    let channel = status & 0x0F;
+    match status & 0xF0 {
+        0x80 if data.len() >= 3 => Message::NoteOff { channel, note: data[1], velocity: data[2] },
+        0x90 if data.len() >= 3 && data[2] == 0 => Message::NoteOff { channel, note: data[1], velocity: 0 },
+        0x90 if data.len() >= 3 => Message::NoteOn { channel, note: data[1], velocity: data[2] },
+        0xB0 if data.len() >= 3 => Message::ControlChange { channel, control: data[1], value: data[2] },
+        0xC0 if data.len() >= 2 => Message::ProgramChange { channel, program: data[1] },
+        0xF0 if status == 0xF0 => Message::SysEx(decode_sysex(data)),
+        _ => Message::Other,
+    }
+}
+
+fn decode_sysex(data: &[u8]) -> SysEx {
+    if data.len() >= 5 && data[1] == 0x7E && data[3] == 0x06 {
+        return match data[4] {
+            0x01 => SysEx::IdentityRequest,
+            0x02 => SysEx::IdentityReply,
+            _ => SysEx::Unknown,
+        };
+    }
+    let header_len = 1 + MAN_AKAI.len() + 2;
+    if data.len() > header_len && &data[1..2] == MAN_AKAI {
+        let profile = Profile::by_product(data[3]);
+        if let (Some(profile), Some(message)) = (profile, LPD8Message::from_pattern(data[header_len])) {
+            let program = if data.len() > 8 && data[7] != 0xF7 { Some(data[7]) } else { None };
+            return SysEx::Program { device: profile.name, message, program };
+        }
+    }
+    SysEx::Unknown
+}
+
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|d| format!("{:02X}", d)).collect::<Vec<_>>().join(" ")
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub time: Duration,
+    pub direction: Direction,
+    pub port: String,
+    pub data: Vec<u8>,
+    pub message: Message,
+}
+
+impl Event {
+    pub fn time_str(&self) -> String {
+        format!("{}.{:03}", self.time.as_secs(), self.time.subsec_millis())
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>10} {:<3} {:<40} | {}", self.time_str(), self.direction, self.message, hex(&self.data))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Filter {
+    pub kind: Option<Kind>,
+    pub channel: Option<u8>,
+}
+
+impl Filter {
+    pub fn matches(&self, event: &Event) -> bool {
+        self.kind.map_or(true, |kind| event.message.kind() == kind)
+            && self.channel.map_or(true, |channel| event.message.channel() == Some(channel))
+    }
+}
+
+/// Shared tap that every send and receive reports to; views subscribe for a copy of each event.
+#[derive(Clone)]
+pub struct Monitor {
+    start: Instant,
+    subscribers: Arc<Mutex<Vec<Sender<Event>>>>,
+}
+
+impl Monitor {
+    pub fn new() -> Monitor {
+        Monitor {
+            start: Instant::now(),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn log(&self, direction: Direction, port: &str, data: &[u8]) {
+        let event = Event {
+            time: self.start.elapsed(),
+            direction,
+            port: port.to_string(),
+            data: data.to_vec(),
+            message: decode(data),
+        };
+        if data.first() == Some(&0xF0) {
+            trace!(target: PROTOCOL, "{} {} {} [{}]", event.direction, event.port, event.message, hex(data));
+        }
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+fn append_row(list: &gtk::ListStore, event: &Event) {
+    list.insert_with_values(None, &[0, 1, 2, 3, 4], &[
+        &event.time_str(),
+        &event.direction.to_string(),
+        &event.port,
+        &event.message.to_string(),
+        &hex(&event.data)]);
+}
+
+pub fn connect_panel(builder: &gtk::Builder, monitor: &Monitor) {
+    let list: gtk::ListStore = builder.get_object("monitor-list").expect("no monitor list model");
+    let view: gtk::TreeView = builder.get_object("monitor-view").expect("no monitor view");
+    let type_select: gtk::ComboBoxText = builder.get_object("monitor-type").expect("no monitor type");
+    let channel_adj: gtk::Adjustment = builder.get_object("monitor-channel-adj").expect("no monitor channel");
+    let pause_button: gtk::ToggleButton = builder.get_object("monitor-pause").expect("no monitor pause");
+    let clear_button: gtk::Button = builder.get_object("monitor-clear").expect("no monitor clear");
+
+    let history = Rc::new(RefCell::new(VecDeque::<Event>::new()));
+    let filter = Rc::new(RefCell::new(Filter::default()));
+
+    let refilter = {
+        let list = list.clone();
+        let history = history.clone();
+        let filter = filter.clone();
+        move || {
+            list.clear();
+            let filter = filter.borrow();
+            for event in history.borrow().iter().filter(|e| filter.matches(e)) {
+                append_row(&list, event);
+            }
+        }
+    };
+
+    {
+        let filter = filter.clone();
+        let refilter = refilter.clone();
+        type_select.connect_changed(move |type_select| {
+            let kind = type_select.get_active_id().and_then(|id| Kind::from_name(&id));
+            filter.borrow_mut().kind = kind;
+            refilter();
+        });
+    }
+    {
+        let filter = filter.clone();
+        let refilter = refilter.clone();
+        channel_adj.connect_value_changed(move |adj| {
+            // 0 shows every channel, 1-16 picks one
+            let channel = adj.get_value() as u8;
+            filter.borrow_mut().channel = if channel == 0 { None } else { Some(channel - 1) };
+            refilter();
+        });
+    }
+    {
+        // Pausing only freezes the list, what came in meanwhile shows on resuming
+        let refilter = refilter.clone();
+        pause_button.connect_toggled(move |pause_button| {
+            if !pause_button.get_active() {
+                refilter();
+            }
+        });
+    }
+    {
+        let list = list.clone();
+        let history = history.clone();
+        clear_button.connect_clicked(move |_button| {
+            history.borrow_mut().clear();
+            list.clear();
+        });
+    }
+
+    let rx = monitor.subscribe();
+    gtk::timeout_add(50, move || {
+        let mut appended = false;
+        let paused = pause_button.get_active();
+        for event in rx.try_iter() {
+            if !paused && filter.borrow().matches(&event) {
+                append_row(&list, &event);
+                appended = true;
+            }
+            let mut history = history.borrow_mut();
+            history.push_back(event);
+            if history.len() > HISTORY_LEN {
+                let dropped = history.pop_front().expect("history is full");
+                if !paused && filter.borrow().matches(&dropped) {
+                    if let Some(first) = list.get_iter_first() {
+                        list.remove(&first);
+                    }
+                }
+            }
+        }
+        if appended {
+            if let Some(last) = list.iter_n_children(None).checked_sub(1) {
+                let path = gtk::TreePath::new_from_indicesv(&[last]);
+                view.scroll_to_cell(Some(&path), None, false, 0.0, 0.0);
+            }
+        }
+        glib::Continue(true)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(data: &[u8]) -> Event {
+        Event {
+            time: Duration::from_millis(0),
+            direction: Direction::In,
+            port: "LPD8".to_string(),
+            data: data.to_vec(),
+            message: decode(data),
+        }
+    }
+
+    #[test]
+    fn decode_channel_messages() {
+        assert_eq!(decode(&[0x92, 36, 100]), Message::NoteOn { channel: 2, note: 36, velocity: 100 });
+        assert_eq!(decode(&[0x82, 36, 64]), Message::NoteOff { channel: 2, note: 36, velocity: 64 });
+        // A note on with velocity 0 is a note off
+        assert_eq!(decode(&[0x9F, 40, 0]), Message::NoteOff { channel: 15, note: 40, velocity: 0 });
+        assert_eq!(decode(&[0xB0, 74, 127]), Message::ControlChange { channel: 0, control: 74, value: 127 });
+        assert_eq!(decode(&[0xC5, 3]), Message::ProgramChange { channel: 5, program: 3 });
+    }
+
+    #[test]
+    fn decode_short_and_other() {
+        assert_eq!(decode(&[]), Message::Other);
+        assert_eq!(decode(&[0x90, 36]), Message::Other);
+        assert_eq!(decode(&[0xC0]), Message::Other);
+        assert_eq!(decode(&[0xF8]), Message::Other);
+        assert_eq!(decode(&[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]), Message::SysEx(SysEx::IdentityRequest));
+    }
+
+    #[test]
+    fn filter() {
+        let note = event(&[0x91, 36, 100]);
+        let cc = event(&[0xB2, 1, 64]);
+        assert!(Filter::default().matches(&note));
+        let notes = Filter { kind: Some(Kind::Note), channel: None };
+        assert!(notes.matches(&note));
+        assert!(!notes.matches(&cc));
+        let channel_2 = Filter { kind: None, channel: Some(1) };
+        assert!(channel_2.matches(&note));
+        assert!(!channel_2.matches(&cc));
+        // SysEx has no channel
+        assert!(!channel_2.matches(&event(&[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7])));
+    }
+
+    #[test]
+    fn identify_controls() {
+        let mut program = Program { channel: 1, ..Program::default() };
+        program.pads[3].note = 39;
+        program.pads[4].control_change = 20;
+        program.knobs[0].control_change = 20;
+        program.knobs[1].control_change = 21;
+        let identify = |data: &[u8]| ControlEvent::identify(&program, &decode(data));
+        assert_eq!(identify(&[0x91, 39, 90]), Some(ControlEvent::PadPressed { pad: 3, velocity: 90 }));
+        assert_eq!(identify(&[0x91, 39, 0]), Some(ControlEvent::PadReleased { pad: 3 }));
+        assert_eq!(identify(&[0x90, 39, 90]), None);
+        // Knobs win over pads sending the same CC
+        assert_eq!(identify(&[0xB1, 20, 5]), Some(ControlEvent::Knob { knob: 0, value: 5 }));
+        assert_eq!(identify(&[0xB1, 22, 5]), None);
+    }
+}
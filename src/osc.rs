@@ -0,0 +1,241 @@
+//! MIDI to OSC bridge.
+//!
+//! Sends what the pads and knobs do as OSC messages over UDP, for visuals software that speaks
+//! OSC rather than MIDI. Controls are found by the program shown in the editor: a pad sends its
+//! velocity, 0 on release, and a knob its value scaled to 0-1 between the knob's low and high.
+//! Addresses are configurable, `{n}` is replaced by the control's number (1-8) and `{program}`
+//! by the program's.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::monitor::{decode, ControlEvent};
+use crate::{Knob, Program};
+
+/// Where and how to send, kept in the settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// `host:port` to send to
+    #[serde(default = "default_target")]
+    pub target: String,
+    #[serde(default = "default_pad_address")]
+    pub pad_address: String,
+    #[serde(default = "default_knob_address")]
+    pub knob_address: String,
+}
+
+fn default_target() -> String {
+    "127.0.0.1:9000".to_string()
+}
+
+fn default_pad_address() -> String {
+    "/lpd8/pad/{n}".to_string()
+}
+
+fn default_knob_address() -> String {
+    "/lpd8/knob/{n}".to_string()
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            target: default_target(),
+            pad_address: default_pad_address(),
+            knob_address: default_knob_address(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Float(f32),
+}
+
+/// OSC message with `args`
+pub fn encode(address: &str, args: &[Arg]) -> Vec<u8> {
+    let mut tags = ",".to_string();
+    for arg in args {
+        tags.push(match arg {
+            Arg::Int(_) => 'i',
+            Arg::Float(_) => 'f',
+        });
+    }
+    let mut packet = Vec::new();
+    push_string(&mut packet, address);
+    push_string(&mut packet, &tags);
+    for arg in args {
+        match arg {
+            Arg::Int(value) => packet.extend(&value.to_be_bytes()),
+            Arg::Float(value) => packet.extend(&value.to_bits().to_be_bytes()),
+        }
+    }
+    packet
+}
+
+/// Strings end with at least one null and are padded to 4 bytes.
+fn push_string(packet: &mut Vec<u8>, text: &str) {
+    packet.extend(text.as_bytes());
+    packet.push(0);
+    while packet.len() % 4 != 0 {
+        packet.push(0);
+    }
+}
+
+/// An open socket with what to send
+struct Link {
+    socket: UdpSocket,
+    config: Config,
+}
+
+impl Link {
+    fn open(config: &Config) -> io::Result<Link> {
+        let target = config.target.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("no address for {}", config.target)))?;
+        // Any local address of the target's family
+        let local = match target {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+        Ok(Link { socket, config: config.clone() })
+    }
+
+    fn send(&self, id: u8, program: &Program, event: ControlEvent) -> io::Result<()> {
+        let address = |pattern: &str, i: usize| {
+            pattern.replace("{n}", &(i + 1).to_string()).replace("{program}", &id.to_string())
+        };
+        let (address, arg) = match event {
+            ControlEvent::PadPressed { pad, velocity } => {
+                (address(&self.config.pad_address, pad), Arg::Int(i32::from(velocity)))
+            },
+            ControlEvent::PadReleased { pad } => (address(&self.config.pad_address, pad), Arg::Int(0)),
+            ControlEvent::Knob { knob, value } => {
+                (address(&self.config.knob_address, knob), Arg::Float(scale(&program.knobs[knob], value)))
+            },
+        };
+        self.socket.send(&encode(&address, &[arg])).map(|_| ())
+    }
+}
+
+/// `value` between the knob's low and high as 0-1, reversed ranges going down
+fn scale(knob: &Knob, value: u8) -> f32 {
+    let (low, high) = (f32::from(knob.low), f32::from(knob.high));
+    if low == high {
+        return 0.0;
+    }
+    ((f32::from(value) - low) / (high - low)).max(0.0).min(1.0)
+}
+
+/// Forwards input while started, shared with the MIDI input callback.
+#[derive(Clone)]
+pub struct Bridge {
+    link: Arc<Mutex<Option<Link>>>,
+}
+
+impl Bridge {
+    pub fn new() -> Bridge {
+        Bridge {
+            link: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn start(&self, config: &Config) -> io::Result<()> {
+        *self.link.lock().unwrap() = Some(Link::open(config)?);
+        info!("Sending OSC to {}", config.target);
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        *self.link.lock().unwrap() = None;
+    }
+
+    /// Sends `data` as OSC if it came from a control of program `id`.
+    pub fn forward(&self, id: u8, program: &Program, data: &[u8]) {
+        let link = self.link.lock().unwrap();
+        let link = match link.as_ref() {
+            Some(link) => link,
+            None => return,
+        };
+        if let Some(event) = ControlEvent::identify(program, &decode(data)) {
+            if let Err(e) = link.send(id, program, event) {
+                warn!("Couldn't send OSC to {}: {}", link.config.target, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn receive(listener: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0; 256];
+        let len = listener.recv(&mut buf).expect("an OSC packet");
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode("/a", &[Arg::Int(3)]), b"/a\0\0,i\0\0\0\0\0\x03".to_vec());
+        assert_eq!(encode("/abc", &[Arg::Float(1.0)]), b"/abc\0\0\0\0,f\0\0\x3f\x80\0\0".to_vec());
+    }
+
+    #[test]
+    fn bridge_sends_to_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let config = Config {
+            target: listener.local_addr().unwrap().to_string(),
+            pad_address: "/lpd8/{program}/pad/{n}".to_string(),
+            ..Config::default()
+        };
+
+        let mut program = Program { channel: 1, ..Program::default() };
+        program.pads[2].note = 38;
+        program.knobs[4].control_change = 20;
+        program.knobs[4].low = 27;
+        program.knobs[4].high = 127;
+
+        let bridge = Bridge::new();
+        bridge.start(&config).unwrap();
+        // On another channel, not the program's
+        bridge.forward(2, &program, &[0x90, 38, 100]);
+        bridge.forward(2, &program, &[0x91, 38, 100]);
+        assert_eq!(receive(&listener), encode("/lpd8/2/pad/3", &[Arg::Int(100)]));
+        bridge.forward(2, &program, &[0x81, 38, 64]);
+        assert_eq!(receive(&listener), encode("/lpd8/2/pad/3", &[Arg::Int(0)]));
+        bridge.forward(2, &program, &[0xB1, 20, 77]);
+        assert_eq!(receive(&listener), encode("/lpd8/knob/5", &[Arg::Float(0.5)]));
+
+        bridge.stop();
+        listener.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        bridge.forward(2, &program, &[0x91, 38, 100]);
+        assert!(listener.recv(&mut [0; 256]).is_err());
+    }
+
+    #[test]
+    fn ipv6_target() {
+        let listener = match UdpSocket::bind("[::1]:0") {
+            Ok(listener) => listener,
+            // No IPv6 on this machine
+            Err(_) => return,
+        };
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let config = Config { target: listener.local_addr().unwrap().to_string(), ..Config::default() };
+        assert!(config.target.starts_with('['));
+
+        let mut program = Program::default();
+        program.pads[0].note = 36;
+        let bridge = Bridge::new();
+        bridge.start(&config).unwrap();
+        bridge.forward(1, &program, &[0x90, 36, 90]);
+        assert_eq!(receive(&listener), encode("/lpd8/pad/1", &[Arg::Int(90)]));
+    }
+}
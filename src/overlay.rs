@@ -0,0 +1,250 @@
+//! Printable overlays.
+//!
+//! Draws programs at the LPD8's real size, each pad and knob labelled with its name, note or
+//! control number and range, to print and stick above the controls. SVG puts the programs one
+//! below the other, PDF gives each a page.
+
+use std::path::Path;
+
+use cairo::{Context, FontSlant, FontWeight};
+
+use crate::fill::note_name;
+use crate::program_file::Names;
+use crate::Program;
+
+/// Points in a millimetre, everything below is drawn in millimetres
+const MM: f64 = 72.0 / 25.4;
+
+/// Top face of the LPD8
+const WIDTH: f64 = 338.0;
+const HEIGHT: f64 = 100.0;
+const MARGIN: f64 = 10.0;
+/// Room above the face for the program's title
+const HEADER: f64 = 8.0;
+
+const KNOB_X: f64 = 20.0;
+const KNOB_Y: f64 = 26.0;
+const KNOB_STEP: f64 = 24.0;
+const KNOB_ROW_STEP: f64 = 44.0;
+const KNOB_RADIUS: f64 = 7.0;
+
+const PAD_X: f64 = 128.0;
+const PAD_Y: f64 = 6.0;
+const PAD_SIZE: f64 = 42.0;
+const PAD_STEP: f64 = 48.0;
+const PAD_ROW_STEP: f64 = 46.0;
+
+pub static FORMATS: &[&str] = &["svg", "pdf"];
+
+/// A program to draw with its number and names
+pub struct Sheet<'a> {
+    pub id: u8,
+    pub program: &'a Program,
+    pub names: &'a Names,
+}
+
+/// Writes the sheets to `path`, as SVG or PDF by its extension.
+pub fn export(path: &Path, sheets: &[Sheet]) -> Result<(), String> {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let page_width = (WIDTH + 2.0 * MARGIN) * MM;
+    let page_height = (HEADER + HEIGHT + 2.0 * MARGIN) * MM;
+    match extension.as_str() {
+        "svg" => {
+            let surface = cairo::svg::File::new(page_width, page_height * sheets.len() as f64, path);
+            let cr = Context::new(&surface);
+            cr.scale(MM, MM);
+            for (i, sheet) in sheets.iter().enumerate() {
+                cr.save();
+                cr.translate(MARGIN, MARGIN + i as f64 * (HEADER + HEIGHT + 2.0 * MARGIN));
+                draw(&cr, sheet);
+                cr.restore();
+            }
+            finish(&cr, &surface)
+        },
+        "pdf" => {
+            let surface = cairo::pdf::File::new(page_width, page_height, path);
+            let cr = Context::new(&surface);
+            cr.scale(MM, MM);
+            for sheet in sheets {
+                cr.save();
+                cr.translate(MARGIN, MARGIN);
+                draw(&cr, sheet);
+                cr.restore();
+                cr.show_page();
+            }
+            finish(&cr, &surface)
+        },
+        _ => Err(format!("unknown overlay format \"{}\", expected one of {}", extension, FORMATS.join(", "))),
+    }
+}
+
+fn finish(cr: &Context, surface: &cairo::Surface) -> Result<(), String> {
+    surface.finish();
+    match cr.status() {
+        cairo::Status::Success => Ok(()),
+        status => Err(format!("{:?}", status)),
+    }
+}
+
+/// Outline of a control, in millimetres from the face's top left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outline {
+    /// Centre of a knob
+    Knob(f64, f64),
+    /// Top left corner of a pad
+    Pad(f64, f64),
+}
+
+/// Text to draw, in millimetres from the face's top left corner
+#[derive(Debug, Clone, PartialEq)]
+struct Label {
+    text: String,
+    x: f64,
+    y: f64,
+    size: f64,
+    bold: bool,
+    /// Centred on `x` rather than starting there
+    centred: bool,
+    /// Shrunk to fit when wider
+    width: Option<f64>,
+}
+
+impl Label {
+    fn new(text: String, x: f64, y: f64, size: f64, bold: bool, width: Option<f64>) -> Label {
+        Label { text, x, y, size, bold, centred: true, width }
+    }
+}
+
+/// What to draw for one program, the title above the face
+fn layout(sheet: &Sheet) -> (Vec<Outline>, Vec<Label>) {
+    let (program, names) = (sheet.program, sheet.names);
+    let (mut outlines, mut labels) = (Vec::new(), Vec::new());
+
+    let mut title = format!("{} · Channel {}", names.program_title(sheet.id), program.channel + 1);
+    if !names.description.is_empty() {
+        title = format!("{} · {}", title, names.description);
+    }
+    labels.push(Label { centred: false, ..Label::new(title, 0.0, -3.0, 4.0, true, Some(WIDTH)) });
+
+    // K1 to K4 along the top
+    for (i, knob) in program.knobs.iter().enumerate() {
+        let x = KNOB_X + KNOB_STEP * (i % 4) as f64;
+        let y = KNOB_Y + KNOB_ROW_STEP * (i / 4) as f64;
+        outlines.push(Outline::Knob(x, y));
+        let width = Some(KNOB_STEP - 1.0);
+        labels.push(Label::new(names.knob_title(i), x, y + KNOB_RADIUS + 4.0, 2.8, true, width));
+        labels.push(Label::new(format!("CC {}", knob.control_change), x, y + KNOB_RADIUS + 7.5, 2.4, false, width));
+        labels.push(Label::new(format!("{}–{}", knob.low, knob.high), x, y + KNOB_RADIUS + 10.5, 2.4, false, width));
+    }
+
+    // PAD 1 to 4 along the bottom, as on the device
+    for (i, pad) in program.pads.iter().enumerate() {
+        let x = PAD_X + PAD_STEP * (i % 4) as f64;
+        let y = PAD_Y + PAD_ROW_STEP * (1 - i / 4) as f64;
+        outlines.push(Outline::Pad(x, y));
+        let (middle, width) = (x + PAD_SIZE / 2.0, Some(PAD_SIZE - 2.0));
+        labels.push(Label::new(names.pad_title(i), middle, y + 9.0, 3.6, true, width));
+        labels.push(Label::new(format!("{} ({})", note_name(pad.note), pad.note), middle, y + 18.0, 3.2, false, width));
+        labels.push(Label::new(format!("CC {} · PC {}", pad.control_change, pad.program_change), middle, y + 24.0, 2.8, false, width));
+        if pad.toggle {
+            labels.push(Label::new("Toggle".to_string(), middle, y + 30.0, 2.8, false, width));
+        }
+    }
+    (outlines, labels)
+}
+
+/// Draws one program with the face's top left corner `HEADER` below the origin.
+fn draw(cr: &Context, sheet: &Sheet) {
+    cr.set_source_rgb(0.0, 0.0, 0.0);
+    cr.set_line_width(0.3);
+    cr.translate(0.0, HEADER);
+    cr.rectangle(0.0, 0.0, WIDTH, HEIGHT);
+    cr.stroke();
+
+    let (outlines, labels) = layout(sheet);
+    for outline in outlines {
+        match outline {
+            Outline::Knob(x, y) => {
+                cr.new_sub_path();
+                cr.arc(x, y, KNOB_RADIUS, 0.0, 2.0 * std::f64::consts::PI);
+            },
+            Outline::Pad(x, y) => cr.rectangle(x, y, PAD_SIZE, PAD_SIZE),
+        }
+        cr.stroke();
+    }
+    for label in labels {
+        if label.centred {
+            centered(cr, &label.text, label.x, label.y, label.size, label.bold, label.width);
+        } else {
+            text(cr, &label.text, label.x, label.y, label.size, label.bold, label.width);
+        }
+    }
+}
+
+/// Text starting at `x`, shrunk to fit `width`
+fn text(cr: &Context, text: &str, x: f64, y: f64, size: f64, bold: bool, width: Option<f64>) {
+    let weight = if bold { FontWeight::Bold } else { FontWeight::Normal };
+    cr.select_font_face("Sans", FontSlant::Normal, weight);
+    cr.set_font_size(size);
+    let extents = cr.text_extents(text);
+    if let Some(width) = width.filter(|width| extents.width > *width) {
+        cr.set_font_size(size * width / extents.width);
+    }
+    let extents = cr.text_extents(text);
+    cr.move_to(x - extents.x_bearing, y);
+    cr.show_text(text);
+}
+
+/// Text centred on `x`, shrunk to fit `width`
+fn centered(cr: &Context, text: &str, x: f64, y: f64, size: f64, bold: bool, width: Option<f64>) {
+    let weight = if bold { FontWeight::Bold } else { FontWeight::Normal };
+    cr.select_font_face("Sans", FontSlant::Normal, weight);
+    cr.set_font_size(size);
+    let extents = cr.text_extents(text);
+    let fitted = width.map_or(extents.width, |width| extents.width.min(width));
+    self::text(cr, text, x - fitted / 2.0, y, size, bold, width);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_per_control() {
+        let mut program = Program { channel: 9, ..Program::default() };
+        program.pads[0].note = 36;
+        program.pads[0].toggle = true;
+        program.pads[4].note = 40;
+        program.knobs[5].control_change = 74;
+        program.knobs[5].high = 100;
+        let mut names = Names { program: "Drums".to_string(), ..Names::default() };
+        names.pads[0] = "Kick".to_string();
+        let (outlines, labels) = layout(&Sheet { id: 2, program: &program, names: &names });
+
+        assert_eq!(outlines.len(), 16);
+        // Three labels for every knob and pad, the title and one toggle
+        assert_eq!(labels.len(), 1 + 8 * 3 + 8 * 3 + 1);
+        assert_eq!(labels[0].text, "PROG 2 Drums · Channel 10");
+        assert!(!labels[0].centred && labels[0].y < 0.0);
+
+        let at = |text: &str| labels.iter().find(|l| l.text == text).unwrap_or_else(|| panic!("no \"{}\"", text));
+        // K6 is second in the bottom row of knobs
+        assert_eq!(outlines[5], Outline::Knob(KNOB_X + KNOB_STEP, KNOB_Y + KNOB_ROW_STEP));
+        assert_eq!(at("CC 74").x, KNOB_X + KNOB_STEP);
+        assert_eq!(at("0–100").x, KNOB_X + KNOB_STEP);
+
+        // PAD 1 is bottom left, PAD 5 above it
+        assert_eq!(outlines[8], Outline::Pad(PAD_X, PAD_Y + PAD_ROW_STEP));
+        assert_eq!(outlines[12], Outline::Pad(PAD_X, PAD_Y));
+        let kick = at("PAD 1 Kick");
+        assert_eq!((kick.x, kick.y), (PAD_X + PAD_SIZE / 2.0, PAD_Y + PAD_ROW_STEP + 9.0));
+        assert!(kick.bold && kick.centred);
+        assert_eq!(at("C1 (36)").y, PAD_Y + PAD_ROW_STEP + 18.0);
+        assert_eq!(at("E1 (40)").y, PAD_Y + 18.0);
+        assert_eq!(at("Toggle").x, kick.x);
+        // Every label fits on the face
+        for label in &labels[1..] {
+            assert!(label.x >= 0.0 && label.x <= WIDTH && label.y >= 0.0 && label.y <= HEIGHT, "{:?}", label);
+        }
+    }
+}
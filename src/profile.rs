@@ -0,0 +1,361 @@
+//! Device profiles.
+//!
+//! The LPD8, LPK25 and MPK mini share one program protocol, `F0 47 <device id> <product> <command>
+//! <length MSB> <length LSB> <payload> F7` with the `LPD8Message` commands, and only differ in
+//! what the payload holds. A `Profile` describes a device and, for that protocol, its payload
+//! layout, so supporting another sibling is a matter of adding one to `PROFILES`. The LPD8 MK2
+//! speaks its own protocol, see `mk2`.
+
+use std::ops::Range;
+
+use crate::monitor::Monitor;
+use crate::{mk2, push_sysex, transact_sysex, DeviceIDs, LPD8Message, Program, MAN_AKAI, SYSEX_END, SYSEX_START};
+
+// F0, manufacturer, device id, product, command and two length bytes
+const HEADER_LEN: usize = 7;
+const PAD_LEN: usize = 4;
+const KNOB_LEN: usize = 3;
+/// Replies are matched on the header without the device id, units answer with their own
+const RESPONSE_TEST_RANGES: &[Range<usize>] = &[
+    0..2,
+    3..HEADER_LEN,
+];
+
+/// Part of a program payload
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section {
+    /// Program slot number
+    Id,
+    /// The program's MIDI channel
+    Channel,
+    /// Note, program change, control change and toggle of each pad
+    Pads,
+    /// Control change, low and high of each knob
+    Knobs,
+    /// Settings the editor doesn't show yet, sent as these factory defaults
+    Fixed(&'static [u8]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    /// The shared protocol, with the payload layout. When a section repeats, as with separate
+    /// pad and key channels, every copy is sent the same value and the first one is read.
+    Shared(&'static [Section]),
+    Mk2,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Profile {
+    /// Short name for the command line
+    pub id: &'static str,
+    pub name: &'static str,
+    /// Identity reply family and, where a family has more than one model, model
+    pub family: u16,
+    pub model: Option<u16>,
+    /// Product byte following the device id in the program messages
+    pub product: u8,
+    pub pads: usize,
+    pub knobs: usize,
+    pub codec: Codec,
+}
+
+pub static LPD8: Profile = Profile {
+    id: "lpd8",
+    name: "LPD8",
+    family: 0x75,
+    model: Some(0x19),
+    product: 0x75,
+    pads: 8,
+    knobs: 8,
+    codec: Codec::Shared(&[Section::Id, Section::Channel, Section::Pads, Section::Knobs]),
+};
+
+pub static LPD8_MK2: Profile = Profile {
+    id: "mk2",
+    name: "LPD8 MK2",
+    family: 0x4C,
+    model: None,
+    product: 0x4C,
+    pads: 8,
+    knobs: 8,
+    codec: Codec::Mk2,
+};
+
+pub static LPK25: Profile = Profile {
+    id: "lpk25",
+    name: "LPK25",
+    family: 0x76,
+    model: None,
+    product: 0x76,
+    pads: 0,
+    knobs: 0,
+    codec: Codec::Shared(&[
+        Section::Id,
+        Section::Channel,
+        // Octave, transpose, arpeggiator on, mode, time division, clock, latch, octaves and tempo
+        Section::Fixed(&[0x04, 0x0C, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78]),
+    ]),
+};
+
+pub static MPK_MINI: Profile = Profile {
+    id: "mpkmini",
+    name: "MPK mini",
+    family: 0x7C,
+    model: None,
+    product: 0x7C,
+    pads: 8,
+    knobs: 8,
+    codec: Codec::Shared(&[
+        Section::Id,
+        // Pads, then keys
+        Section::Channel,
+        Section::Channel,
+        // Octave, arpeggiator on, mode, time division, clock, latch, octaves, tap count and tempo
+        Section::Fixed(&[0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x78]),
+        // Bank A and B
+        Section::Pads,
+        Section::Pads,
+        Section::Knobs,
+    ]),
+};
+
+pub static PROFILES: &[&Profile] = &[&LPD8, &LPD8_MK2, &LPK25, &MPK_MINI];
+
+impl Profile {
+    /// Profile for an identity reply's family and model
+    pub fn find(family: u16, model: u16) -> Option<&'static Profile> {
+        PROFILES.iter().cloned()
+            .find(|p| p.family == family && p.model.map_or(true, |m| m == model))
+    }
+
+    pub fn by_id(id: &str) -> Option<&'static Profile> {
+        PROFILES.iter().cloned().find(|p| p.id == id)
+    }
+
+    /// Profile speaking the shared protocol with `product`
+    pub fn by_product(product: u8) -> Option<&'static Profile> {
+        PROFILES.iter().cloned().find(|p| p.product == product && p.layout().is_some())
+    }
+
+    fn layout(&self) -> Option<&'static [Section]> {
+        match self.codec {
+            Codec::Shared(layout) => Some(layout),
+            Codec::Mk2 => None,
+        }
+    }
+
+    fn section_len(&self, section: &Section) -> usize {
+        match section {
+            Section::Id | Section::Channel => 1,
+            Section::Pads => self.pads * PAD_LEN,
+            Section::Knobs => self.knobs * KNOB_LEN,
+            Section::Fixed(bytes) => bytes.len(),
+        }
+    }
+
+    fn payload_len(&self, layout: &[Section]) -> usize {
+        layout.iter().map(|s| self.section_len(s)).sum()
+    }
+
+    fn message(&self, sysex_id: u8, command: LPD8Message, payload: &[u8]) -> Vec<u8> {
+        SYSEX_START.iter()
+            .chain(MAN_AKAI)
+            .chain(&[sysex_id, self.product, command.to_pattern(), (payload.len() >> 7) as u8, (payload.len() & 0x7F) as u8])
+            .chain(payload)
+            .chain(SYSEX_END)
+            .cloned().collect()
+    }
+
+    fn encode_program(&self, layout: &[Section], id: u8, program: &Program) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(self.payload_len(layout));
+        for section in layout {
+            match section {
+                Section::Id => payload.push(id),
+                Section::Channel => payload.push(program.channel),
+                Section::Pads => for pad in program.pads.iter().take(self.pads) {
+                    payload.extend(&[pad.note, pad.program_change, pad.control_change, if pad.toggle {1} else {0}]);
+                },
+                Section::Knobs => for knob in program.knobs.iter().take(self.knobs) {
+                    payload.extend(&[knob.control_change, knob.low, knob.high]);
+                },
+                Section::Fixed(bytes) => payload.extend(*bytes),
+            }
+        }
+        payload
+    }
+
+    /// Decodes an upload or download message.
+    pub fn parse_program(&self, message: &[u8]) -> Option<Program> {
+        let layout = match self.codec {
+            Codec::Shared(layout) => layout,
+            Codec::Mk2 => return mk2::parse_program(message),
+        };
+        if message.len() != HEADER_LEN + self.payload_len(layout) + 1 {
+            return None;
+        }
+        let mut program = Program::default();
+        let mut seen = Vec::new();
+        let mut payload = &message[HEADER_LEN..message.len() - 1];
+        for section in layout {
+            let (bytes, rest) = payload.split_at(self.section_len(section));
+            payload = rest;
+            if seen.contains(section) {
+                continue;
+            }
+            seen.push(*section);
+            match section {
+                Section::Channel => program.channel = bytes[0],
+                Section::Pads => for (pad, p) in program.pads.iter_mut().zip(bytes.chunks(PAD_LEN)) {
+                    pad.note = p[0];
+                    pad.program_change = p[1];
+                    pad.control_change = p[2];
+                    pad.toggle = p[3] == 1;
+                },
+                Section::Knobs => for (knob, k) in program.knobs.iter_mut().zip(bytes.chunks(KNOB_LEN)) {
+                    knob.control_change = k[0];
+                    knob.low = k[1];
+                    knob.high = k[2];
+                },
+                Section::Id | Section::Fixed(_) => {},
+            }
+        }
+        Some(program)
+    }
+
+    pub fn download_program(&self, monitor: &Monitor, device_id: &DeviceIDs, id: u8) -> Result<Program, String> {
+        let layout = match self.codec {
+            Codec::Shared(layout) => layout,
+            Codec::Mk2 => return mk2::download_program(monitor, device_id, id),
+        };
+        let request = self.message(device_id.2, LPD8Message::DownloadProgram, &[id]);
+        let response_filter = self.message(device_id.2, LPD8Message::DownloadProgram, &vec![0; self.payload_len(layout)]);
+        transact_sysex(monitor, &(device_id.0).1, &(device_id.1).1,
+            &request, &response_filter, RESPONSE_TEST_RANGES)
+            .and_then(|response| self.parse_program(&response)
+                .ok_or_else(|| "the device sent a program that couldn't be read".to_string()))
+    }
+
+    pub fn upload_program(&self, monitor: &Monitor, device_id: &DeviceIDs, id: u8, program: &Program) -> Result<(), String> {
+        match self.codec {
+            Codec::Shared(layout) => push_sysex(monitor, &(device_id.1).1,
+                &self.message(device_id.2, LPD8Message::UploadProgram, &self.encode_program(layout, id, program))),
+            Codec::Mk2 => mk2::upload_program(monitor, device_id, id, program),
+        }
+    }
+
+    /// Whether the device can report and switch its active program
+    pub fn has_active_program(&self) -> bool {
+        self.layout().is_some()
+    }
+
+    pub fn set_active_program(&self, monitor: &Monitor, device_id: &DeviceIDs, id: u8) -> Result<(), String> {
+        if !self.has_active_program() {
+            return Ok(());
+        }
+        push_sysex(monitor, &(device_id.1).1, &self.message(device_id.2, LPD8Message::SetActiveProgram, &[id]))
+    }
+
+    pub fn get_active_program(&self, monitor: &Monitor, device_id: &DeviceIDs) -> Option<u8> {
+        if !self.has_active_program() {
+            return None;
+        }
+        let request = self.message(device_id.2, LPD8Message::GetActiveProgram, &[]);
+        let response_filter = self.message(device_id.2, LPD8Message::GetActiveProgram, &[0]);
+        transact_sysex(monitor, &(device_id.0).1, &(device_id.1).1,
+            &request, &response_filter, RESPONSE_TEST_RANGES)
+            .map(|response| response[HEADER_LEN])
+            .ok()
+    }
+
+    /// Message the device answers a download request with.
+    pub fn download_reply(&self, sysex_id: u8, id: u8, program: &Program) -> Vec<u8> {
+        match self.codec {
+            Codec::Shared(layout) => self.message(sysex_id, LPD8Message::DownloadProgram, &self.encode_program(layout, id, program)),
+            Codec::Mk2 => mk2::download_reply(sysex_id, id, program),
+        }
+    }
+
+    /// Answer to a get active program request.
+    pub fn active_program_reply(&self, sysex_id: u8, id: u8) -> Vec<u8> {
+        self.message(sysex_id, LPD8Message::GetActiveProgram, &[id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A program using every setting the profile's pads and knobs have
+    fn program(profile: &Profile) -> Program {
+        let mut program = Program { channel: 5, ..Program::default() };
+        for (i, pad) in program.pads.iter_mut().take(profile.pads).enumerate() {
+            let i = i as u8;
+            pad.note = 36 + i;
+            pad.program_change = i;
+            pad.control_change = 20 + i;
+            pad.toggle = i % 2 == 1;
+        }
+        for (i, knob) in program.knobs.iter_mut().take(profile.knobs).enumerate() {
+            let i = i as u8;
+            knob.control_change = 70 + i;
+            knob.low = i;
+            knob.high = 127 - i;
+        }
+        program
+    }
+
+    fn assert_same(a: &Program, b: &Program) {
+        assert_eq!(a.channel, b.channel);
+        for (i, (pa, pb)) in a.pads.iter().zip(b.pads.iter()).enumerate() {
+            assert_eq!((pa.note, pa.program_change, pa.control_change, pa.toggle),
+                (pb.note, pb.program_change, pb.control_change, pb.toggle), "pad {}", i + 1);
+        }
+        for (i, (ka, kb)) in a.knobs.iter().zip(b.knobs.iter()).enumerate() {
+            assert_eq!((ka.control_change, ka.low, ka.high), (kb.control_change, kb.low, kb.high), "knob {}", i + 1);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for profile in PROFILES {
+            let program = program(profile);
+            let reply = profile.download_reply(0x00, 3, &program);
+            assert!(reply.iter().skip(1).take(reply.len() - 2).all(|b| *b < 0x80), "{} sends 8 bit data", profile.id);
+            let parsed = profile.parse_program(&reply).unwrap_or_else(|| panic!("{} doesn't read its own program", profile.id));
+            assert_same(&parsed, &program);
+        }
+    }
+
+    #[test]
+    fn shared_message_layout() {
+        let program = program(&LPD8);
+        let reply = LPD8.download_reply(0x02, 3, &program);
+        // Header, id, channel, 8 pads of 4 and 8 knobs of 3
+        assert_eq!(reply.len(), HEADER_LEN + 2 + 32 + 24 + 1);
+        assert_eq!(&reply[..HEADER_LEN + 2], &[0xF0, 0x47, 0x02, 0x75, LPD8Message::DownloadProgram.to_pattern(), 0x00, 58, 3, 5]);
+        assert_eq!(&reply[HEADER_LEN + 2..HEADER_LEN + 6], &[36, 0, 20, 0]);
+
+        // Repeated sections are all sent the same value
+        let reply = MPK_MINI.download_reply(0x00, 1, &program);
+        assert_eq!(reply[HEADER_LEN + 1], reply[HEADER_LEN + 2]);
+        assert!(LPK25.parse_program(&LPK25.download_reply(0x00, 1, &program)).is_some());
+    }
+
+    #[test]
+    fn wrong_length() {
+        let reply = LPD8.download_reply(0x00, 1, &Program::default());
+        assert!(LPD8.parse_program(&reply[..reply.len() - 2]).is_none());
+        // An MPK mini program isn't an LPD8's
+        assert!(LPD8.parse_program(&MPK_MINI.download_reply(0x00, 1, &Program::default())).is_none());
+    }
+
+    #[test]
+    fn lookup() {
+        assert_eq!(Profile::find(0x75, 0x19).map(|p| p.id), Some("lpd8"));
+        assert_eq!(Profile::find(0x75, 0x20), None);
+        assert_eq!(Profile::find(0x4C, 0x03).map(|p| p.id), Some("mk2"));
+        assert_eq!(Profile::by_product(0x7C).map(|p| p.id), Some("mpkmini"));
+        // The MK2 doesn't speak the shared protocol
+        assert_eq!(Profile::by_product(0x4C), None);
+    }
+}
@@ -0,0 +1,258 @@
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mk2::{Mk2Knob, Mk2Pad};
+use crate::{Knob, Pad, Program};
+
+pub static EXTENSION: &str = "lpd8.json";
+
+/// On disk layout of a saved program, also what goes on the clipboard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProgramFile {
+    pub program: Program,
+    #[serde(default, skip_serializing_if = "Names::is_empty")]
+    pub names: Names,
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+}
+
+impl ProgramFile {
+    pub fn new(program: &Program, names: &Names) -> ProgramFile {
+        ProgramFile {
+            program: *program,
+            names: names.clone(),
+            metadata: Metadata::default(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<ProgramFile> {
+        serde_json::from_reader(File::open(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Clipboard text
+    pub fn text(&self) -> String {
+        serde_json::to_string_pretty(self).expect("programs always serialize")
+    }
+
+    pub fn parse(text: &str) -> serde_json::Result<ProgramFile> {
+        serde_json::from_str(text)
+    }
+}
+
+/// Names given to a program and its controls. The device only stores numbers, so these are
+/// kept in files alone.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Names {
+    pub program: String,
+    pub description: String,
+    pub pads: [String; 8],
+    pub knobs: [String; 8],
+}
+
+impl Names {
+    pub fn is_empty(&self) -> bool {
+        *self == Names::default()
+    }
+
+    /// "PROG 1", followed by the name when there is one
+    pub fn program_title(&self, id: u8) -> String {
+        title(format!("PROG {}", id), &self.program)
+    }
+
+    pub fn pad_title(&self, i: usize) -> String {
+        title(format!("PAD {}", i + 1), &self.pads[i])
+    }
+
+    pub fn knob_title(&self, i: usize) -> String {
+        title(format!("K{}", i + 1), &self.knobs[i])
+    }
+}
+
+fn title(number: String, name: &str) -> String {
+    if name.is_empty() {
+        number
+    } else {
+        format!("{} {}", number, name)
+    }
+}
+
+/// What a library preset is and is for, all optional
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    pub name: String,
+    pub author: String,
+    pub tags: Vec<String>,
+    pub description: String,
+    /// Instrument or software the program is set up to play
+    pub instrument: String,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+}
+
+/// One pad or knob with its MK2 settings, as copied on its own
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Control {
+    Pad(Pad, Mk2Pad),
+    Knob(Knob, Mk2Knob),
+}
+
+impl Control {
+    pub fn pad(program: &Program, i: usize) -> Control {
+        Control::Pad(program.pads[i], program.mk2.pads[i])
+    }
+
+    pub fn knob(program: &Program, i: usize) -> Control {
+        Control::Knob(program.knobs[i], program.mk2.knobs[i])
+    }
+
+    /// Puts this in place of pad or, for a knob, knob `i`.
+    pub fn apply(&self, program: &mut Program, i: usize) {
+        match *self {
+            Control::Pad(pad, mk2) => {
+                program.pads[i] = pad;
+                program.mk2.pads[i] = mk2;
+            },
+            Control::Knob(knob, mk2) => {
+                program.knobs[i] = knob;
+                program.mk2.knobs[i] = mk2;
+            },
+        }
+    }
+
+    /// Clipboard text
+    pub fn text(&self) -> String {
+        serde_json::to_string_pretty(self).expect("controls always serialize")
+    }
+
+    pub fn parse(text: &str) -> serde_json::Result<Control> {
+        serde_json::from_str(text)
+    }
+
+    /// A copied control of the same kind as `like`, pads only take pads and knobs knobs
+    pub fn parse_like(text: &str, like: &Control) -> Option<Control> {
+        Control::parse(text).ok()
+            .filter(|control| mem::discriminant(control) == mem::discriminant(like))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use crate::mk2::Rgb;
+
+    #[test]
+    fn file_round_trip() {
+        let mut program = Program { channel: 9, ..Program::default() };
+        program.pads[0].note = 36;
+        program.knobs[7].low = 10;
+        program.mk2.pads[1].off = Rgb(1, 2, 3);
+        let mut names = Names { program: "Drums".to_string(), ..Names::default() };
+        names.knobs[7] = "Cutoff".to_string();
+        let file = ProgramFile {
+            metadata: Metadata { tags: vec!["techno".to_string()], ..Metadata::default() },
+            ..ProgramFile::new(&program, &names)
+        };
+
+        let read = ProgramFile::parse(&file.text()).unwrap();
+        assert_eq!((read.program.channel, read.program.pads[0].note, read.program.knobs[7].low), (9, 36, 10));
+        assert_eq!(read.program.mk2.pads[1].off, Rgb(1, 2, 3));
+        assert_eq!(read.names, names);
+        assert_eq!(read.metadata, file.metadata);
+
+        let path = env::temp_dir().join(format!("lpd8-program-file-test-{}.{}", process::id(), EXTENSION));
+        file.save(&path).unwrap();
+        let loaded = ProgramFile::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().names, names);
+    }
+
+    #[test]
+    fn empty_names_left_out() {
+        let text = ProgramFile::new(&Program::default(), &Names::default()).text();
+        assert!(!text.contains("names") && !text.contains("metadata"));
+    }
+
+    #[test]
+    fn older_files() {
+        // Saved before names, metadata and MK2 settings
+        let pad = r#"{"note": 36, "program_change": 1, "control_change": 2, "toggle": true}"#;
+        let knob = r#"{"control_change": 70, "low": 0, "high": 127}"#;
+        let text = format!(r#"{{"program": {{"channel": 3, "pads": [{}], "knobs": [{}]}}}}"#,
+            vec![pad; 8].join(", "), vec![knob; 8].join(", "));
+        let file = ProgramFile::parse(&text).unwrap();
+        assert_eq!((file.program.channel, file.program.pads[7].note, file.program.knobs[0].high), (3, 36, 127));
+        assert!(file.names.is_empty() && file.metadata.is_empty());
+        assert_eq!(file.program.mk2.pads[0].on, Rgb::default());
+    }
+
+    #[test]
+    fn bad_files() {
+        assert!(ProgramFile::parse("").is_err());
+        assert!(ProgramFile::parse("{}").is_err());
+        assert!(ProgramFile::parse(r#"{"program": {"channel": 3}}"#).is_err());
+        let text = ProgramFile::new(&Program::default(), &Names::default()).text();
+        assert!(ProgramFile::parse(&text[..text.len() / 2]).is_err());
+        // Seven pads
+        let mut json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        json["program"]["pads"].as_array_mut().unwrap().pop();
+        assert!(ProgramFile::parse(&json.to_string()).is_err());
+        let mut json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        json["program"]["channel"] = 300.into();
+        assert!(ProgramFile::parse(&json.to_string()).is_err());
+        assert!(ProgramFile::load(Path::new("/nonexistent/program.lpd8.json")).is_err());
+    }
+
+    #[test]
+    fn control_round_trip() {
+        let mut program = Program::default();
+        program.pads[2].note = 40;
+        program.pads[2].toggle = true;
+        program.mk2.pads[2].on = Rgb(255, 0, 128);
+        program.knobs[5].control_change = 74;
+        program.knobs[5].high = 100;
+
+        let mut other = Program::default();
+        let pad = Control::parse(&Control::pad(&program, 2).text()).unwrap();
+        pad.apply(&mut other, 7);
+        assert_eq!((other.pads[7].note, other.pads[7].toggle, other.mk2.pads[7].on), (40, true, Rgb(255, 0, 128)));
+        let knob = Control::parse(&Control::knob(&program, 5).text()).unwrap();
+        knob.apply(&mut other, 0);
+        assert_eq!((other.knobs[0].control_change, other.knobs[0].high), (74, 100));
+        // Only the target changed
+        assert_eq!(other.pads[2].note, 0);
+        assert_eq!(other.knobs[5].control_change, 0);
+    }
+
+    #[test]
+    fn control_kind() {
+        let program = Program::default();
+        let knob_text = Control::knob(&program, 0).text();
+        assert!(Control::parse_like(&knob_text, &Control::pad(&program, 0)).is_none());
+        assert!(Control::parse_like(&knob_text, &Control::knob(&program, 3)).is_some());
+        assert!(Control::parse_like("{}", &Control::pad(&program, 0)).is_none());
+        // A whole program isn't a control
+        let program_text = ProgramFile::new(&program, &Names::default()).text();
+        assert!(Control::parse_like(&program_text, &Control::pad(&program, 0)).is_none());
+    }
+}
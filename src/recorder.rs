@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use std::sync::{Arc, Mutex};
+
+pub const TICKS_PER_QUARTER: u16 = 480;
+// Microseconds per quarter note, 120 BPM
+const TEMPO: u32 = 500_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Type 0, every event on one track
+    SingleTrack,
+    /// Type 1, a tempo track followed by one track per MIDI channel and one for SysEx
+    MultiTrack,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "0" => Some(Format::SingleTrack),
+            "1" => Some(Format::MultiTrack),
+            _ => None,
+        }
+    }
+}
+
+/// Collects timestamped input while recording, shared with the MIDI input callback.
+#[derive(Clone)]
+pub struct Recorder {
+    events: Arc<Mutex<Option<Vec<(u64, Vec<u8>)>>>>,
+}
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder {
+            events: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn start(&self) {
+        *self.events.lock().unwrap() = Some(Vec::new());
+    }
+
+    pub fn stop(&self) -> Vec<(u64, Vec<u8>)> {
+        self.events.lock().unwrap().take().unwrap_or_default()
+    }
+
+    /// Takes the timestamp in microseconds as given by midir.
+    pub fn push(&self, timestamp: u64, data: &[u8]) {
+        if let Some(events) = self.events.lock().unwrap().as_mut() {
+            events.push((timestamp, data.to_vec()));
+        }
+    }
+}
+
+fn push_var_len(buf: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.extend(bytes.iter().rev());
+}
+
+fn to_ticks(microseconds: u64) -> u64 {
+    microseconds * u64::from(TICKS_PER_QUARTER) / u64::from(TEMPO)
+}
+
+fn track_chunk(events: &[&(u64, Vec<u8>)], origin: u64, tempo: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    if tempo {
+        body.extend(&[0x00, 0xFF, 0x51, 0x03]);
+        body.extend(&TEMPO.to_be_bytes()[1..]);
+    }
+    let mut last_tick = 0;
+    for (timestamp, data) in events.iter().map(|e| (e.0, &e.1)) {
+        let tick = to_ticks(timestamp.saturating_sub(origin));
+        push_var_len(&mut body, (tick - last_tick) as u32);
+        last_tick = tick;
+        if data[0] == 0xF0 {
+            // Length covers everything after the leading F0, including F7
+            body.push(0xF0);
+            push_var_len(&mut body, (data.len() - 1) as u32);
+            body.extend(&data[1..]);
+        } else {
+            body.extend(data.iter());
+        }
+    }
+    body.extend(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    let mut chunk = b"MTrk".to_vec();
+    chunk.extend(&(body.len() as u32).to_be_bytes());
+    chunk.extend(body);
+    chunk
+}
+
+/// Encodes recorded input as a Standard MIDI File.
+pub fn encode(format: Format, events: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    // System common and realtime messages can't be stored, 0xFF means meta in a file
+    let events = events.iter()
+        .filter(|(_t, data)| !data.is_empty() && data[0] <= 0xF0)
+        .collect::<Vec<_>>();
+    let origin = events.iter().map(|e| e.0).min().unwrap_or(0);
+
+    let tracks = match format {
+        Format::SingleTrack => vec![track_chunk(&events, origin, true)],
+        Format::MultiTrack => {
+            let mut tracks = vec![track_chunk(&[], origin, true)];
+            for channel in 0..16 {
+                let channel_events = events.iter()
+                    .filter(|e| e.1[0] < 0xF0 && e.1[0] & 0x0F == channel)
+                    .cloned().collect::<Vec<_>>();
+                if !channel_events.is_empty() {
+                    tracks.push(track_chunk(&channel_events, origin, false));
+                }
+            }
+            let sysex_events = events.iter()
+                .filter(|e| e.1[0] == 0xF0)
+                .cloned().collect::<Vec<_>>();
+            if !sysex_events.is_empty() {
+                tracks.push(track_chunk(&sysex_events, origin, false));
+            }
+            tracks
+        },
+    };
+
+    let mut file = b"MThd".to_vec();
+    file.extend(&6u32.to_be_bytes());
+    file.extend(&(if format == Format::SingleTrack { 0u16 } else { 1u16 }).to_be_bytes());
+    file.extend(&(tracks.len() as u16).to_be_bytes());
+    file.extend(&TICKS_PER_QUARTER.to_be_bytes());
+    for track in tracks {
+        file.extend(track);
+    }
+    file
+}
+
+pub fn write(path: &Path, format: Format, events: &[(u64, Vec<u8>)]) -> io::Result<()> {
+    File::create(path)?.write_all(&encode(format, events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture() -> Vec<(u64, Vec<u8>)> {
+        vec![
+            (1_000_000, vec![0x90, 0x24, 0x64]),
+            // Clock isn't stored
+            (1_200_000, vec![0xF8]),
+            // Half a second later, a quarter note at 120 BPM
+            (1_500_000, vec![0x80, 0x24, 0x00]),
+            (5_000_000, vec![0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]),
+        ]
+    }
+
+    #[test]
+    fn variable_length() {
+        let encoded = |value| {
+            let mut buf = Vec::new();
+            push_var_len(&mut buf, value);
+            buf
+        };
+        assert_eq!(encoded(0), vec![0x00]);
+        assert_eq!(encoded(0x7F), vec![0x7F]);
+        assert_eq!(encoded(0x80), vec![0x81, 0x00]);
+        assert_eq!(encoded(480), vec![0x83, 0x60]);
+        assert_eq!(encoded(0x0FFF_FFFF), vec![0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn single_track() {
+        let mut expected = vec![
+            b'M', b'T', b'h', b'd', 0x00, 0x00, 0x00, 0x06,
+            // Format 0, one track, 480 ticks per quarter note
+            0x00, 0x00, 0x00, 0x01, 0x01, 0xE0,
+            b'M', b'T', b'r', b'k', 0x00, 0x00, 0x00, 0x1D,
+            // Tempo, 500000 µs per quarter note
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20,
+            0x00, 0x90, 0x24, 0x64,
+            0x83, 0x60, 0x80, 0x24, 0x00,
+            // 3360 ticks, then the SysEx with its length
+            0x9A, 0x20, 0xF0, 0x05, 0x7E, 0x7F, 0x06, 0x01, 0xF7,
+        ];
+        // End of track
+        expected.extend(&[0x00, 0xFF, 0x2F, 0x00]);
+        assert_eq!(encode(Format::SingleTrack, &capture()), expected);
+    }
+
+    #[test]
+    fn multi_track() {
+        let file = encode(Format::MultiTrack, &capture());
+        // Format 1 with the tempo track, channel 1 and SysEx
+        assert_eq!(&file[8..12], &[0x00, 0x01, 0x00, 0x03]);
+        let mut chunks = Vec::new();
+        let mut rest = &file[14..];
+        while !rest.is_empty() {
+            assert_eq!(&rest[..4], b"MTrk");
+            let len = u32::from_be_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            chunks.push(&rest[8..8 + len]);
+            rest = &rest[8 + len..];
+        }
+        assert_eq!(chunks.len(), 3);
+        for chunk in chunks.iter() {
+            assert!(chunk.ends_with(&[0x00, 0xFF, 0x2F, 0x00]));
+        }
+        assert_eq!(chunks[0], &[0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, 0x00, 0xFF, 0x2F, 0x00][..]);
+        assert_eq!(&chunks[1][..4], &[0x00, 0x90, 0x24, 0x64]);
+        // Timed from the first event of the recording, not of the track: 3840 ticks
+        assert_eq!(&chunks[2][..3], &[0x9E, 0x00, 0xF0]);
+    }
+}
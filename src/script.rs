@@ -0,0 +1,326 @@
+//! DAW controller scripts generated from templates.
+//!
+//! A template is the file to write with placeholders filled in from a program: `{{channel}}`,
+//! `{{program}}`... and sections repeated for each control, `{{#pads}}...{{/pads}}`. Bitwig
+//! Studio and Reaper templates are built in. Others are added, or the built in ones replaced, by
+//! putting files named `ID.EXTENSION` in the `templates` directory next to the library, so
+//! supporting another DAW needs no change here.
+//!
+//! Lines like `{{! name: Bitwig Studio Script }}` set the template's name, how names are
+//! escaped (`escape: js`, `xml` or `none`) and the targets the dialog offers, one per
+//! `{{! pad-target: ID Label }}` or `{{! knob-target: ID Label }}` line. They're left out of
+//! the output.
+//!
+//! | Placeholder                 | Value                                                          |
+//! |-----------------------------|----------------------------------------------------------------|
+//! | `app`                       | this program's name                                            |
+//! | `program`, `name`           | "PROG 1" with the program's name, the name alone               |
+//! | `description`               | the program's description                                      |
+//! | `channel`, `channel0`       | MIDI channel, 1-16 and 0-15                                    |
+//! | `channel-hex`               | 0-15 as one hexadecimal digit                                  |
+//! | `note-status`, `cc-status`  | note on and CC status bytes on the channel, in decimal         |
+//! | `uuid`                      | an id made from the program's title, the same every time       |
+//!
+//! Sections: `pads`, `knobs`, and `assigned-pads`, `unassigned-pads`... for those with or
+//! without a target. Inside them `number` (1-8), `index` (0-7), `name` ("PAD 1 Kick"), `label`
+//! (the name alone), `target`, and `note`, `note-hex`, `cc`, `pc`, `toggle` for pads or `cc`,
+//! `cc-hex`, `low`, `high` for knobs.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::mapping::{Assignments, Choice};
+use crate::program_file::Names;
+use crate::Program;
+
+static BUILT_IN: &[(&str, &str)] = &[
+    ("bitwig.control.js", include_str!("templates/bitwig.control.js")),
+    ("reaper.ReaperKeyMap", include_str!("templates/reaper.ReaperKeyMap")),
+];
+
+type Vars = Vec<(&'static str, String)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Js,
+    Xml,
+}
+
+impl Escape {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Escape::None => text.to_string(),
+            Escape::Js => text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"),
+            Escape::Xml => crate::mapping::escape(text),
+        }
+    }
+}
+
+pub struct Template {
+    pub id: String,
+    pub name: String,
+    /// Extension of the files written
+    pub extension: String,
+    escape: Escape,
+    pub pad_choices: Vec<Choice>,
+    pub knob_choices: Vec<Choice>,
+    body: String,
+}
+
+impl Template {
+    /// Reads a template from the text of file `file_name`, `None` if the name has no extension.
+    fn parse(file_name: &str, text: &str) -> Option<Template> {
+        let dot = file_name.find('.')?;
+        let mut template = Template {
+            id: file_name[..dot].to_string(),
+            name: file_name[..dot].to_string(),
+            extension: file_name[dot + 1..].to_string(),
+            escape: Escape::None,
+            pad_choices: Vec::new(),
+            knob_choices: Vec::new(),
+            body: String::new(),
+        };
+        for line in text.lines() {
+            let directive = line.trim();
+            let directive = match (directive.strip_prefix("{{!"), directive.ends_with("}}")) {
+                (Some(directive), true) => directive[..directive.len() - 2].trim(),
+                _ => {
+                    template.body.push_str(line);
+                    template.body.push('\n');
+                    continue;
+                },
+            };
+            let (key, value) = match directive.find(':') {
+                Some(i) => (directive[..i].trim(), directive[i + 1..].trim()),
+                // A plain comment
+                None => continue,
+            };
+            let choice = || {
+                let (id, label) = value.split_at(value.find(char::is_whitespace).unwrap_or(value.len()));
+                Choice { id: id.to_string(), label: label.trim().to_string() }
+            };
+            match key {
+                "name" => template.name = value.to_string(),
+                "escape" => template.escape = match value {
+                    "js" => Escape::Js,
+                    "xml" => Escape::Xml,
+                    _ => Escape::None,
+                },
+                "pad-target" => template.pad_choices.push(choice()),
+                "knob-target" => template.knob_choices.push(choice()),
+                _ => warn!("Unknown directive \"{}\" in template {}", key, file_name),
+            }
+        }
+        Some(template)
+    }
+
+    /// The script for program `id`
+    pub fn render(&self, id: u8, program: &Program, names: &Names, assignments: &Assignments) -> Result<String, String> {
+        let text = |text: &str| self.escape.apply(text);
+        let channel = program.channel & 0x0F;
+        let title = names.program_title(id);
+        let vars: Vars = vec![
+            ("app", env!("CARGO_PKG_NAME").to_string()),
+            ("program", text(&title)),
+            ("name", text(&names.program)),
+            ("description", text(&names.description)),
+            ("channel", (channel + 1).to_string()),
+            ("channel0", channel.to_string()),
+            ("channel-hex", format!("{:X}", channel)),
+            ("note-status", (0x90 | channel).to_string()),
+            ("cc-status", (0xB0 | channel).to_string()),
+            ("uuid", uuid(&format!("{} {}", env!("CARGO_PKG_NAME"), title))),
+        ];
+        let pads = program.pads.iter().enumerate().map(|(i, pad)| (!assignments.pads[i].is_empty(), vec![
+            ("number", (i + 1).to_string()),
+            ("index", i.to_string()),
+            ("name", text(&names.pad_title(i))),
+            ("label", text(&names.pads[i])),
+            ("target", text(&assignments.pads[i])),
+            ("note", pad.note.to_string()),
+            ("note-hex", format!("{:02X}", pad.note)),
+            ("cc", pad.control_change.to_string()),
+            ("pc", pad.program_change.to_string()),
+            ("toggle", pad.toggle.to_string()),
+        ])).collect::<Vec<_>>();
+        let knobs = program.knobs.iter().enumerate().map(|(i, knob)| (!assignments.knobs[i].is_empty(), vec![
+            ("number", (i + 1).to_string()),
+            ("index", i.to_string()),
+            ("name", text(&names.knob_title(i))),
+            ("label", text(&names.knobs[i])),
+            ("target", text(&assignments.knobs[i])),
+            ("cc", knob.control_change.to_string()),
+            ("cc-hex", format!("{:02X}", knob.control_change)),
+            ("low", knob.low.to_string()),
+            ("high", knob.high.to_string()),
+        ])).collect::<Vec<_>>();
+        let section = |name: &str| {
+            let (controls, wanted) = match name {
+                "pads" => (&pads, None),
+                "assigned-pads" => (&pads, Some(true)),
+                "unassigned-pads" => (&pads, Some(false)),
+                "knobs" => (&knobs, None),
+                "assigned-knobs" => (&knobs, Some(true)),
+                "unassigned-knobs" => (&knobs, Some(false)),
+                _ => return None,
+            };
+            Some(controls.iter()
+                .filter(|(assigned, _)| wanted.map_or(true, |wanted| wanted == *assigned))
+                .map(|(_, vars)| vars)
+                .collect::<Vec<_>>())
+        };
+        expand(&self.body, &vars, Some(&section))
+    }
+}
+
+/// Fills in `text`'s placeholders from `vars`, repeating sections for each control `sections`
+/// gives, with the control's placeholders added.
+fn expand<'v>(text: &str, vars: &[(&'static str, String)], sections: Option<&dyn Fn(&str) -> Option<Vec<&'v Vars>>>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let end = start + rest[start..].find("}}").ok_or("a {{ isn't closed")?;
+        let tag = rest[start + 2..end].trim();
+        rest = &rest[end + 2..];
+        if let Some(name) = tag.strip_prefix('#') {
+            let controls = sections.and_then(|sections| sections(name))
+                .ok_or_else(|| format!("unknown section \"{}\"", name))?;
+            let close = format!("{{{{/{}}}}}", name);
+            let close_at = rest.find(&close).ok_or_else(|| format!("section \"{}\" isn't closed", name))?;
+            for control in controls {
+                let mut control_vars = vars.to_vec();
+                control_vars.extend(control.iter().cloned());
+                out.push_str(&expand(&rest[..close_at], &control_vars, None)?);
+            }
+            rest = &rest[close_at + close.len()..];
+        } else {
+            // Later ones, the control's, win
+            let value = vars.iter().rev().find(|(name, _)| *name == tag)
+                .ok_or_else(|| format!("unknown placeholder \"{}\"", tag))?;
+            out.push_str(&value.1);
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// UUID formatted hash of `text`, for scripts that need a stable id
+fn uuid(text: &str) -> String {
+    // FNV-1a, twice with different offsets for 128 bits
+    let hash = |offset: u64| text.bytes().fold(offset, |hash, b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3));
+    let (high, low) = (hash(0xcbf2_9ce4_8422_2325), hash(0x6c62_272e_07bb_0142));
+    format!("{:08x}-{:04x}-4{:03x}-a{:03x}-{:012x}",
+        high >> 32, (high >> 16) & 0xFFFF, high & 0xFFF, (low >> 48) & 0xFFF, low & 0xFFFF_FFFF_FFFF)
+}
+
+pub fn dir() -> PathBuf {
+    glib::get_user_data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(env!("CARGO_PKG_NAME"))
+        .join("templates")
+}
+
+/// Built in templates and those in the templates directory, which replace built in ones with
+/// the same id, by name
+pub fn list() -> Vec<Template> {
+    let mut templates = BUILT_IN.iter()
+        .filter_map(|(file_name, text)| Template::parse(file_name, text))
+        .collect::<Vec<_>>();
+    let entries = fs::read_dir(dir()).into_iter().flatten().filter_map(|entry| entry.ok());
+    for path in entries.map(|entry| entry.path()).filter(|path| path.is_file()) {
+        let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let template = match fs::read_to_string(&path) {
+            Ok(text) => Template::parse(&file_name, &text),
+            Err(e) => {
+                warn!("Skipping template {}: {}", path.display(), e);
+                continue;
+            },
+        };
+        if let Some(template) = template {
+            templates.retain(|t| t.id != template.id);
+            templates.push(template);
+        }
+    }
+    templates.sort_by_key(|t| t.name.to_lowercase());
+    templates
+}
+
+pub fn find(id: &str) -> Result<Template, String> {
+    let templates = list();
+    let ids = templates.iter().map(|t| t.id.clone()).collect::<Vec<_>>();
+    templates.into_iter().find(|t| t.id == id)
+        .ok_or_else(|| format!("no template \"{}\", expected one of {} or a file in {}", id, ids.join(", "), dir().display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn built_in(id: &str) -> Template {
+        BUILT_IN.iter()
+            .filter_map(|(file_name, text)| Template::parse(file_name, text))
+            .find(|t| t.id == id)
+            .unwrap_or_else(|| panic!("no built in {}", id))
+    }
+
+    #[test]
+    fn placeholders() {
+        let vars: Vars = vec![("a", "1".to_string()), ("b", "2".to_string())];
+        assert_eq!(expand("x{{a}}y{{ b }}", &vars, None).unwrap(), "x1y2");
+        assert!(expand("{{c}}", &vars, None).is_err());
+        assert!(expand("{{a", &vars, None).is_err());
+        assert!(expand("{{#pads}}{{/pads}}", &vars, None).is_err());
+    }
+
+    #[test]
+    fn directives() {
+        let text = "{{! name: Test }}\n{{! escape: xml }}\n{{! a plain comment }}\n{{! pad-target: go Go now }}\n<a>{{name}}</a>\n";
+        let template = Template::parse("test.xml", text).unwrap();
+        assert_eq!((template.id.as_str(), template.extension.as_str()), ("test", "xml"));
+        assert_eq!(template.name, "Test");
+        assert_eq!(template.escape, Escape::Xml);
+        assert_eq!(template.pad_choices.len(), 1);
+        assert_eq!((template.pad_choices[0].id.as_str(), template.pad_choices[0].label.as_str()), ("go", "Go now"));
+        assert!(template.knob_choices.is_empty());
+        assert_eq!(template.body, "<a>{{name}}</a>\n");
+
+        let names = Names { program: "R&B".to_string(), ..Names::default() };
+        let rendered = template.render(1, &Program::default(), &names, &Assignments::default()).unwrap();
+        assert_eq!(rendered, "<a>R&amp;B</a>\n");
+
+        assert!(Template::parse("README", text).is_none());
+    }
+
+    #[test]
+    fn sections() {
+        let mut program = Program { channel: 1, ..Program::default() };
+        program.pads[0].note = 36;
+        program.knobs[2].control_change = 22;
+        let assignments = Assignments::parse(&["PAD1=40044", "K3=990"]).unwrap();
+        let rendered = built_in("reaper").render(1, &program, &Names::default(), &assignments).unwrap();
+        let lines = rendered.lines().filter(|line| !line.is_empty()).collect::<Vec<_>>();
+        assert_eq!(lines, vec!["KEY 145 36 40044 0", "KEY 177 22 990 0"]);
+
+        let text = "{{#unassigned-knobs}}{{number}}{{/unassigned-knobs}}";
+        let template = Template::parse("t.txt", text).unwrap();
+        assert_eq!(template.render(1, &program, &Names::default(), &assignments).unwrap(), "1245678\n");
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(Escape::Js.apply("a \"b\"\\\n"), "a \\\"b\\\"\\\\\\n");
+        assert_eq!(Escape::Xml.apply("<a & b>"), "&lt;a &amp; b&gt;");
+
+        let mut names = Names::default();
+        names.pads[0] = "Kick \"A\"".to_string();
+        let template = built_in("bitwig");
+        let rendered = template.render(1, &Program::default(), &names, &Assignments::default()).unwrap();
+        assert!(rendered.contains(r#"name: "PAD 1 Kick \"A\"""#));
+        assert!(!rendered.contains("{{"));
+        // The same id every time
+        assert_eq!(rendered, template.render(1, &Program::default(), &names, &Assignments::default()).unwrap());
+        assert_eq!(uuid("x").len(), 36);
+    }
+}
@@ -0,0 +1,194 @@
+//! Settings kept between runs in `lpd8_config/settings.json` in the user's config directory.
+//!
+//! Besides preferences they keep the device used last, known by its ports and identity, and the
+//! program page shown, so the editor selects that device again as soon as it answers and makes
+//! the page its active program.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::Backend;
+use crate::device_info::DeviceInfo;
+use crate::osc;
+use crate::transport::DEFAULT_MESSAGE_DELAY_MS;
+use crate::websocket;
+
+/// Preferences kept between runs in the user's config directory
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Settings {
+    /// Nicknames by device key
+    #[serde(default)]
+    pub nicknames: HashMap<String, String>,
+    /// Pause after each SysEx message
+    #[serde(default = "default_sysex_delay_ms")]
+    pub sysex_delay_ms: u64,
+    /// MIDI system, the platform's own when not set
+    #[serde(default)]
+    pub backend: Option<Backend>,
+    /// Device selected when the editor was last used
+    #[serde(default)]
+    pub last_device: Option<LastDevice>,
+    /// Program page shown when the editor was last closed
+    #[serde(default)]
+    pub last_page: Option<String>,
+    #[serde(default)]
+    pub window_size: Option<(i32, i32)>,
+    #[serde(default)]
+    pub monitor_visible: bool,
+    /// Where the OSC bridge sends to
+    #[serde(default)]
+    pub osc: osc::Config,
+    /// Where the WebSocket server listens
+    #[serde(default)]
+    pub websocket: websocket::Config,
+}
+
+/// A device, known by its port and what it answered to the identity request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LastDevice {
+    pub key: String,
+    pub family: u16,
+    pub model: u16,
+}
+
+impl LastDevice {
+    pub fn new(key: &str, info: &DeviceInfo) -> LastDevice {
+        LastDevice {
+            key: key.to_string(),
+            family: info.family,
+            model: info.model,
+        }
+    }
+
+    /// Whether the device on `key` answering with `info` is this one
+    pub fn matches(&self, key: &str, info: &DeviceInfo) -> bool {
+        self.key == key && self.family == info.family && self.model == info.model
+    }
+}
+
+fn default_sysex_delay_ms() -> u64 {
+    DEFAULT_MESSAGE_DELAY_MS
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            nicknames: HashMap::new(),
+            sysex_delay_ms: DEFAULT_MESSAGE_DELAY_MS,
+            backend: None,
+            last_device: None,
+            last_page: None,
+            window_size: None,
+            monitor_visible: false,
+            osc: osc::Config::default(),
+            websocket: websocket::Config::default(),
+        }
+    }
+}
+
+impl Settings {
+    fn path() -> PathBuf {
+        glib::get_user_config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(env!("CARGO_PKG_NAME"))
+            .join("settings.json")
+    }
+
+    pub fn load() -> Settings {
+        File::open(Settings::path()).ok()
+            .and_then(|file| serde_json::from_reader(file).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Settings::path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    pub fn nickname(&self, key: &str) -> Option<&str> {
+        self.nicknames.get(key).map(|n| n.as_str())
+    }
+
+    /// Sets or, given an empty name, clears a device's nickname.
+    pub fn set_nickname(&mut self, key: &str, nickname: &str) {
+        if nickname.is_empty() {
+            self.nicknames.remove(key);
+        } else {
+            self.nicknames.insert(key.to_string(), nickname.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(family: u16, model: u16) -> DeviceInfo {
+        DeviceInfo {
+            device_id: 0,
+            manufacturer: vec![0x47],
+            family,
+            model,
+            version: [0; 4],
+            extra: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn last_device() {
+        let last = LastDevice::new("LPD8 MIDI 1", &info(0x75, 0x19));
+        assert!(last.matches("LPD8 MIDI 1", &info(0x75, 0x19)));
+        assert!(!last.matches("LPD8 MIDI 2", &info(0x75, 0x19)));
+        // Another model on the same port
+        assert!(!last.matches("LPD8 MIDI 1", &info(0x4C, 0x19)));
+        assert!(!last.matches("LPD8 MIDI 1", &info(0x75, 0x1A)));
+    }
+
+    #[test]
+    fn missing_fields() {
+        let settings: Settings = serde_json::from_str("{}").unwrap();
+        assert_eq!(settings.sysex_delay_ms, DEFAULT_MESSAGE_DELAY_MS);
+        assert_eq!(settings.backend, None);
+        assert_eq!(settings.last_device, None);
+        assert_eq!(settings.last_page, None);
+        assert!(!settings.monitor_visible);
+        assert_eq!(settings.osc, osc::Config::default());
+        assert_eq!(settings.websocket, websocket::Config::default());
+
+        // A file from before the OSC addresses were configurable
+        let settings: Settings = serde_json::from_str(r#"{
+            "nicknames": {"LPD8 MIDI 1": "Left"},
+            "backend": "Jack",
+            "last_page": "3",
+            "osc": {"target": "10.0.0.2:8000"}
+        }"#).unwrap();
+        assert_eq!(settings.nickname("LPD8 MIDI 1"), Some("Left"));
+        assert_eq!(settings.backend, Some(Backend::Jack));
+        assert_eq!(settings.last_page.as_ref().map(|p| p.as_str()), Some("3"));
+        assert_eq!(settings.osc.target, "10.0.0.2:8000");
+        assert_eq!(settings.osc.pad_address, osc::Config::default().pad_address);
+        assert_eq!(settings.sysex_delay_ms, DEFAULT_MESSAGE_DELAY_MS);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut settings = Settings::default();
+        settings.set_nickname("a", "Left");
+        settings.last_device = Some(LastDevice::new("a", &info(0x75, 0x19)));
+        settings.window_size = Some((800, 600));
+        let read: Settings = serde_json::from_str(&serde_json::to_string(&settings).unwrap()).unwrap();
+        assert_eq!(read.nickname("a"), Some("Left"));
+        assert_eq!(read.last_device, settings.last_device);
+        assert_eq!(read.window_size, Some((800, 600)));
+        settings.set_nickname("a", "");
+        assert_eq!(settings.nickname("a"), None);
+    }
+}
@@ -0,0 +1,113 @@
+{{! name: Bitwig Studio Script }}
+{{! escape: js }}
+{{! pad-target: play Play or stop }}
+{{! pad-target: stop Stop }}
+{{! pad-target: record Record }}
+{{! pad-target: loop Toggle loop }}
+{{! pad-target: metronome Toggle metronome }}
+{{! pad-target: tap-tempo Tap tempo }}
+{{! pad-target: mute Mute selected track }}
+{{! pad-target: solo Solo selected track }}
+{{! pad-target: arm Arm selected track }}
+{{! pad-target: previous-track Select previous track }}
+{{! pad-target: next-track Select next track }}
+{{! knob-target: volume Selected track volume }}
+{{! knob-target: pan Selected track pan }}
+{{! knob-target: remote-1 Remote control 1 }}
+{{! knob-target: remote-2 Remote control 2 }}
+{{! knob-target: remote-3 Remote control 3 }}
+{{! knob-target: remote-4 Remote control 4 }}
+{{! knob-target: remote-5 Remote control 5 }}
+{{! knob-target: remote-6 Remote control 6 }}
+{{! knob-target: remote-7 Remote control 7 }}
+{{! knob-target: remote-8 Remote control 8 }}
+// Bitwig Studio controller script for the LPD8's {{program}}, written by {{app}}.
+// Copy it to Bitwig Studio/Controller Scripts in your documents and add the controller under
+// Settings → Controllers. Pads without a target play notes, knobs without one control the
+// selected device's remote controls.
+loadAPI(10);
+
+host.defineController("Akai", "LPD8 {{program}}", "1.0", "{{uuid}}", "{{app}}");
+host.defineMidiPorts(1, 0);
+host.addDeviceNameBasedDiscoveryPair(["LPD8"], []);
+
+var CHANNEL = {{channel0}};
+var PADS = [
+{{#pads}}    { name: "{{name}}", note: {{note}}, target: "{{target}}" },
+{{/pads}}];
+var KNOBS = [
+{{#knobs}}    { name: "{{name}}", cc: {{cc}}, low: {{low}}, high: {{high}}, target: "{{target}}" },
+{{/knobs}}];
+
+var transport, cursorTrack, remoteControls, knobParameters;
+
+var PAD_ACTIONS = {
+    "play": function () { transport.togglePlay(); },
+    "stop": function () { transport.stop(); },
+    "record": function () { transport.record(); },
+    "loop": function () { transport.isArrangerLoopEnabled().toggle(); },
+    "metronome": function () { transport.isMetronomeEnabled().toggle(); },
+    "tap-tempo": function () { transport.tapTempo(); },
+    "mute": function () { cursorTrack.mute().toggle(); },
+    "solo": function () { cursorTrack.solo().toggle(); },
+    "arm": function () { cursorTrack.arm().toggle(); },
+    "previous-track": function () { cursorTrack.selectPrevious(); },
+    "next-track": function () { cursorTrack.selectNext(); }
+};
+
+function knobParameter(knob, i) {
+    if (knob.target == "volume") {
+        return cursorTrack.volume();
+    }
+    if (knob.target == "pan") {
+        return cursorTrack.pan();
+    }
+    var remote = knob.target.indexOf("remote-") == 0 ? parseInt(knob.target.substring(7), 10) - 1 : i;
+    return remoteControls.getParameter(remote);
+}
+
+function init() {
+    transport = host.createTransport();
+    cursorTrack = host.createCursorTrack(0, 0);
+    remoteControls = cursorTrack.createCursorDevice().createCursorRemoteControlsPage(8);
+    knobParameters = KNOBS.map(knobParameter);
+    knobParameters.forEach(function (parameter, i) {
+        parameter.setIndication(true);
+        parameter.setLabel(KNOBS[i].name);
+    });
+
+    var port = host.getMidiInPort(0);
+    port.setMidiCallback(onMidi);
+    // Only the notes of pads without a target reach instruments
+    var masks = [
+{{#unassigned-pads}}        "9{{channel-hex}}{{note-hex}}??", "8{{channel-hex}}{{note-hex}}??",
+{{/unassigned-pads}}    ];
+    if (masks.length > 0) {
+        port.createNoteInput.apply(port, ["LPD8"].concat(masks));
+    }
+}
+
+function onMidi(status, data1, data2) {
+    if ((status & 0x0F) != CHANNEL) {
+        return;
+    }
+    var type = status & 0xF0;
+    if (type == 0x90 && data2 > 0) {
+        PADS.forEach(function (pad) {
+            if (pad.note == data1 && PAD_ACTIONS[pad.target]) {
+                PAD_ACTIONS[pad.target]();
+            }
+        });
+    } else if (type == 0xB0) {
+        KNOBS.forEach(function (knob, i) {
+            if (knob.cc == data1) {
+                var range = Math.max(1, knob.high - knob.low);
+                var value = Math.min(Math.max(data2 - knob.low, 0), range);
+                knobParameters[i].set(value, range + 1);
+            }
+        });
+    }
+}
+
+function exit() {
+}
@@ -0,0 +1,178 @@
+//! Sending and receiving SysEx within what the MIDI backend allows.
+//!
+//! Some backends only pass SysEx up to a fixed size per event. Longer messages are sent as a
+//! run of fragments, the first starting with F0 and the last ending with F7, paced at the DIN
+//! MIDI rate so the device's buffer keeps up, and fragments coming in are joined back up by a
+//! `Reassembler` before anything looks at them. JACK and CoreMIDI pass at most 66 bytes.
+//!
+//! Every SysEx message is followed by a pause, 18 ms unless `--sysex-delay` or `sysex_delay_ms` in
+//! the settings asks for another, for units that drop messages sent too close together.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use std::time::Duration;
+
+use crate::backend::{Backend, OutputConnection};
+
+/// Largest message the JACK and CoreMIDI backends pass in one event
+const MAX_FRAGMENT_LEN: usize = 66;
+/// Time one byte takes on a 31250 baud DIN cable
+const BYTE_TIME: Duration = Duration::from_micros(320);
+
+pub const DEFAULT_MESSAGE_DELAY_MS: u64 = 18;
+
+static MESSAGE_DELAY_MS: AtomicU64 = AtomicU64::new(DEFAULT_MESSAGE_DELAY_MS);
+
+/// Largest message the current backend can send at once, `None` when there's no limit
+pub fn max_message_len() -> Option<usize> {
+    match Backend::current() {
+        Backend::Jack => Some(MAX_FRAGMENT_LEN),
+        Backend::Alsa if cfg!(target_os = "macos") => Some(MAX_FRAGMENT_LEN),
+        Backend::Alsa => None,
+    }
+}
+
+/// Sets how long to wait after each SysEx message before sending the next.
+pub fn set_message_delay(delay_ms: u64) {
+    MESSAGE_DELAY_MS.store(delay_ms, Ordering::Relaxed);
+}
+
+/// Waits the delay devices need between SysEx messages.
+pub fn message_delay() {
+    thread::sleep(Duration::from_millis(MESSAGE_DELAY_MS.load(Ordering::Relaxed)));
+}
+
+/// Pieces `message` is sent in, SysEx longer than `max_len` split with F0 opening the first
+/// and F7 closing the last
+fn fragments(message: &[u8], max_len: Option<usize>) -> Vec<&[u8]> {
+    match max_len {
+        Some(max_len) if message.len() > max_len && message.first() == Some(&0xF0) => message.chunks(max_len).collect(),
+        _ => vec![message],
+    }
+}
+
+/// Sends `message`, split into fragments the backend accepts when it's too long.
+pub fn send(connection: &mut OutputConnection, message: &[u8]) -> Result<(), String> {
+    let fragments = fragments(message, max_message_len());
+    for fragment in fragments.iter() {
+        connection.send(fragment)?;
+        if fragments.len() > 1 {
+            thread::sleep(BYTE_TIME * fragment.len() as u32);
+        }
+    }
+    Ok(())
+}
+
+/// Joins SysEx that arrives in fragments back into whole messages
+#[derive(Default)]
+pub struct Reassembler {
+    sysex: Vec<u8>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    /// Passes each complete message in `data` on to `f`.
+    pub fn feed<F: FnMut(&[u8])>(&mut self, data: &[u8], mut f: F) {
+        let first = match data.first() {
+            Some(&first) => first,
+            None => return,
+        };
+        // Any status byte but real time and F7 ends an unfinished message
+        let continues = first & 0x80 == 0 || first == 0xF7 || first >= 0xF8;
+        if !self.sysex.is_empty() && !continues {
+            self.sysex.clear();
+        }
+        if self.sysex.is_empty() && (first != 0xF0 || data.last() == Some(&0xF7)) {
+            f(data);
+            return;
+        }
+        // Real time messages may come in the middle of SysEx, even inside a fragment
+        for &byte in data {
+            if byte >= 0xF8 {
+                f(&[byte]);
+            } else {
+                self.sysex.push(byte);
+            }
+        }
+        if self.sysex.last() == Some(&0xF7) {
+            f(&self.sysex);
+            self.sysex.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A SysEx message `len` bytes long
+    fn sysex(len: usize) -> Vec<u8> {
+        let mut message = vec![0xF0];
+        message.extend((1..len - 1).map(|i| (i % 0x80) as u8));
+        message.push(0xF7);
+        message
+    }
+
+    fn feed_all(fragments: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut reassembler = Reassembler::new();
+        let mut messages = Vec::new();
+        for fragment in fragments {
+            reassembler.feed(fragment, |message| messages.push(message.to_vec()));
+        }
+        messages
+    }
+
+    #[test]
+    fn splits_at_66_bytes() {
+        let message = sysex(150);
+        let fragments = fragments(&message, Some(MAX_FRAGMENT_LEN));
+        assert_eq!(fragments.iter().map(|f| f.len()).collect::<Vec<_>>(), vec![66, 66, 18]);
+        assert_eq!(fragments[0][0], 0xF0);
+        assert_eq!(*fragments[2].last().unwrap(), 0xF7);
+        // Only the ends carry F0 and F7
+        assert!(fragments.iter().flat_map(|f| f.iter()).skip(1).take(148).all(|b| *b < 0x80));
+        assert_eq!(fragments.concat(), message);
+    }
+
+    #[test]
+    fn short_and_channel_messages_are_whole() {
+        let message = sysex(MAX_FRAGMENT_LEN);
+        assert_eq!(fragments(&message, Some(MAX_FRAGMENT_LEN)), vec![message.as_slice()]);
+        let long = sysex(200);
+        assert_eq!(fragments(&long, None), vec![long.as_slice()]);
+        assert_eq!(fragments(&[0x90, 36, 100], Some(2)), vec![&[0x90u8, 36, 100][..]]);
+    }
+
+    #[test]
+    fn joins_fragments() {
+        let message = sysex(150);
+        let fragments = fragments(&message, Some(MAX_FRAGMENT_LEN));
+        assert_eq!(feed_all(&fragments), vec![message.clone()]);
+
+        // Whole messages pass straight through
+        assert_eq!(feed_all(&[&[0xB0, 1, 64], &message]), vec![vec![0xB0, 1, 64], message]);
+    }
+
+    #[test]
+    fn real_time_inside_sysex() {
+        let message = sysex(100);
+        let (first, rest) = message.split_at(40);
+        let (middle, last) = rest.split_at(30);
+        let mut with_clock = middle.to_vec();
+        with_clock.insert(10, 0xF8);
+        let messages = feed_all(&[first, &[0xFE], &with_clock, &[0xF8], last]);
+        assert_eq!(messages, vec![vec![0xFE], vec![0xF8], vec![0xF8], message]);
+    }
+
+    #[test]
+    fn unfinished_sysex_is_dropped() {
+        let message = sysex(100);
+        let messages = feed_all(&[&message[..40], &[0x90, 36, 100], &message[40..]]);
+        // The rest has no F0 and is passed on as it came
+        assert_eq!(messages, vec![vec![0x90, 36, 100], message[40..].to_vec()]);
+    }
+}
@@ -0,0 +1,232 @@
+//! Devices coming and going.
+//!
+//! Every output is sent an identity request and paired with the input that answers. Identical
+//! controllers behind one MIDI merge share ports, so each unit answering is reported on its own
+//! with its SysEx device id, and its program messages are sent to that id instead of the
+//! broadcast id 0x7F.
+
+use std::thread;
+
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+
+use std::time::Duration;
+
+use crate::backend;
+use crate::device_info::{self, DeviceInfo};
+use crate::monitor::{Direction, Monitor};
+use crate::transport::{self, Reassembler};
+use crate::{DeviceIDs, PortID, APP_NAME, SYSEX_ALL};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(2000);
+const PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+/// How long to wait for more units after the first one answers
+const PROBE_SETTLE: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone)]
+pub enum PortEvent {
+    Added(DeviceIDs, DeviceInfo),
+    Removed(DeviceIDs),
+}
+
+fn port_names() -> (Vec<String>, Vec<String>) {
+    (backend::input_names(&APP_NAME), backend::output_names(&APP_NAME))
+}
+
+/// Sends an identity request to each of `out_names` and pairs it with the input that answers.
+///
+/// With `sysex_id` only the unit with that device id is asked and it's addressed with it after.
+/// Without it every unit answers, if more than one answers through the same ports, say behind a
+/// MIDI merge, each is addressed with the device id from its reply, otherwise with `SYSEX_ALL`.
+pub fn probe(monitor: &Monitor, sysex_id: Option<u8>, out_names: &[String]) -> Vec<(DeviceIDs, DeviceInfo)> {
+    let (tx, rx) = channel();
+    let in_names = backend::input_names(&APP_NAME);
+    let _connections = in_names.into_iter().enumerate().filter_map(|(i, port_name)| {
+        let name = format!("{}_response:{}", APP_NAME, i);
+        let (tx, monitor) = (tx.clone(), monitor.clone());
+        let mut reassembler = Reassembler::new();
+        backend::connect_input(&name, &port_name.clone(), move |_t, data: &[u8]| {
+            reassembler.feed(data, |message| {
+                monitor.log(Direction::In, &port_name, message);
+                let info = DeviceInfo::parse(message).ok()
+                    .filter(|info| info.profile().is_some())
+                    .filter(|info| sysex_id.map_or(true, |id| id == SYSEX_ALL || info.device_id == id));
+                if let Some(info) = info {
+                    let _ = tx.send((PortID(i, port_name.clone()), info));
+                }
+            });
+        }).ok()
+    }).collect::<Vec<_>>();
+
+    let mut devices = Vec::new();
+    let out_ids = backend::output_names(&APP_NAME).into_iter().enumerate()
+        .filter(|(_i, name)| out_names.contains(name))
+        .collect::<Vec<_>>();
+    let request = device_info::identity_request(sysex_id.unwrap_or(SYSEX_ALL));
+    for (i, out_name) in out_ids {
+        let name = format!("{}_call:{}", APP_NAME, i);
+        let connection = backend::connect_output(&name, &out_name).ok();
+        if let Some(mut connection) = connection {
+            // Drop late answers to the previous port
+            rx.try_iter().count();
+            monitor.log(Direction::Out, &out_name, &request);
+            if transport::send(&mut connection, &request).is_err() {
+                continue;
+            }
+            let mut replies: Vec<(PortID, DeviceInfo)> = Vec::new();
+            if let Ok(reply) = rx.recv_timeout(PROBE_TIMEOUT) {
+                replies.push(reply);
+                while let Ok(reply) = rx.recv_timeout(PROBE_SETTLE) {
+                    if !replies.iter().any(|(in_id, info)| in_id.1 == (reply.0).1 && info.device_id == reply.1.device_id) {
+                        replies.push(reply);
+                    }
+                }
+            }
+            let shared = replies.len() > 1;
+            for (in_id, info) in replies {
+                info!("{} is \"{:?}\", firmware {}, device id {}", info.model_name(), in_id, info.firmware(), info.device_id);
+                let address = match sysex_id {
+                    Some(id) => id,
+                    None if shared => info.device_id,
+                    None => SYSEX_ALL,
+                };
+                devices.push((DeviceIDs(in_id, PortID(i, out_name.clone()), address), info));
+            }
+        }
+    }
+    devices
+}
+
+/// Probes every output port.
+pub fn probe_all(monitor: &Monitor, sysex_id: Option<u8>) -> Vec<(DeviceIDs, DeviceInfo)> {
+    probe(monitor, sysex_id, &port_names().1)
+}
+
+/// What changed in the port lists since the last pass
+#[derive(Debug, PartialEq)]
+struct Changes {
+    /// Devices whose ports are all still there
+    present: Vec<DeviceIDs>,
+    gone: Vec<DeviceIDs>,
+    /// Outputs not seen before and without a device, to probe
+    new_outs: Vec<String>,
+}
+
+fn changes(devices: Vec<DeviceIDs>, known_outs: &[String], in_names: &[String], out_names: &[String]) -> Changes {
+    let (present, gone): (Vec<_>, Vec<_>) = devices.into_iter()
+        .partition(|d| in_names.contains(&(d.0).1) && out_names.contains(&(d.1).1));
+    let new_outs = out_names.iter()
+        .filter(|name| !known_outs.contains(name))
+        .filter(|name| !present.iter().any(|d| (d.1).1 == **name))
+        .cloned().collect();
+    Changes { present, gone, new_outs }
+}
+
+pub struct Watcher {
+    pub events: Receiver<PortEvent>,
+    rescan: Sender<()>,
+}
+
+impl Watcher {
+    /// Probes every port again on the next pass, not just the new ones.
+    pub fn rescan(&self) {
+        let _ = self.rescan.send(());
+    }
+}
+
+/// Polls the port list in the background, probing new ports and reporting devices that appear or go away.
+pub fn spawn(monitor: Monitor, sysex_id: Option<u8>) -> Watcher {
+    let (tx, rx) = channel();
+    let (rescan_tx, rescan_rx) = channel();
+    thread::spawn(move || {
+        let mut known_outs: Vec<String> = Vec::new();
+        let mut devices: Vec<DeviceIDs> = Vec::new();
+        loop {
+            let (in_names, out_names) = port_names();
+
+            let changes = changes(devices, &known_outs, &in_names, &out_names);
+            devices = changes.present;
+            for device in changes.gone {
+                if tx.send(PortEvent::Removed(device)).is_err() {
+                    return;
+                }
+            }
+
+            if !changes.new_outs.is_empty() {
+                for (device, info) in probe(&monitor, sysex_id, &changes.new_outs) {
+                    devices.push(device.clone());
+                    if tx.send(PortEvent::Added(device, info)).is_err() {
+                        return;
+                    }
+                }
+            }
+            known_outs = out_names;
+
+            match rescan_rx.recv_timeout(POLL_INTERVAL) {
+                Ok(()) => known_outs.clear(),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    });
+    Watcher {
+        events: rx,
+        rescan: rescan_tx,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn device(name: &str, sysex_id: u8) -> DeviceIDs {
+        DeviceIDs(PortID(0, format!("{} in", name)), PortID(0, format!("{} out", name)), sysex_id)
+    }
+
+    #[test]
+    fn hot_plug() {
+        let ins = names(&["Synth in", "LPD8 in"]);
+        let outs = names(&["Synth out", "LPD8 out"]);
+        let first = changes(Vec::new(), &[], &ins, &outs);
+        assert_eq!(first.new_outs, outs);
+        assert!(first.gone.is_empty());
+
+        // Probing found the LPD8, nothing answered on the synth's port
+        let devices = vec![device("LPD8", SYSEX_ALL)];
+        let again = changes(devices.clone(), &outs, &ins, &outs);
+        assert_eq!(again, Changes { present: devices.clone(), gone: Vec::new(), new_outs: Vec::new() });
+
+        // A second one plugged in
+        let more_ins = names(&["Synth in", "LPD8 in", "LPD8 #2 in"]);
+        let more_outs = names(&["Synth out", "LPD8 out", "LPD8 #2 out"]);
+        let plugged = changes(devices.clone(), &outs, &more_ins, &more_outs);
+        assert_eq!(plugged.new_outs, names(&["LPD8 #2 out"]));
+        assert_eq!(plugged.present, devices);
+
+        // A rescan forgets the known outputs, ports with a device stay unprobed
+        let rescan = changes(devices, &[], &ins, &outs);
+        assert_eq!(rescan.new_outs, names(&["Synth out"]));
+    }
+
+    #[test]
+    fn unplug() {
+        let units = vec![device("LPD8", 1), device("LPD8", 2), device("Other", SYSEX_ALL)];
+        let outs = names(&["LPD8 out", "Other out"]);
+        let unplugged = changes(units.clone(), &outs, &names(&["Other in"]), &names(&["Other out"]));
+        // Both units behind the merge go with their ports
+        assert_eq!(unplugged.gone, units[..2].to_vec());
+        assert_eq!(unplugged.present, units[2..].to_vec());
+        assert!(unplugged.new_outs.is_empty());
+
+        // Only the input gone is enough
+        let half = changes(units.clone(), &outs, &names(&["LPD8 in"]), &outs);
+        assert_eq!(half.gone, units[2..].to_vec());
+
+        // Plugged back in, it's probed again
+        let back = changes(Vec::new(), &names(&["Other out"]), &names(&["LPD8 in"]), &names(&["LPD8 out"]));
+        assert_eq!(back.new_outs, names(&["LPD8 out"]));
+    }
+}
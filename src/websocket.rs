@@ -0,0 +1,290 @@
+//! Local WebSocket server for browser based tools.
+//!
+//! Every connected page is sent what the pads and knobs do as JSON, found by the program shown
+//! in the editor:
+//!
+//! ```json
+//! {"event": "pad-pressed", "index": 2, "velocity": 100, "program": 1, "channel": 1,
+//!  "control": {"note": 38, "program_change": 0, "control_change": 0, "toggle": false}}
+//! ```
+//!
+//! `pad-released` has no velocity, `knob` has the `value` sent. `index` is 0-7, `channel` 1-16
+//! and `control` the pad or knob's settings in the program. Pages send commands back:
+//!
+//! ```json
+//! {"command": "set-active", "program": 2}
+//! {"command": "push", "program": 2, "data": {"program": {...}, "names": {...}}}
+//! ```
+//!
+//! `set-active` shows the program and switches the device to it, `push` sends it to the device,
+//! replaced by `data`, a saved program file, first when given. Commands that can't be read are
+//! answered with `{"event": "error", "message": "..."}`.
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tungstenite::{Message as WsMessage, WebSocket};
+
+use crate::monitor::{decode, ControlEvent};
+use crate::program_file::ProgramFile;
+use crate::Program;
+
+/// How long threads wait before looking for input, events and whether to stop
+const POLL: Duration = Duration::from_millis(50);
+
+/// Where to listen, kept in the settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Config {
+    /// `host:port`, only this computer can connect to the default
+    #[serde(default = "default_address")]
+    pub address: String,
+}
+
+fn default_address() -> String {
+    "127.0.0.1:9001".to_string()
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { address: default_address() }
+    }
+}
+
+/// What a page asked for, program numbers are 1-4
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Command {
+    SetActive { program: u8 },
+    Push {
+        program: u8,
+        #[serde(default)]
+        data: Option<ProgramFile>,
+    },
+}
+
+impl Command {
+    pub fn parse(text: &str) -> Result<Command, String> {
+        let command = serde_json::from_str::<Command>(text).map_err(|e| e.to_string())?;
+        let program = match command {
+            Command::SetActive { program } | Command::Push { program, .. } => program,
+        };
+        if program < 1 || program > 4 {
+            return Err(format!("program {} isn't 1-4", program));
+        }
+        Ok(command)
+    }
+}
+
+/// JSON sent for `event` from program `id`
+pub fn event_json(id: u8, program: &Program, event: ControlEvent) -> String {
+    let channel = (program.channel & 0x0F) + 1;
+    let json = match event {
+        ControlEvent::PadPressed { pad, velocity } => json!({
+            "event": "pad-pressed", "index": pad, "velocity": velocity,
+            "program": id, "channel": channel, "control": program.pads[pad],
+        }),
+        ControlEvent::PadReleased { pad } => json!({
+            "event": "pad-released", "index": pad,
+            "program": id, "channel": channel, "control": program.pads[pad],
+        }),
+        ControlEvent::Knob { knob, value } => json!({
+            "event": "knob", "index": knob, "value": value,
+            "program": id, "channel": channel, "control": program.knobs[knob],
+        }),
+    };
+    json.to_string()
+}
+
+fn error_json(message: &str) -> String {
+    json!({ "event": "error", "message": message }).to_string()
+}
+
+/// A listening server, stopped when dropped
+struct Running {
+    address: SocketAddr,
+    clients: Arc<Mutex<Vec<Sender<String>>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Broadcasts input while started, shared with the MIDI input callback.
+#[derive(Clone)]
+pub struct Server {
+    running: Arc<Mutex<Option<Running>>>,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            running: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Listens on the configured address, giving the commands pages send. The receiver
+    /// disconnects once the server is stopped and every page is gone.
+    pub fn start(&self, config: &Config) -> io::Result<Receiver<Command>> {
+        let listener = TcpListener::bind(&config.address)?;
+        listener.set_nonblocking(true)?;
+        let running = Running {
+            address: listener.local_addr()?,
+            clients: Arc::new(Mutex::new(Vec::new())),
+            stop: Arc::new(AtomicBool::new(false)),
+        };
+        let (commands_tx, commands) = channel();
+        let clients = running.clients.clone();
+        let stop = running.stop.clone();
+        thread::spawn(move || accept(listener, clients, commands_tx, stop));
+        info!("WebSocket server listening on {}", running.address);
+        *self.running.lock().unwrap() = Some(running);
+        Ok(commands)
+    }
+
+    pub fn stop(&self) {
+        *self.running.lock().unwrap() = None;
+    }
+
+    /// Where it listens, `None` when stopped
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.running.lock().unwrap().as_ref().map(|running| running.address)
+    }
+
+    /// Sends `data` to every page if it came from a control of program `id`.
+    pub fn forward(&self, id: u8, program: &Program, data: &[u8]) {
+        let running = self.running.lock().unwrap();
+        let running = match running.as_ref() {
+            Some(running) => running,
+            None => return,
+        };
+        if let Some(event) = ControlEvent::identify(program, &decode(data)) {
+            let text = event_json(id, program, event);
+            let mut clients = running.clients.lock().unwrap();
+            clients.retain(|tx| tx.send(text.clone()).is_ok());
+        }
+    }
+}
+
+fn accept(listener: TcpListener, clients: Arc<Mutex<Vec<Sender<String>>>>, commands: Sender<Command>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok((stream, address)) => {
+                debug!("WebSocket connection from {}", address);
+                stream
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(POLL);
+                continue;
+            },
+            Err(e) => {
+                warn!("Couldn't accept a WebSocket connection: {}", e);
+                thread::sleep(POLL);
+                continue;
+            },
+        };
+        let (tx, events) = channel();
+        clients.lock().unwrap().push(tx);
+        let commands = commands.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve(stream, &events, &commands, &stop) {
+                debug!("WebSocket connection closed: {}", e);
+            }
+        });
+    }
+}
+
+/// Talks to one page until it leaves or the server stops.
+fn serve(stream: TcpStream, events: &Receiver<String>, commands: &Sender<Command>, stop: &AtomicBool) -> Result<(), String> {
+    stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+    let mut socket: WebSocket<TcpStream> = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+    socket.get_ref().set_read_timeout(Some(POLL)).map_err(|e| e.to_string())?;
+    while !stop.load(Ordering::Relaxed) {
+        match socket.read_message() {
+            Ok(WsMessage::Text(text)) => match Command::parse(&text) {
+                Ok(command) => {
+                    debug!("WebSocket command {:?}", command);
+                    if commands.send(command).is_err() {
+                        break;
+                    }
+                },
+                Err(e) => socket.write_message(WsMessage::Text(error_json(&e))).map_err(|e| e.to_string())?,
+            },
+            Ok(WsMessage::Close(_)) => return Ok(()),
+            Ok(_) => {},
+            Err(tungstenite::Error::Io(ref e))
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {},
+            Err(e) => return Err(e.to_string()),
+        }
+        for text in events.try_iter() {
+            socket.write_message(WsMessage::Text(text)).map_err(|e| e.to_string())?;
+        }
+        match socket.write_pending() {
+            Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {},
+            result => result.map_err(|e| e.to_string())?,
+        }
+    }
+    socket.close(None).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_text<S: io::Read + io::Write>(client: &mut WebSocket<S>) -> serde_json::Value {
+        loop {
+            if let WsMessage::Text(text) = client.read_message().expect("a message") {
+                return serde_json::from_str(&text).expect("JSON");
+            }
+        }
+    }
+
+    #[test]
+    fn events_and_commands() {
+        let server = Server::new();
+        let commands = server.start(&Config { address: "127.0.0.1:0".to_string() }).unwrap();
+        let url = format!("ws://{}", server.address().unwrap());
+        let (mut client, _) = tungstenite::connect(url.as_str()).expect("connected");
+
+        client.write_message(WsMessage::Text(r#"{"command": "set-active", "program": 3}"#.to_string())).unwrap();
+        match commands.recv_timeout(Duration::from_secs(5)).expect("a command") {
+            Command::SetActive { program } => assert_eq!(program, 3),
+            command => panic!("unexpected {:?}", command),
+        }
+
+        client.write_message(WsMessage::Text(r#"{"command": "set-active", "program": 5}"#.to_string())).unwrap();
+        assert_eq!(read_text(&mut client)["event"], "error");
+
+        let mut program = Program { channel: 9, ..Program::default() };
+        program.knobs[1].control_change = 74;
+        server.forward(2, &program, &[0xB9, 74, 64]);
+        let event = read_text(&mut client);
+        assert_eq!(event["event"], "knob");
+        assert_eq!(event["index"], 1);
+        assert_eq!(event["value"], 64);
+        assert_eq!(event["program"], 2);
+        assert_eq!(event["channel"], 10);
+        assert_eq!(event["control"]["control_change"], 74);
+
+        server.stop();
+    }
+
+    #[test]
+    fn push_command() {
+        let command = Command::parse(r#"{"command": "push", "program": 1}"#).unwrap();
+        assert!(matches!(command, Command::Push { program: 1, data: None }));
+        let file = ProgramFile::new(&Program::default(), &Default::default());
+        let text = format!(r#"{{"command": "push", "program": 4, "data": {}}}"#, file.text());
+        assert!(matches!(Command::parse(&text).unwrap(), Command::Push { program: 4, data: Some(_) }));
+    }
+}